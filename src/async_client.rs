@@ -0,0 +1,811 @@
+// the tokio counterpart of client.rs; speaks the same wire format as the blocking client
+use crate::async_util;
+use crate::auth::Credentials;
+use crate::client::{
+    compatibility_check, compatible_protocol_version, decode_response, decode_stream_item,
+    negotiated_compression, negotiated_format, serialize, ConnectionOptions,
+};
+use crate::codec::{self, Codec, Format};
+use crate::compression::Compression;
+use crate::mgmt;
+use crate::transport;
+use crate::ProtocolVersion;
+use crate::Service;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{lookup_host, TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// routes the transmissions received on the rpc stream to the callers waiting for the transmission id
+#[derive(Debug, Default)]
+struct Dispatcher {
+    pending: Mutex<HashMap<u64, mpsc::UnboundedSender<Vec<u8>>>>,
+    closed: AtomicBool,
+}
+
+impl Dispatcher {
+    fn register(&self, transmission_id: u64) -> io::Result<mpsc::UnboundedReceiver<Vec<u8>>> {
+        let mut pending = self.pending.lock().expect("getting lock");
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "connection closed",
+            ));
+        }
+        let (sender, receiver) = mpsc::unbounded_channel();
+        pending.insert(transmission_id, sender);
+        Ok(receiver)
+    }
+
+    fn unregister(&self, transmission_id: u64) {
+        self.pending
+            .lock()
+            .expect("getting lock")
+            .remove(&transmission_id);
+    }
+
+    fn dispatch(&self, transmission_id: u64, payload: Vec<u8>) {
+        let pending = self.pending.lock().expect("getting lock");
+        match pending.get(&transmission_id) {
+            Some(sender) => {
+                // the receiver might already be gone if the caller dropped the pending response
+                let _ = sender.send(payload);
+            }
+            None => log::warn!("no caller waiting for transmission id {}", transmission_id),
+        }
+    }
+
+    // dropping all senders wakes up all waiting callers
+    fn close(&self) {
+        let mut pending = self.pending.lock().expect("getting lock");
+        self.closed.store(true, Ordering::Relaxed);
+        pending.clear();
+    }
+
+    async fn receive(
+        dispatcher: Arc<Dispatcher>,
+        mut stream: OwnedReadHalf,
+        format: Format,
+        compression: Option<Compression>,
+        max_frame_size: u64,
+    ) {
+        loop {
+            let payload = match async_util::wait_for_transmission(&mut stream).await {
+                Ok(length_prefix) => {
+                    async_util::read_transmission(
+                        &mut stream,
+                        length_prefix,
+                        max_frame_size,
+                        compression,
+                    )
+                    .await
+                }
+                Err(err) => Err(err),
+            };
+            let payload = match payload {
+                Ok(payload) => payload,
+                // the read half is dropped, the write half is closed with the connection
+                Err(_) => break,
+            };
+            match format.transmission_id(&payload) {
+                Some(transmission_id) => dispatcher.dispatch(transmission_id, payload),
+                None => log::error!("transmission without transmission id"),
+            }
+        }
+        dispatcher.close();
+        log::debug!("end async client receiver");
+    }
+}
+
+// the sending side of the rpc stream
+#[derive(Debug)]
+struct Writer {
+    stream: OwnedWriteHalf,
+    compression: Option<Compression>,
+    last_transmission: Instant,
+}
+
+impl Writer {
+    async fn write(&mut self, transmission: Vec<u8>) -> io::Result<usize> {
+        self.last_transmission = Instant::now();
+        async_util::write_transmission(&mut self.stream, transmission, self.compression).await
+    }
+
+    // sends a KeepAlive transmission whenever nothing else was sent for half of the rpc interval timeout
+    async fn keep_alive(
+        writer: Arc<tokio::sync::Mutex<Writer>>,
+        format: Format,
+        interval: Duration,
+    ) {
+        let interval = interval / 2;
+        loop {
+            tokio::time::sleep(interval).await;
+            let mut writer = writer.lock().await;
+            if writer.last_transmission.elapsed() < interval {
+                continue;
+            }
+            let keep_alive = serialize::<(), ()>(&format, 0, transport::Type::KeepAlive);
+            let sent = match keep_alive {
+                Ok(keep_alive) => writer.write(keep_alive).await.map_err(crate::Error::from),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = sent {
+                log::error!("sending keep alive: {:?}", err);
+                break;
+            }
+        }
+        log::debug!("end async client keep alive");
+    }
+}
+
+#[derive(Debug)]
+pub struct AsyncConnection<Req, Resp, Error>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+    Error: DeserializeOwned,
+{
+    id: u32,
+    port: u16,
+    format: Format,
+    compression: Option<Compression>,
+    writer: Arc<tokio::sync::Mutex<Writer>>,
+    transmission_id: AtomicU64,
+    dispatcher: Arc<Dispatcher>,
+    receiver: JoinHandle<()>,
+    keep_alive: Option<JoinHandle<()>>,
+    server_protocol_version: u32,
+    server_service: Service,
+    _req: PhantomData<Req>,
+    _resp: PhantomData<Resp>,
+    _error: PhantomData<Error>,
+}
+
+// unregisters the transmission id from the dispatcher when the caller is not interested anymore
+#[derive(Debug)]
+struct Subscription<'a> {
+    transmission_id: u64,
+    // the codec of the received transmissions
+    format: Format,
+    receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+    dispatcher: &'a Dispatcher,
+    // the writer of the connection for the Cancel transmission
+    writer: &'a tokio::sync::Mutex<Writer>,
+}
+
+impl<'a> Subscription<'a> {
+    fn new(
+        dispatcher: &'a Dispatcher,
+        writer: &'a tokio::sync::Mutex<Writer>,
+        transmission_id: u64,
+        format: Format,
+    ) -> io::Result<Self> {
+        Ok(Subscription {
+            transmission_id,
+            format,
+            receiver: dispatcher.register(transmission_id)?,
+            dispatcher,
+            writer,
+        })
+    }
+
+    fn canceller<Error>(&self) -> Canceller<'a, Error> {
+        Canceller {
+            transmission_id: self.transmission_id,
+            format: self.format,
+            writer: self.writer,
+            _error: PhantomData,
+        }
+    }
+
+    async fn recv(&mut self) -> io::Result<Vec<u8>> {
+        self.receiver.recv().await.ok_or_else(|| {
+            log::error!("connection closed while waiting for response");
+            io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "connection closed while waiting for response",
+            )
+        })
+    }
+}
+
+impl<'a> Drop for Subscription<'a> {
+    fn drop(&mut self) {
+        self.dispatcher.unregister(self.transmission_id);
+    }
+}
+
+// cancels an outstanding request, e.g. from another task than the one waiting for the response
+#[derive(Debug)]
+pub struct Canceller<'a, Error> {
+    transmission_id: u64,
+    format: Format,
+    writer: &'a tokio::sync::Mutex<Writer>,
+    _error: PhantomData<Error>,
+}
+
+impl<'a, Error> Canceller<'a, Error> {
+    // sends the Cancel transmission; the waiting caller receives Error::Cancelled, or the response if the server
+    // sent it before it received the Cancel
+    pub async fn cancel(&self) -> Result<(), crate::Error<Error>> {
+        let cancel =
+            serialize::<(), Error>(&self.format, self.transmission_id, transport::Type::Cancel)?;
+        self.writer.lock().await.write(cancel).await?;
+        Ok(())
+    }
+}
+
+// a request which was sent to the server and whose response is not yet received
+#[derive(Debug)]
+pub struct PendingResponse<'a, Resp, Error> {
+    subscription: Subscription<'a>,
+    _resp: PhantomData<Resp>,
+    _error: PhantomData<Error>,
+}
+
+impl<'a, Resp, Error> PendingResponse<'a, Resp, Error>
+where
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    pub fn transmission_id(&self) -> u64 {
+        self.subscription.transmission_id
+    }
+
+    pub async fn wait(mut self) -> Result<Resp, crate::Error<Error>> {
+        let response = self.subscription.recv().await?;
+        decode_response::<Resp, Error>(&self.subscription.format, &response)
+    }
+
+    // like wait, but gives up with Error::Timeout at the deadline; a late response is discarded
+    pub async fn wait_until(mut self, deadline: Instant) -> Result<Resp, crate::Error<Error>> {
+        let deadline = tokio::time::Instant::from_std(deadline);
+        let response = tokio::time::timeout_at(deadline, self.subscription.recv())
+            .await
+            .map_err(|_| {
+                log::error!("no response within the deadline");
+                crate::Error::Timeout
+            })??;
+        decode_response::<Resp, Error>(&self.subscription.format, &response)
+    }
+
+    // asks the server to cancel the request; wait still has to be called for the outcome
+    pub async fn cancel(&self) -> Result<(), crate::Error<Error>> {
+        self.canceller().cancel().await
+    }
+
+    pub fn canceller(&self) -> Canceller<'a, Error> {
+        self.subscription.canceller()
+    }
+}
+
+// the responses of a stream request; ends with the End transmission of the server or after the first error
+#[derive(Debug)]
+pub struct StreamingResponse<'a, Resp, Error> {
+    subscription: Subscription<'a>,
+    finished: bool,
+    _resp: PhantomData<Resp>,
+    _error: PhantomData<Error>,
+}
+
+impl<'a, Resp, Error> StreamingResponse<'a, Resp, Error>
+where
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    pub fn transmission_id(&self) -> u64 {
+        self.subscription.transmission_id
+    }
+
+    // asks the server to stop the stream; next returns Error::Cancelled after the items already sent
+    pub async fn cancel(&self) -> Result<(), crate::Error<Error>> {
+        self.canceller().cancel().await
+    }
+
+    pub fn canceller(&self) -> Canceller<'a, Error> {
+        self.subscription.canceller()
+    }
+
+    // returns None after the last response
+    pub async fn next(&mut self) -> Option<Result<Resp, crate::Error<Error>>> {
+        if self.finished {
+            return None;
+        }
+        let item = match self.subscription.recv().await {
+            Ok(item) => decode_stream_item::<Resp, Error>(&self.subscription.format, &item),
+            Err(err) => Some(Err(err.into())),
+        };
+        if !matches!(item, Some(Ok(_))) {
+            self.finished = true;
+        }
+        item
+    }
+}
+
+impl<Req, Resp, Error> AsyncConnection<Req, Resp, Error>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    // the address of the service management port, e.g. ("localhost", 5000) or "[::1]:5000"
+    pub async fn new(
+        address: impl ToSocketAddrs,
+        connection_id: i32,
+    ) -> Result<AsyncConnection<Req, Resp, Error>, crate::Error<Error>> {
+        Self::with_options(address, connection_id, ConnectionOptions::default()).await
+    }
+
+    pub async fn with_options(
+        address: impl ToSocketAddrs,
+        connection_id: i32,
+        options: ConnectionOptions,
+    ) -> Result<AsyncConnection<Req, Resp, Error>, crate::Error<Error>> {
+        #[cfg(feature = "tls")]
+        if options.tls.is_some() {
+            return Err(crate::Error::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "tls is not supported by the async client",
+            )));
+        }
+        let protocol_version = ProtocolVersion::entity().version();
+
+        let (addr, response) =
+            Self::mgmt_identify(lookup_host(address).await?, protocol_version, &options)
+                .await
+                .map_err(Self::mgmt_error)?;
+        let identity = if let mgmt::Response::Identify(identity) = response {
+            Ok(identity)
+        } else {
+            log::error!("wrong response to Identify");
+            Err(crate::Error::Protocol(
+                "wrong response to Identify".to_string(),
+            ))
+        }?;
+        compatible_protocol_version(&identity)?;
+        let format = negotiated_format(&identity)?;
+
+        // a negative connection id lets the server assign an unused one
+        let connection_id = u32::try_from(connection_id).unwrap_or(mgmt::DYNAMIC_CONNECTION_ID);
+        let comm_params = mgmt::Request::Connect(mgmt::CommParams {
+            protocol_version,
+            connection_id,
+            rpc_interval_timeout_ms: mgmt::rpc_interval_timeout_ms(options.rpc_interval_timeout),
+            format,
+            compression: options.compression.clone(),
+        });
+        // in single port mode the server keeps the management connection open for the rpcs
+        let mut mgmt_stream = Self::mgmt_open(&addr, &options)
+            .await
+            .map_err(Self::mgmt_error)?;
+        if let Some(credentials) = &options.credentials {
+            Self::mgmt_authenticate(&mut mgmt_stream, credentials.as_ref(), &options)
+                .await
+                .map_err(Self::mgmt_error)?;
+        }
+        let response = Self::mgmt_request(&mut mgmt_stream, comm_params, &options)
+            .await
+            .map_err(Self::mgmt_error)?;
+        let comm_settings = match response {
+            mgmt::Response::Connect(comm_settings) => Ok(comm_settings),
+            mgmt::Response::ConnectionIdInUse(connection_id) => {
+                log::error!("connection id {} already in use", connection_id);
+                Err(crate::Error::ConnectionIdInUse(connection_id))
+            }
+            mgmt::Response::ServerBusy(limit) => {
+                log::error!("server busy: {}", limit);
+                Err(crate::Error::ServerBusy(limit))
+            }
+            mgmt::Response::AuthenticationFailed => {
+                log::error!("the server requires authentication");
+                Err(crate::Error::AuthenticationFailed)
+            }
+            mgmt::Response::UnsupportedFormat(format) => {
+                log::error!("the server doesn't support the codec {}", format);
+                Err(crate::Error::Protocol(format!(
+                    "the server doesn't support the codec {}",
+                    format
+                )))
+            }
+            _ => {
+                log::error!("wrong response to Connect");
+                Err(crate::Error::Protocol(
+                    "wrong response to Connect".to_string(),
+                ))
+            }
+        }?;
+        let compression = negotiated_compression(&comm_settings, &options)?;
+
+        let (stream, port) = match comm_settings.endpoint {
+            mgmt::RpcEndpoint::Management => {
+                log::info!("using the management port for rpcs");
+                (mgmt_stream, addr.port())
+            }
+            mgmt::RpcEndpoint::Port(port) => {
+                log::info!("assigned port: {}", port);
+                let addr = SocketAddr::new(addr.ip(), port);
+                let mut stream = Self::connect(&addr, &options).await.map_err(|err| {
+                    log::error!("failed to open communication port: {:?}", err);
+                    err
+                })?;
+                // authorizes the connection, the server doesn't serve it otherwise
+                stream.write_all(&comm_settings.rpc_token.0).await?;
+                (stream, port)
+            }
+            endpoint => {
+                log::error!("rpc endpoint {:?} doesn't match {}", endpoint, addr);
+                return Err(crate::Error::Protocol(format!(
+                    "rpc endpoint {:?} doesn't match the management address {}",
+                    endpoint, addr
+                )));
+            }
+        };
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+
+        let dispatcher = Arc::new(Dispatcher::default());
+        let receiver = tokio::spawn(Dispatcher::receive(
+            dispatcher.clone(),
+            reader,
+            format,
+            compression,
+            options.max_frame_size,
+        ));
+
+        let writer = Arc::new(tokio::sync::Mutex::new(Writer {
+            stream: writer,
+            compression,
+            last_transmission: Instant::now(),
+        }));
+
+        let keep_alive = match options.rpc_interval_timeout {
+            Some(interval) if options.keep_alive => Some(tokio::spawn(Writer::keep_alive(
+                writer.clone(),
+                format,
+                interval,
+            ))),
+            _ => None,
+        };
+
+        log::info!("connected to service: '{}'", identity.service.id());
+        Ok(AsyncConnection::<Req, Resp, Error> {
+            id: comm_settings.connection_id,
+            port,
+            format,
+            compression,
+            writer,
+            transmission_id: AtomicU64::new(0),
+            dispatcher,
+            receiver,
+            keep_alive,
+            server_protocol_version: identity.protocol_version,
+            server_service: identity.service,
+            _req: PhantomData,
+            _resp: PhantomData,
+            _error: PhantomData,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    // the codec of the rpcs as negotiated with the server
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    // the compression of the rpcs as chosen by the server; None if the transmissions are uncompressed
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    async fn connect(addr: &SocketAddr, options: &ConnectionOptions) -> io::Result<TcpStream> {
+        tokio::time::timeout(options.connect_timeout, TcpStream::connect(addr))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timeout"))?
+    }
+
+    async fn mgmt_open(
+        addr: &SocketAddr,
+        options: &ConnectionOptions,
+    ) -> Result<TcpStream, crate::Error<transport::Error>> {
+        let stream = Self::connect(addr, options).await.map_err(|err| {
+            log::error!("failed to open tcp port: {:?}", err);
+            err
+        })?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    async fn mgmt_request(
+        stream: &mut TcpStream,
+        req: mgmt::Request,
+        options: &ConnectionOptions,
+    ) -> Result<mgmt::Response, crate::Error<transport::Error>> {
+        const MGMT_TRANSMISSION_ID: u64 = 0;
+        let transmission = serialize(
+            &codec::Bincode,
+            MGMT_TRANSMISSION_ID,
+            transport::Type::Request(req),
+        )?;
+        let response = tokio::time::timeout(options.handshake_timeout, async {
+            async_util::write_transmission(stream, transmission, None).await?;
+            let payload_size = async_util::wait_for_transmission(stream).await?;
+            async_util::read_transmission(stream, payload_size, crate::DEFAULT_MAX_FRAME_SIZE, None)
+                .await
+        })
+        .await
+        .map_err(|_| crate::Error::Timeout)??;
+        decode_response::<mgmt::Response, transport::Error>(&codec::Bincode, &response)
+    }
+
+    // answers the challenge of the server on the management connection, before the Connect request
+    async fn mgmt_authenticate(
+        stream: &mut TcpStream,
+        credentials: &dyn Credentials,
+        options: &ConnectionOptions,
+    ) -> Result<(), crate::Error<transport::Error>> {
+        let challenge =
+            match Self::mgmt_request(stream, mgmt::Request::Authenticate, options).await? {
+                mgmt::Response::Challenge(challenge) => challenge,
+                _ => {
+                    log::error!("wrong response to Authenticate");
+                    return Err(crate::Error::Protocol(
+                        "wrong response to Authenticate".to_string(),
+                    ));
+                }
+            };
+        let request = mgmt::Request::Credentials(credentials.respond(&challenge));
+        match Self::mgmt_request(stream, request, options).await? {
+            mgmt::Response::Authenticated => Ok(()),
+            mgmt::Response::AuthenticationFailed => {
+                log::error!("authentication failed");
+                Err(crate::Error::AuthenticationFailed)
+            }
+            _ => {
+                log::error!("wrong response to Credentials");
+                Err(crate::Error::Protocol(
+                    "wrong response to Credentials".to_string(),
+                ))
+            }
+        }
+    }
+
+    // the first resolved address which answers the Identify request is used for the connection
+    async fn mgmt_identify(
+        addrs: impl Iterator<Item = SocketAddr>,
+        protocol_version: u32,
+        options: &ConnectionOptions,
+    ) -> Result<(SocketAddr, mgmt::Response), crate::Error<transport::Error>> {
+        let mut last_err = None;
+        for addr in addrs {
+            let identify = mgmt::Request::Identify {
+                protocol_version,
+                formats: options.formats.clone(),
+            };
+            let response = match Self::mgmt_open(&addr, options).await {
+                Ok(mut stream) => Self::mgmt_request(&mut stream, identify, options).await,
+                Err(err) => Err(err),
+            };
+            match response {
+                Ok(response) => return Ok((addr, response)),
+                Err(err @ (crate::Error::Io(_) | crate::Error::Timeout)) => {
+                    log::warn!("service management port {} unreachable: {}", addr, err);
+                    last_err = Some(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            crate::Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no address to connect to",
+            ))
+        }))
+    }
+
+    // errors of the management protocol are not errors of the service, therefore a remote error is a protocol error
+    fn mgmt_error(err: crate::Error<transport::Error>) -> crate::Error<Error> {
+        err.map_remote(crate::Error::Protocol)
+    }
+
+    pub fn compatibility_check(&self, service: Service) -> bool {
+        compatibility_check(self.server_protocol_version, &self.server_service, &service)
+    }
+
+    // sends the request and waits for the response; other tasks may have requests in flight on the same connection
+    pub async fn transceive(&self, request: Req) -> Result<Resp, crate::Error<Error>> {
+        self.send(request).await?.wait().await
+    }
+
+    // like transceive, but gives up with Error::Timeout at the deadline; the server doesn't execute the
+    // request once the deadline passed
+    pub async fn transceive_with_deadline(
+        &self,
+        request: Req,
+        deadline: Instant,
+    ) -> Result<Resp, crate::Error<Error>> {
+        self.send_with_deadline(request, deadline)
+            .await?
+            .wait_until(deadline)
+            .await
+    }
+
+    // sends the request without waiting for the response; the response is matched by the transmission id
+    pub async fn send(
+        &self,
+        request: Req,
+    ) -> Result<PendingResponse<'_, Resp, Error>, crate::Error<Error>> {
+        self.send_request(transport::Type::Request(request)).await
+    }
+
+    // sends a Notification, which the server passes to MessageProcessing::on_notification without responding;
+    // Ok only means that the notification was sent
+    pub async fn notify(&self, request: Req) -> Result<(), crate::Error<Error>> {
+        let transmission_id = self.transmission_id.fetch_add(1, Ordering::Relaxed);
+        let transmission = serialize(
+            &self.format,
+            transmission_id,
+            transport::Type::Notification(request),
+        )?;
+        self.writer.lock().await.write(transmission).await?;
+        Ok(())
+    }
+
+    // like send, transmitting the time until the deadline to the server
+    pub async fn send_with_deadline(
+        &self,
+        request: Req,
+        deadline: Instant,
+    ) -> Result<PendingResponse<'_, Resp, Error>, crate::Error<Error>> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(crate::Error::Timeout);
+        }
+        let remaining_ms = u64::try_from(remaining.as_millis()).unwrap_or(u64::MAX);
+        self.send_request(transport::Type::RequestWithDeadline(request, remaining_ms))
+            .await
+    }
+
+    async fn send_request(
+        &self,
+        request: transport::Type<Req>,
+    ) -> Result<PendingResponse<'_, Resp, Error>, crate::Error<Error>> {
+        let transmission_id = self.transmission_id.fetch_add(1, Ordering::Relaxed);
+        let transmission = serialize(&self.format, transmission_id, request)?;
+
+        let pending = PendingResponse {
+            subscription: Subscription::new(
+                &self.dispatcher,
+                &self.writer,
+                transmission_id,
+                self.format,
+            )?,
+            _resp: PhantomData,
+            _error: PhantomData,
+        };
+
+        self.writer.lock().await.write(transmission).await?;
+
+        Ok(pending)
+    }
+
+    // sends a stream request; the server responds with an arbitrary number of responses
+    pub async fn transceive_stream(
+        &self,
+        request: Req,
+    ) -> Result<StreamingResponse<'_, Resp, Error>, crate::Error<Error>> {
+        let transmission_id = self.transmission_id.fetch_add(1, Ordering::Relaxed);
+        let transmission = serialize(
+            &self.format,
+            transmission_id,
+            transport::Type::StreamRequest(request),
+        )?;
+        // the client has no further requests for this stream
+        let end = serialize::<(), Error>(&self.format, transmission_id, transport::Type::End)?;
+
+        let streaming = StreamingResponse {
+            subscription: Subscription::new(
+                &self.dispatcher,
+                &self.writer,
+                transmission_id,
+                self.format,
+            )?,
+            finished: false,
+            _resp: PhantomData,
+            _error: PhantomData,
+        };
+
+        let mut writer = self.writer.lock().await;
+        writer.write(transmission).await?;
+        writer.write(end).await?;
+
+        Ok(streaming)
+    }
+
+    // sends all requests as client stream and waits for the response
+    pub async fn transceive_client_stream<I>(
+        &self,
+        requests: I,
+    ) -> Result<Resp, crate::Error<Error>>
+    where
+        I: IntoIterator<Item = Req>,
+    {
+        let transmission_id = self.transmission_id.fetch_add(1, Ordering::Relaxed);
+        let mut subscription =
+            Subscription::new(&self.dispatcher, &self.writer, transmission_id, self.format)?;
+
+        let transmissions = requests
+            .into_iter()
+            .map(|request| {
+                serialize(
+                    &self.format,
+                    transmission_id,
+                    transport::Type::Stream(request),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // an End for an unknown transmission id would terminate the connection
+        if transmissions.is_empty() {
+            return Err(crate::Error::Protocol(
+                "client stream without requests".to_string(),
+            ));
+        }
+        let end = serialize::<(), Error>(&self.format, transmission_id, transport::Type::End)?;
+
+        {
+            let mut writer = self.writer.lock().await;
+            for transmission in transmissions {
+                writer.write(transmission).await?;
+            }
+            writer.write(end).await?;
+        }
+
+        let response = subscription.recv().await?;
+        decode_response::<Resp, Error>(&self.format, &response)
+    }
+
+    // signals the server the end of the connection; dropping the connection closes it without the End transmission
+    pub async fn close(self) -> Result<(), crate::Error<Error>> {
+        let transmission_id = self.transmission_id.fetch_add(1, Ordering::Relaxed);
+        let end = serialize::<(), Error>(&self.format, transmission_id, transport::Type::End)?;
+        self.writer.lock().await.write(end).await?;
+        Ok(())
+    }
+}
+
+impl<Req, Resp, Error> Drop for AsyncConnection<Req, Resp, Error>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+    Error: DeserializeOwned,
+{
+    fn drop(&mut self) {
+        log::info!("shutdown async stream");
+        if let Some(keep_alive) = self.keep_alive.take() {
+            keep_alive.abort();
+        }
+        // the read half is dropped with the receiver task
+        self.receiver.abort();
+        self.dispatcher.close();
+    }
+}
@@ -0,0 +1,1114 @@
+// the tokio counterpart of server.rs; speaks the same wire format as the blocking server
+use crate::async_stream::{AsyncListener, AsyncStream};
+use crate::async_util;
+use crate::codec::{self, Codec, Format};
+use crate::compression::Compression;
+use crate::mgmt;
+#[cfg(feature = "tls")]
+use crate::server::TLS_HANDSHAKE_TIMEOUT;
+use crate::server::{
+    authenticate, compatible_protocol_version, compression, connection_limit, decode_request,
+    enables, legacy_identity, rpc_listener, Authentication, CancellationToken, Context,
+    FaultPolicy, ReceiveError, Rejection, ServerOptions, MGMT_REQUEST_TIMEOUT, UNIX_PEER_IP,
+};
+use crate::stream::Listener;
+use crate::transport;
+use crate::util;
+use crate::ProtocolVersion;
+use crate::Service;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, WriteHalf};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinSet;
+
+// the items are sent as transport::Type::Stream transmissions, terminated by transport::Type::End;
+// the stream ends when all senders are dropped
+pub type ResponseStream<Rsp, E> = mpsc::Receiver<Result<Rsp, E>>;
+
+// the requests of a client stream or bidirectional stream; ends when the client sends the End transmission
+#[derive(Debug)]
+pub struct RequestStream<Rq> {
+    receiver: mpsc::UnboundedReceiver<Rq>,
+}
+
+impl<Rq> RequestStream<Rq> {
+    pub async fn next(&mut self) -> Option<Rq> {
+        self.receiver.recv().await
+    }
+}
+
+// the async equivalent of server::MessageProcessing
+pub trait MessageProcessing: Send + Sync + 'static {
+    type Rq: DeserializeOwned + Send + 'static;
+    type Rsp: Serialize + Send + 'static;
+    type E: Serialize + std::fmt::Debug + Send + 'static;
+
+    fn new() -> Arc<Self>;
+
+    fn setup(
+        &self,
+        connection_info: String,
+        connection_id: u32,
+    ) -> impl Future<Output = ()> + Send {
+        async move {
+            // default implementation does nothing
+            log::trace!(
+                "default implementation for MessageProcessing::setup: {} : {}",
+                connection_info,
+                connection_id
+            );
+        }
+    }
+
+    fn execute(
+        &self,
+        connection_id: u32,
+        rpc: Self::Rq,
+    ) -> impl Future<Output = Result<Self::Rsp, Self::E>> + Send;
+
+    // like execute, but with the context of the request, e.g. the deadline;
+    // the default implementation calls execute
+    fn execute_with_context(
+        &self,
+        context: &Context,
+        rpc: Self::Rq,
+    ) -> impl Future<Output = Result<Self::Rsp, Self::E>> + Send {
+        self.execute(context.connection_id(), rpc)
+    }
+
+    // a transport::Type::Notification; the client doesn't wait for a response and none is sent.
+    // notifications are executed concurrently like requests; the context has no deadline
+    fn on_notification(&self, context: &Context, rpc: Self::Rq) -> impl Future<Output = ()> + Send {
+        async move {
+            // default implementation drops the notification
+            log::trace!(
+                "default implementation for MessageProcessing::on_notification: {}",
+                context.connection_id()
+            );
+            drop(rpc);
+        }
+    }
+
+    fn execute_stream(
+        &self,
+        connection_id: u32,
+        rpc: Self::Rq,
+    ) -> impl Future<Output = Result<ResponseStream<Self::Rsp, Self::E>, Self::E>> + Send {
+        async move {
+            // default implementation responds with a stream of only the response of execute
+            log::trace!("default implementation for MessageProcessing::execute_stream");
+            let (sender, receiver) = mpsc::channel(1);
+            let _ = sender.try_send(self.execute(connection_id, rpc).await);
+            Ok(receiver)
+        }
+    }
+
+    // the client streamed the requests, starting with rpc; the response is sent after the client sent the End transmission
+    fn execute_client_stream(
+        &self,
+        connection_id: u32,
+        rpc: Self::Rq,
+        mut rpcs: RequestStream<Self::Rq>,
+    ) -> impl Future<Output = Result<Self::Rsp, Self::E>> + Send {
+        async move {
+            // default implementation executes each request and responds with the last response
+            log::trace!("default implementation for MessageProcessing::execute_client_stream");
+            let mut response = self.execute(connection_id, rpc).await?;
+            while let Some(rpc) = rpcs.next().await {
+                response = self.execute(connection_id, rpc).await?;
+            }
+            Ok(response)
+        }
+    }
+
+    // like execute_client_stream, but with the context of the stream;
+    // the default implementation calls execute_client_stream
+    fn execute_client_stream_with_context(
+        &self,
+        context: &Context,
+        rpc: Self::Rq,
+        rpcs: RequestStream<Self::Rq>,
+    ) -> impl Future<Output = Result<Self::Rsp, Self::E>> + Send {
+        self.execute_client_stream(context.connection_id(), rpc, rpcs)
+    }
+
+    // rpc is the StreamRequest which opened the stream, rpcs are the requests the client streams afterwards
+    fn execute_bidi_stream(
+        &self,
+        connection_id: u32,
+        rpc: Self::Rq,
+        rpcs: RequestStream<Self::Rq>,
+    ) -> impl Future<Output = Result<ResponseStream<Self::Rsp, Self::E>, Self::E>> + Send {
+        // default implementation ignores the streamed requests; this also serves the plain server streams
+        log::trace!("default implementation for MessageProcessing::execute_bidi_stream");
+        drop(rpcs);
+        self.execute_stream(connection_id, rpc)
+    }
+
+    // like execute_bidi_stream, but with the context of the stream;
+    // the default implementation calls execute_bidi_stream
+    fn execute_bidi_stream_with_context(
+        &self,
+        context: &Context,
+        rpc: Self::Rq,
+        rpcs: RequestStream<Self::Rq>,
+    ) -> impl Future<Output = Result<ResponseStream<Self::Rsp, Self::E>, Self::E>> + Send {
+        self.execute_bidi_stream(context.connection_id(), rpc, rpcs)
+    }
+
+    fn cleanup(
+        &self,
+        connection_info: String,
+        connection_id: u32,
+    ) -> impl Future<Output = ()> + Send {
+        async move {
+            // default implementation does nothing
+            log::trace!(
+                "default implementation for MessageProcessing::cleanup: {} : {}",
+                connection_info,
+                connection_id
+            );
+        }
+    }
+
+    // the server stops if this returns true after a management request, e.g. mgmt::Request::CheckRunState
+    fn shutdown(&self) -> bool {
+        // default implementation does nothing
+        log::trace!("default implementation for MessageProcessing::shutdown");
+        false
+    }
+}
+
+// can be used from any thread or task to stop a running server
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        log::debug!("shutdown requested");
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.sender.borrow()
+    }
+}
+
+// the stream for the rpcs of a connection
+enum RpcChannel {
+    // the client connects to a separate port and authorizes the connection with the token
+    Listener(AsyncListener, mgmt::RpcToken),
+    // the client keeps using the management connection
+    Management(AsyncStream),
+}
+
+pub struct Server<T: MessageProcessing> {
+    pub(crate) message_processing: Arc<T>,
+    port: u16,
+    service: Service,
+    options: ServerOptions,
+    shutdown: Arc<watch::Sender<bool>>,
+    // the peer ip addresses of the open connections, by connection id
+    connection_ids: Arc<Mutex<HashMap<u32, IpAddr>>>,
+    transceivers: Mutex<JoinSet<()>>,
+}
+
+impl<T: MessageProcessing> Server<T> {
+    pub fn new(port: u16, service: Service) -> Self {
+        Self::with_options(port, service, ServerOptions::default())
+    }
+
+    pub fn with_options(port: u16, service: Service, options: ServerOptions) -> Self {
+        Server {
+            message_processing: T::new(),
+            port,
+            service,
+            options,
+            shutdown: Arc::new(watch::channel(false).0),
+            connection_ids: Arc::new(Mutex::new(HashMap::new())),
+            transceivers: Mutex::new(JoinSet::new()),
+        }
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            sender: self.shutdown.clone(),
+        }
+    }
+
+    pub async fn run(&self) -> io::Result<()> {
+        log::info!("run");
+
+        // the accept loops of all listeners feed the mgmt requests into one channel;
+        // they are aborted on shutdown, which also removes the socket file of a unix domain socket
+        let (sender, mut receiver) = mpsc::channel(1);
+        let mut listeners = Vec::new();
+        for ip in &self.options.addresses {
+            listeners.push(AsyncListener::from_std(Listener::Tcp(util::bind(
+                *ip, self.port,
+            )?))?);
+        }
+        #[cfg(unix)]
+        if let Some(path) = &self.options.unix_socket {
+            listeners.push(AsyncListener::from_std(Listener::bind_unix(path)?)?);
+        }
+        let mut accept_loops = JoinSet::new();
+        for listener in listeners {
+            let sender = sender.clone();
+            accept_loops.spawn(async move {
+                loop {
+                    let stream = listener.accept().await;
+                    if sender.send(stream).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        let mut shutdown = self.shutdown.subscribe();
+
+        while !*shutdown.borrow_and_update() {
+            let stream = tokio::select! {
+                _ = shutdown.changed() => break,
+                stream = receiver.recv() => match stream {
+                    Some(stream) => stream,
+                    None => break,
+                },
+            };
+            let handled = match stream {
+                Ok(stream) => self.handle_mgmt_request(stream).await,
+                Err(err) => Err(err),
+            };
+            let _ = handled.map_err(|err| log::error!("mgmt request: {:?}", err));
+            if self.message_processing.shutdown() {
+                break;
+            }
+        }
+
+        accept_loops.shutdown().await;
+
+        // signals all transceivers to finish and waits for them
+        self.shutdown.send_replace(true);
+        let mut transceivers =
+            std::mem::take(&mut *self.transceivers.lock().expect("getting lock"));
+        while transceivers.join_next().await.is_some() {}
+
+        log::info!("run -> stop");
+        Ok(())
+    }
+
+    async fn handle_mgmt_request(&self, stream: AsyncStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut stream = secure(stream, &self.options).await?;
+        let peer = stream.peer()?;
+
+        // the authentication continues on the same connection up to the Connect request
+        let mut authentication = Authentication::None;
+        loop {
+            let request = tokio::time::timeout(MGMT_REQUEST_TIMEOUT, async {
+                let payload_size = async_util::wait_for_transmission(&mut stream).await?;
+                let payload = async_util::read_transmission(
+                    &mut stream,
+                    payload_size,
+                    self.options.max_frame_size,
+                    None,
+                )
+                .await
+                .map_err(|err| ReceiveError::from_read(err, &codec::Bincode))?;
+                match decode_request::<mgmt::Request>(&codec::Bincode, &payload) {
+                    Err(rejection) => match legacy_identity(&payload, &self.service) {
+                        Some(answer) => Ok(Err(answer)),
+                        None => Err(rejection),
+                    },
+                    request => request.map(Ok),
+                }
+            })
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "mgmt request timeout"))?;
+
+            let (transmission_id, request) = match request {
+                Ok(Ok(request)) => request,
+                // the Identify of a client of protocol version 0
+                Ok(Err(answer)) => {
+                    return async_util::write_transmission(&mut stream, answer, None)
+                        .await
+                        .map(|_| ());
+                }
+                Err(ReceiveError::Io(err)) => return Err(err),
+                Err(ReceiveError::Rejected(rejection)) => {
+                    return reject(&mut stream, &codec::Bincode, None, &rejection).await;
+                }
+            };
+
+            let response: Result<mgmt::Response, transport::Error> = match request {
+                transport::Type::Request(mgmt::Request::Identify {
+                    protocol_version,
+                    formats,
+                }) => {
+                    log::debug!("server::Identify request");
+                    let server_protocol_version = ProtocolVersion::entity().version();
+                    if server_protocol_version != protocol_version {
+                        log::warn!("server::identify -> incompatible protocol versions; server: {}, client: {}", server_protocol_version, protocol_version);
+                    }
+                    Ok(mgmt::Response::Identify(mgmt::Identity {
+                        protocol_version: server_protocol_version,
+                        service: self.service.clone(),
+                        format: Format::negotiate(&self.options.formats, &formats),
+                    }))
+                }
+                transport::Type::Request(mgmt::Request::Connect(params)) => {
+                    log::debug!("server::Connect request");
+                    match compatible_protocol_version(&params) {
+                        Ok(()) => {
+                            match std::mem::replace(&mut authentication, Authentication::None) {
+                                Authentication::Authenticated(principal) => {
+                                    return self
+                                        .connection_request(
+                                            stream,
+                                            transmission_id,
+                                            params,
+                                            principal,
+                                        )
+                                        .await
+                                }
+                                _ if self.options.authenticator.is_some() => {
+                                    log::warn!(
+                                        "unauthenticated connection request of {}",
+                                        peer.address()
+                                    );
+                                    Ok(mgmt::Response::AuthenticationFailed)
+                                }
+                                _ => {
+                                    return self
+                                        .connection_request(stream, transmission_id, params, None)
+                                        .await
+                                }
+                            }
+                        }
+                        Err(err) => Err(err),
+                    }
+                }
+                transport::Type::Request(mgmt::Request::CheckRunState) => {
+                    log::debug!("server::CheckRunState request");
+                    Ok(mgmt::Response::CheckRunState)
+                }
+                transport::Type::Request(mgmt::Request::Authenticate) => {
+                    log::debug!("server::Authenticate request");
+                    let challenge = self
+                        .options
+                        .authenticator
+                        .as_ref()
+                        .map_or_else(Vec::new, |authenticator| authenticator.challenge(&peer));
+                    authentication = Authentication::Challenged(challenge.clone());
+                    Ok(mgmt::Response::Challenge(challenge))
+                }
+                transport::Type::Request(mgmt::Request::Credentials(credentials)) => {
+                    log::debug!("server::Credentials request");
+                    let (authenticated, response) = authenticate(
+                        &self.options,
+                        &peer,
+                        std::mem::replace(&mut authentication, Authentication::None),
+                        &credentials,
+                    );
+                    authentication = authenticated;
+                    Ok(response)
+                }
+                transport::Type::End => {
+                    log::trace!("end request");
+                    return Ok(());
+                }
+                _ => {
+                    let rejection = Rejection {
+                        transmission_id: Some(transmission_id),
+                        fault: transport::Fault::UnexpectedType,
+                        recoverable: true,
+                    };
+                    return reject(&mut stream, &codec::Bincode, None, &rejection).await;
+                }
+            };
+
+            send(
+                &mut stream,
+                &codec::Bincode,
+                None,
+                transmission_id,
+                response_type(response),
+            )
+            .await?;
+            if matches!(authentication, Authentication::None) {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn connection_request(
+        &self,
+        mut stream: AsyncStream,
+        transmission_id: u64,
+        params: mgmt::CommParams,
+        principal: Option<String>,
+    ) -> io::Result<()> {
+        if !enables(&self.options, params.format) {
+            log::warn!(
+                "connection request with unsupported codec {}",
+                params.format
+            );
+            return send(
+                &mut stream,
+                &codec::Bincode,
+                None,
+                transmission_id,
+                response_type::<_, transport::Error>(Ok(mgmt::Response::UnsupportedFormat(
+                    params.format,
+                ))),
+            )
+            .await;
+        }
+        // connections over a unix domain socket count as local connections
+        let peer_ip = stream
+            .peer_addr()
+            .map_or(UNIX_PEER_IP, |address| address.ip());
+        // the rpc listener is bound to the address on which the management connection arrived
+        let local_address = stream.local_address()?;
+        let connection_id = {
+            let mut connection_ids = self.connection_ids.lock().expect("getting lock");
+            let limit = connection_limit(&self.options, peer_ip, |peer_ip| {
+                connection_ids
+                    .values()
+                    .filter(|ip| peer_ip.is_none_or(|peer_ip| **ip == peer_ip))
+                    .count()
+            });
+            let connection_id = if params.connection_id == mgmt::DYNAMIC_CONNECTION_ID {
+                (0..mgmt::DYNAMIC_CONNECTION_ID).find(|id| !connection_ids.contains_key(id))
+            } else {
+                Some(params.connection_id)
+            };
+            match (limit, connection_id) {
+                (Some(limit), _) => {
+                    log::warn!("connection from {} refused: {}", peer_ip, limit);
+                    Err(mgmt::Response::ServerBusy(limit))
+                }
+                (None, Some(connection_id)) if !connection_ids.contains_key(&connection_id) => {
+                    connection_ids.insert(connection_id, peer_ip);
+                    Ok(connection_id)
+                }
+                (None, Some(connection_id)) => {
+                    log::warn!("connection id {} already in use", connection_id);
+                    Err(mgmt::Response::ConnectionIdInUse(connection_id))
+                }
+                (None, None) => Err(mgmt::Response::ConnectionIdInUse(params.connection_id)),
+            }
+        };
+        let connection_id = match connection_id {
+            Ok(connection_id) => connection_id,
+            Err(response) => {
+                return send(
+                    &mut stream,
+                    &codec::Bincode,
+                    None,
+                    transmission_id,
+                    response_type::<_, transport::Error>(Ok(response)),
+                )
+                .await;
+            }
+        };
+
+        let compression = compression(&self.options, &params.compression);
+        let settings = if self.options.single_port {
+            Ok((None, mgmt::RpcEndpoint::Management))
+        } else {
+            rpc_listener(&local_address, connection_id).and_then(|(listener, endpoint)| {
+                Ok((Some(AsyncListener::from_std(listener)?), endpoint))
+            })
+        };
+        let settings = settings
+            .and_then(|(listener, endpoint)| Ok((listener, endpoint, mgmt::RpcToken::generate()?)));
+        let (listener, response) = match settings {
+            Ok((listener, endpoint, rpc_token)) => (
+                Some(listener.map(|listener| (listener, rpc_token))),
+                Ok(mgmt::Response::Connect(mgmt::CommSettings {
+                    connection_id,
+                    endpoint,
+                    compression,
+                    rpc_token,
+                })),
+            ),
+            Err(err) => {
+                log::error!("connection request: {:?}", err);
+                (None, Err(format!("connection request failed: {}", err)))
+            }
+        };
+        let sent = send(
+            &mut stream,
+            &codec::Bincode,
+            None,
+            transmission_id,
+            response_type(response),
+        )
+        .await;
+
+        let channel = match (listener, sent) {
+            (Some(Some((listener, rpc_token))), Ok(())) => {
+                RpcChannel::Listener(listener, rpc_token)
+            }
+            (Some(None), Ok(())) => RpcChannel::Management(stream),
+            (_, sent) => {
+                self.connection_ids
+                    .lock()
+                    .expect("getting lock")
+                    .remove(&connection_id);
+                return sent;
+            }
+        };
+
+        // start the server transmission handler
+        let message_processing = self.message_processing.clone();
+        let shutdown = self.shutdown.subscribe();
+        let connection_ids = self.connection_ids.clone();
+        let options = self.options.clone();
+        let context = Context {
+            connection_id,
+            principal: principal.map(Arc::from),
+            deadline: None,
+            cancellation: CancellationToken::default(),
+        };
+        self.transceivers
+            .lock()
+            .expect("getting lock")
+            .spawn(async move {
+                let _ = Self::transceiver(
+                    message_processing,
+                    channel,
+                    shutdown,
+                    context,
+                    &params,
+                    compression,
+                    &options,
+                )
+                .await
+                .map_err(|err| log::error!("transceiver: {:?}", err));
+                connection_ids
+                    .lock()
+                    .expect("getting lock")
+                    .remove(&connection_id);
+            });
+
+        Ok(())
+    }
+
+    async fn transceiver(
+        message_processing: Arc<T>,
+        channel: RpcChannel,
+        mut shutdown: watch::Receiver<bool>,
+        // the context of the connection; each request gets its own deadline and cancellation
+        context: Context,
+        params: &mgmt::CommParams,
+        compression: Option<Compression>,
+        options: &ServerOptions,
+    ) -> io::Result<()> {
+        let rpc_interval_timeout = mgmt::rpc_interval_timeout(params.rpc_interval_timeout_ms);
+        let format = params.format;
+        let connection_id = context.connection_id;
+        let stream = match channel {
+            RpcChannel::Listener(listener, rpc_token) => tokio::select! {
+                _ = shutdown.changed() => {
+                    log::debug!("server shutdown before the client connected");
+                    return Ok(());
+                }
+                accepted = tokio::time::timeout(
+                    Duration::from_secs(2),
+                    accept_rpc(&listener, rpc_token, connection_id, options),
+                ) => {
+                    accepted
+                        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "accept timeout"))??
+                }
+            },
+            RpcChannel::Management(stream) => stream,
+        };
+        stream.set_nodelay(true)?;
+        let peer_address = stream.peer_description()?;
+        let (mut reader, writer) = tokio::io::split(stream);
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+
+        message_processing
+            .setup(peer_address.clone(), connection_id)
+            .await;
+
+        // requests are executed concurrently and the responses are sent in the order of completion
+        let mut executions = JoinSet::new();
+        // the requests and stream requests which are not yet answered, by transmission id
+        let cancellations = Arc::new(Mutex::new(HashMap::<u64, oneshot::Sender<()>>::new()));
+        let register = |transmission_id: u64| {
+            let (sender, receiver) = oneshot::channel();
+            cancellations
+                .lock()
+                .expect("getting lock")
+                .insert(transmission_id, sender);
+            receiver
+        };
+        // the execution is dropped when the deadline of the request passes or the client cancels it
+        let execute = |transmission_id: u64, rpc: T::Rq, deadline: Option<tokio::time::Instant>| {
+            let message_processing = message_processing.clone();
+            let writer = writer.clone();
+            let cancelled = register(transmission_id);
+            let cancellations = cancellations.clone();
+            let principal = context.principal.clone();
+            async move {
+                let context = Context {
+                    connection_id,
+                    principal,
+                    deadline: deadline.map(tokio::time::Instant::into_std),
+                    cancellation: CancellationToken::default(),
+                };
+                let execution = async {
+                    let execution = message_processing.execute_with_context(&context, rpc);
+                    match deadline {
+                        Some(deadline) if deadline <= tokio::time::Instant::now() => {
+                            Err(transport::Fault::DeadlineExceeded)
+                        }
+                        Some(deadline) => tokio::time::timeout_at(deadline, execution)
+                            .await
+                            .map_err(|_| transport::Fault::DeadlineExceeded),
+                        None => Ok(execution.await),
+                    }
+                };
+                let response = tokio::select! {
+                    response = execution => response,
+                    _ = cancelled => {
+                        // for work the execution handed over, e.g. to a spawned task
+                        context.cancellation.cancel();
+                        Err(transport::Fault::Cancelled)
+                    }
+                };
+                cancellations
+                    .lock()
+                    .expect("getting lock")
+                    .remove(&transmission_id);
+                let _ = respond(&writer, &format, compression, transmission_id, response)
+                    .await
+                    .map_err(|err| log::error!("transmission error: {:?}", err));
+            }
+        };
+        // streams for which the client has not yet sent the End transmission, by transmission id;
+        // the sender is taken by a Cancel, the further requests of the stream are discarded
+        let mut open_streams = HashMap::<u64, Option<mpsc::UnboundedSender<T::Rq>>>::new();
+        loop {
+            let request = tokio::select! {
+                _ = shutdown.changed() => {
+                    log::debug!("server shutdown");
+                    break;
+                }
+                request = receive_request::<T::Rq>(&mut reader, &format, compression, options.max_frame_size, rpc_interval_timeout) => request,
+            };
+            while executions.try_join_next().is_some() {}
+            match request {
+                Ok((transmission_id, transport::Type::Request(rpc))) => {
+                    executions.spawn(execute(transmission_id, rpc, None));
+                }
+                Ok((transmission_id, transport::Type::RequestWithDeadline(rpc, timeout_ms))) => {
+                    // a deadline beyond the range of Instant is no deadline
+                    let deadline =
+                        tokio::time::Instant::now().checked_add(Duration::from_millis(timeout_ms));
+                    executions.spawn(execute(transmission_id, rpc, deadline));
+                }
+                Ok((transmission_id, transport::Type::StreamRequest(rpc))) => {
+                    let (sender, receiver) = mpsc::unbounded_channel();
+                    open_streams.insert(transmission_id, Some(sender));
+                    let message_processing = message_processing.clone();
+                    let writer = writer.clone();
+                    let cancelled = register(transmission_id);
+                    let cancellations = cancellations.clone();
+                    let context = Context {
+                        deadline: None,
+                        cancellation: CancellationToken::default(),
+                        ..context.clone()
+                    };
+                    executions.spawn(async move {
+                        let rpcs = RequestStream { receiver };
+                        let response = message_processing
+                            .execute_bidi_stream_with_context(&context, rpc, rpcs)
+                            .await;
+                        let _ = send_stream(
+                            &writer,
+                            &format,
+                            compression,
+                            transmission_id,
+                            response,
+                            cancelled,
+                            &context.cancellation,
+                        )
+                        .await
+                        .map_err(|err| log::error!("transmission error: {:?}", err));
+                        cancellations
+                            .lock()
+                            .expect("getting lock")
+                            .remove(&transmission_id);
+                    });
+                }
+                Ok((transmission_id, transport::Type::Stream(rpc))) => {
+                    if let Some(sender) = open_streams.get(&transmission_id) {
+                        // the handler might not be interested in further requests
+                        if let Some(sender) = sender {
+                            let _ = sender.send(rpc);
+                        }
+                        continue;
+                    }
+                    // the first Stream transmission with a new transmission id opens a client stream
+                    let (sender, receiver) = mpsc::unbounded_channel();
+                    open_streams.insert(transmission_id, Some(sender));
+                    let message_processing = message_processing.clone();
+                    let writer = writer.clone();
+                    let cancelled = register(transmission_id);
+                    let cancellations = cancellations.clone();
+                    let context = Context {
+                        deadline: None,
+                        cancellation: CancellationToken::default(),
+                        ..context.clone()
+                    };
+                    executions.spawn(async move {
+                        let rpcs = RequestStream { receiver };
+                        // the cancel wins if the handler returns because its requests ended
+                        let response = tokio::select! {
+                            biased;
+                            _ = cancelled => {
+                                // for work the execution handed over, e.g. to a spawned task
+                                context.cancellation.cancel();
+                                Err(transport::Fault::Cancelled)
+                            }
+                            response = message_processing
+                                .execute_client_stream_with_context(&context, rpc, rpcs) => Ok(response),
+                        };
+                        cancellations
+                            .lock()
+                            .expect("getting lock")
+                            .remove(&transmission_id);
+                        let _ = respond(&writer, &format, compression, transmission_id, response)
+                            .await
+                            .map_err(|err| log::error!("transmission error: {:?}", err));
+                    });
+                }
+                Ok((transmission_id, transport::Type::End))
+                    if open_streams.remove(&transmission_id).is_some() =>
+                {
+                    log::trace!("end of stream {}", transmission_id);
+                }
+                Ok((_, transport::Type::End)) => {
+                    log::trace!("end request");
+                    break;
+                }
+                Ok((_, transport::Type::KeepAlive)) => {
+                    log::trace!("keep alive");
+                }
+                Ok((_, transport::Type::Notification(rpc))) => {
+                    let message_processing = message_processing.clone();
+                    let context = context.clone();
+                    executions.spawn(async move {
+                        message_processing.on_notification(&context, rpc).await
+                    });
+                }
+                Ok((transmission_id, transport::Type::Cancel)) => {
+                    // the response might have been sent already
+                    let cancellation = cancellations
+                        .lock()
+                        .expect("getting lock")
+                        .remove(&transmission_id);
+                    match cancellation {
+                        Some(cancellation) => {
+                            let _ = cancellation.send(());
+                        }
+                        None => log::trace!("cancel of finished request {}", transmission_id),
+                    }
+                    // the requests of the stream end for the handler after the cancel was signalled
+                    if let Some(sender) = open_streams.get_mut(&transmission_id) {
+                        sender.take();
+                    }
+                }
+                Ok((transmission_id, _)) => {
+                    let rejection = Rejection {
+                        transmission_id: Some(transmission_id),
+                        fault: transport::Fault::UnexpectedType,
+                        recoverable: true,
+                    };
+                    let _ =
+                        reject(&mut *writer.lock().await, &format, compression, &rejection).await;
+                    if options.fault_policy == FaultPolicy::CloseConnection {
+                        break;
+                    }
+                }
+                Err(ReceiveError::Rejected(rejection)) => {
+                    let _ =
+                        reject(&mut *writer.lock().await, &format, compression, &rejection).await;
+                    if !rejection.recoverable
+                        || options.fault_policy == FaultPolicy::CloseConnection
+                    {
+                        break;
+                    }
+                }
+                Err(ReceiveError::Io(err))
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    log::warn!(
+                        "connection {} exceeded the rpc interval timeout of {:?}",
+                        connection_id,
+                        rpc_interval_timeout
+                    );
+                    break;
+                }
+                Err(ReceiveError::Io(err)) => {
+                    log::error!("transmission error: {:?}", err);
+                    break;
+                }
+            }
+        }
+
+        // outstanding requests are finished before the connection is cleaned up;
+        // streams which were not ended by the client are ended now
+        open_streams.clear();
+        while executions.join_next().await.is_some() {}
+        let _ = writer.lock().await.shutdown().await;
+
+        message_processing
+            .cleanup(peer_address, connection_id)
+            .await;
+
+        log::debug!("end message processing async transceiver");
+        Ok(())
+    }
+}
+
+// performs the tls handshake on an accepted connection if the server is configured for tls
+#[cfg(feature = "tls")]
+async fn secure(stream: AsyncStream, options: &ServerOptions) -> io::Result<AsyncStream> {
+    match &options.tls {
+        Some(config) => {
+            tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, stream.accept_tls(config.clone()))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "tls handshake timeout"))?
+        }
+        None => Ok(stream),
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+async fn secure(stream: AsyncStream, _options: &ServerOptions) -> io::Result<AsyncStream> {
+    Ok(stream)
+}
+
+// serves the first connection which sends the rpc token; the handshakes of the connections run concurrently,
+// so that a client which doesn't send the token doesn't hold up the one which does
+async fn accept_rpc(
+    listener: &AsyncListener,
+    rpc_token: mgmt::RpcToken,
+    connection_id: u32,
+    options: &ServerOptions,
+) -> io::Result<AsyncStream> {
+    let mut candidates = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let stream = accepted?;
+                let options = options.clone();
+                candidates.spawn(async move {
+                    let mut stream = secure(stream, &options).await?;
+                    let mut received = mgmt::RpcToken([0u8; mgmt::RPC_TOKEN_SIZE]);
+                    stream.read_exact(&mut received.0).await?;
+                    if received != rpc_token {
+                        return Err(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            "invalid rpc token",
+                        ));
+                    }
+                    Ok(stream)
+                });
+            }
+            Some(candidate) = candidates.join_next() => match candidate {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(err)) => log::warn!("rpc connection {} refused: {:?}", connection_id, err),
+                Err(err) => log::error!("rpc connection {}: {:?}", connection_id, err),
+            },
+        }
+    }
+}
+
+async fn receive_request<Rq: DeserializeOwned>(
+    stream: &mut (impl AsyncRead + Unpin),
+    codec: &impl Codec,
+    compression: Option<Compression>,
+    max_frame_size: u64,
+    rpc_interval_timeout: Option<Duration>,
+) -> Result<(u64, transport::Type<Rq>), ReceiveError> {
+    let length_prefix = match rpc_interval_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, async_util::wait_for_transmission(stream))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "rpc interval timeout"))??,
+        None => async_util::wait_for_transmission(stream).await?,
+    };
+    let payload = async_util::read_transmission(stream, length_prefix, max_frame_size, compression)
+        .await
+        .map_err(|err| ReceiveError::from_read(err, codec))?;
+    decode_request(codec, &payload)
+}
+
+fn response_type<Rsp, E>(response: Result<Rsp, E>) -> transport::Type<Rsp, E> {
+    match response {
+        Ok(response) => transport::Type::Response(response),
+        Err(err) => transport::Type::Error(err),
+    }
+}
+
+async fn send<W, Rsp, E>(
+    stream: &mut W,
+    codec: &impl Codec,
+    compression: Option<Compression>,
+    transmission_id: u64,
+    r#type: transport::Type<Rsp, E>,
+) -> io::Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+    Rsp: Serialize,
+    E: Serialize,
+{
+    let transmission = transport::Transmission {
+        id: transmission_id,
+        r#type,
+    };
+    let serialized = codec
+        .encode(&transmission)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    async_util::write_transmission(stream, serialized, compression)
+        .await
+        .map(|_| ())
+}
+
+// sends the outcome of an execution, the fault e.g. if the client cancelled the request
+async fn respond<Rsp, E>(
+    writer: &tokio::sync::Mutex<WriteHalf<AsyncStream>>,
+    codec: &impl Codec,
+    compression: Option<Compression>,
+    transmission_id: u64,
+    response: Result<Result<Rsp, E>, transport::Fault>,
+) -> io::Result<()>
+where
+    Rsp: Serialize,
+    E: Serialize,
+{
+    let mut writer = writer.lock().await;
+    match response {
+        Ok(response) => {
+            let r#type = response_type(response);
+            send(&mut *writer, codec, compression, transmission_id, r#type).await
+        }
+        Err(fault) => {
+            let rejection = Rejection {
+                transmission_id: Some(transmission_id),
+                fault,
+                recoverable: true,
+            };
+            reject(&mut *writer, codec, compression, &rejection).await
+        }
+    }
+}
+
+// the stream is stopped with a Cancelled fault when the client cancels it
+async fn send_stream<Rsp, E>(
+    writer: &tokio::sync::Mutex<WriteHalf<AsyncStream>>,
+    codec: &impl Codec,
+    compression: Option<Compression>,
+    transmission_id: u64,
+    response: Result<ResponseStream<Rsp, E>, E>,
+    mut cancelled: oneshot::Receiver<()>,
+    cancellation: &CancellationToken,
+) -> io::Result<()>
+where
+    Rsp: Serialize,
+    E: Serialize,
+{
+    let mut items = match response {
+        Ok(items) => items,
+        Err(err) => {
+            let r#type = transport::Type::<Rsp, E>::Error(err);
+            return send(
+                &mut *writer.lock().await,
+                codec,
+                compression,
+                transmission_id,
+                r#type,
+            )
+            .await;
+        }
+    };
+    loop {
+        let item = tokio::select! {
+            item = items.recv() => item,
+            _ = &mut cancelled => {
+                // for work the handler handed over, e.g. to a spawned task
+                cancellation.cancel();
+                let rejection = Rejection {
+                    transmission_id: Some(transmission_id),
+                    fault: transport::Fault::Cancelled,
+                    recoverable: true,
+                };
+                return reject(&mut *writer.lock().await, codec, compression, &rejection).await;
+            }
+        };
+        let item = match item {
+            Some(item) => item,
+            None => break,
+        };
+        let mut writer = writer.lock().await;
+        match item {
+            Ok(item) => {
+                let r#type = transport::Type::<Rsp, E>::Stream(item);
+                send(&mut *writer, codec, compression, transmission_id, r#type).await?
+            }
+            Err(err) => {
+                let r#type = transport::Type::<Rsp, E>::Error(err);
+                return send(&mut *writer, codec, compression, transmission_id, r#type).await;
+            }
+        }
+    }
+    let r#type = transport::Type::<Rsp, E>::End;
+    send(
+        &mut *writer.lock().await,
+        codec,
+        compression,
+        transmission_id,
+        r#type,
+    )
+    .await
+}
+
+// answers a faulty transmission with a Fault transmission
+async fn reject<W>(
+    stream: &mut W,
+    codec: &impl Codec,
+    compression: Option<Compression>,
+    rejection: &Rejection,
+) -> io::Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    log::warn!("rejected transmission: {}", rejection.fault);
+    match rejection.transmission_id {
+        Some(transmission_id) => {
+            let r#type = transport::Type::<()>::Fault(rejection.fault.clone());
+            send(stream, codec, compression, transmission_id, r#type).await
+        }
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,208 @@
+// the tokio counterparts of the streams and listeners in stream.rs
+use crate::server::Peer;
+use crate::stream::{Address, Listener};
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+
+#[cfg(unix)]
+use crate::stream::SocketFile;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+#[derive(Debug)]
+pub(crate) enum AsyncStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<tokio_rustls::server::TlsStream<AsyncStream>>),
+}
+
+impl AsyncStream {
+    // the handshake is finished before the stream is returned
+    #[cfg(feature = "tls")]
+    pub(crate) async fn accept_tls(
+        self,
+        config: Arc<rustls::ServerConfig>,
+    ) -> io::Result<AsyncStream> {
+        tokio_rustls::TlsAcceptor::from(config)
+            .accept(self)
+            .await
+            .map(|stream| AsyncStream::Tls(Box::new(stream)))
+    }
+
+    // a unix domain socket has no Nagle algorithm
+    pub(crate) fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            AsyncStream::Tcp(stream) => stream.set_nodelay(nodelay),
+            #[cfg(unix)]
+            AsyncStream::Unix(_) => Ok(()),
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => stream.get_ref().0.set_nodelay(nodelay),
+        }
+    }
+
+    // the peer of a unix domain socket is usually unnamed, therefore only tcp peers have an address
+    pub(crate) fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            AsyncStream::Tcp(stream) => stream.peer_addr().ok(),
+            #[cfg(unix)]
+            AsyncStream::Unix(_) => None,
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => stream.get_ref().0.peer_addr(),
+        }
+    }
+
+    // the address on which the connection arrived; for a unix domain socket the path of the listener
+    pub(crate) fn local_address(&self) -> io::Result<Address> {
+        match self {
+            AsyncStream::Tcp(stream) => stream.local_addr().map(Address::Tcp),
+            #[cfg(unix)]
+            AsyncStream::Unix(stream) => unix_address(&stream.local_addr()?),
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => stream.get_ref().0.local_address(),
+        }
+    }
+
+    // the client for the Authenticator, before it is authenticated
+    pub(crate) fn peer(&self) -> io::Result<Peer> {
+        Ok(Peer {
+            address: self.peer_description()?,
+            principal: None,
+            #[cfg(feature = "tls")]
+            certificates: match self {
+                AsyncStream::Tls(stream) => stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .map(<[_]>::to_vec)
+                    .unwrap_or_default(),
+                _ => Vec::new(),
+            },
+        })
+    }
+
+    // describes the peer for MessageProcessing::setup and cleanup
+    pub(crate) fn peer_description(&self) -> io::Result<String> {
+        match self {
+            AsyncStream::Tcp(stream) => stream.peer_addr().map(|address| address.to_string()),
+            #[cfg(unix)]
+            AsyncStream::Unix(_) => self.local_address().map(|address| address.to_string()),
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => stream.get_ref().0.peer_description(),
+        }
+    }
+}
+
+impl AsyncRead for AsyncStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AsyncStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            AsyncStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for AsyncStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            AsyncStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            AsyncStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AsyncStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            AsyncStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AsyncStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            AsyncStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum AsyncListener {
+    Tcp(TcpListener),
+    // the socket file is removed when the listener is dropped
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        _socket_file: SocketFile,
+    },
+}
+
+impl AsyncListener {
+    // binds like the blocking listener, e.g. a stale socket file is replaced
+    pub(crate) fn from_std(listener: Listener) -> io::Result<AsyncListener> {
+        match listener {
+            Listener::Tcp(listener) => {
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener).map(AsyncListener::Tcp)
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, socket_file) => {
+                listener.set_nonblocking(true)?;
+                UnixListener::from_std(listener).map(|listener| AsyncListener::Unix {
+                    listener,
+                    _socket_file: socket_file,
+                })
+            }
+        }
+    }
+
+    pub(crate) async fn accept(&self) -> io::Result<AsyncStream> {
+        match self {
+            AsyncListener::Tcp(listener) => listener
+                .accept()
+                .await
+                .map(|(stream, _)| AsyncStream::Tcp(stream)),
+            #[cfg(unix)]
+            AsyncListener::Unix { listener, .. } => listener
+                .accept()
+                .await
+                .map(|(stream, _)| AsyncStream::Unix(stream)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn unix_address(address: &tokio::net::unix::SocketAddr) -> io::Result<Address> {
+    address
+        .as_pathname()
+        .map(|path| Address::Unix(path.to_path_buf()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::AddrNotAvailable, "unnamed unix socket"))
+}
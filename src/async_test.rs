@@ -0,0 +1,830 @@
+use super::*;
+use crate::codec::Codec;
+use crate::test::rpc_impostor_refused;
+#[cfg(feature = "tls")]
+use crate::test::{tls_options, TestCa};
+use crate::test_common::TEST_PORT_BASE;
+
+use serde::{Deserialize, Serialize};
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum DummyRequest {
+    Ping,
+    Fail,
+    Sleep(u64),
+    Count(u32),
+    Add(u32),
+    Notified,
+    Remaining,
+    Principal,
+    Blob(Vec<u8>),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum DummyResponse {
+    Pong,
+    Slept,
+    Number(u32),
+    Principal(Option<String>),
+    Blob(Vec<u8>),
+}
+
+#[derive(Default)]
+struct AsyncDummyServer {
+    notified: AtomicU32,
+}
+
+impl async_server::MessageProcessing for AsyncDummyServer {
+    type Rq = DummyRequest;
+    type Rsp = DummyResponse;
+    type E = String;
+
+    fn new() -> Arc<Self> {
+        Arc::new(AsyncDummyServer::default())
+    }
+
+    async fn execute(&self, _connection_id: u32, rpc: Self::Rq) -> Result<Self::Rsp, Self::E> {
+        match rpc {
+            DummyRequest::Ping => Ok(DummyResponse::Pong),
+            DummyRequest::Sleep(ms) => {
+                tokio::time::sleep(Duration::from_millis(ms)).await;
+                Ok(DummyResponse::Slept)
+            }
+            DummyRequest::Add(n) => Ok(DummyResponse::Number(n)),
+            DummyRequest::Notified => {
+                Ok(DummyResponse::Number(self.notified.load(Ordering::SeqCst)))
+            }
+            DummyRequest::Blob(blob) => Ok(DummyResponse::Blob(blob)),
+            unexpected => Err(format!("unexpected request: {:?}", unexpected)),
+        }
+    }
+
+    async fn execute_with_context(
+        &self,
+        context: &server::Context,
+        rpc: Self::Rq,
+    ) -> Result<Self::Rsp, Self::E> {
+        match rpc {
+            DummyRequest::Remaining => match context.remaining() {
+                Some(remaining) => Ok(DummyResponse::Number(remaining.as_millis() as u32)),
+                None => Err("no deadline".to_string()),
+            },
+            DummyRequest::Principal => Ok(DummyResponse::Principal(
+                context.principal().map(str::to_string),
+            )),
+            rpc => self.execute(context.connection_id(), rpc).await,
+        }
+    }
+
+    async fn on_notification(&self, _context: &server::Context, rpc: Self::Rq) {
+        if let DummyRequest::Add(n) = rpc {
+            self.notified.fetch_add(n, Ordering::SeqCst);
+        }
+    }
+
+    async fn execute_stream(
+        &self,
+        _connection_id: u32,
+        rpc: Self::Rq,
+    ) -> Result<async_server::ResponseStream<Self::Rsp, Self::E>, Self::E> {
+        match rpc {
+            DummyRequest::Count(count) => {
+                let (sender, receiver) = mpsc::channel(1);
+                tokio::spawn(async move {
+                    for n in 0..count {
+                        let _ = sender.send(Ok(DummyResponse::Number(n))).await;
+                    }
+                });
+                Ok(receiver)
+            }
+            unexpected => Err(format!("unexpected request: {:?}", unexpected)),
+        }
+    }
+
+    async fn execute_client_stream(
+        &self,
+        _connection_id: u32,
+        rpc: Self::Rq,
+        mut rpcs: async_server::RequestStream<Self::Rq>,
+    ) -> Result<Self::Rsp, Self::E> {
+        let mut sum = 0;
+        let mut rpc = Some(rpc);
+        while let Some(DummyRequest::Add(n)) = rpc {
+            sum += n;
+            rpc = rpcs.next().await;
+        }
+        Ok(DummyResponse::Number(sum))
+    }
+}
+
+struct DummyServer {}
+
+impl server::MessageProcessing for DummyServer {
+    type Rq = DummyRequest;
+    type Rsp = DummyResponse;
+    type E = String;
+
+    fn new() -> Arc<Self> {
+        Arc::new(DummyServer {})
+    }
+
+    fn execute(&self, _connection_id: u32, rpc: Self::Rq) -> Result<Self::Rsp, Self::E> {
+        match rpc {
+            DummyRequest::Ping => Ok(DummyResponse::Pong),
+            DummyRequest::Sleep(ms) => {
+                thread::sleep(Duration::from_millis(ms));
+                Ok(DummyResponse::Slept)
+            }
+            DummyRequest::Add(n) => Ok(DummyResponse::Number(n)),
+            DummyRequest::Blob(blob) => Ok(DummyResponse::Blob(blob)),
+            unexpected => Err(format!("unexpected request: {:?}", unexpected)),
+        }
+    }
+}
+
+type AsyncConnection = async_client::AsyncConnection<DummyRequest, DummyResponse, String>;
+type Connection = client::Connection<DummyRequest, DummyResponse, String>;
+
+async fn connect(ip: impl Into<IpAddr>, port: u16) -> AsyncConnection {
+    connect_with_options(ip, port, Default::default()).await
+}
+
+async fn connect_with_options(
+    ip: impl Into<IpAddr>,
+    port: u16,
+    options: client::ConnectionOptions,
+) -> AsyncConnection {
+    let ip = ip.into();
+    let mut retries = 100;
+    loop {
+        match AsyncConnection::with_options((ip, port), -1, options.clone()).await {
+            Ok(connection) => break connection,
+            Err(_) if retries > 0 => {
+                retries -= 1;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Err(err) => panic!("could not connect to server: {:?}", err),
+        }
+    }
+}
+
+// runs the async server in the background of the test
+async fn run_async_server<F, Fut>(options: server::ServerOptions, client: F) -> io::Result<()>
+where
+    F: FnOnce(Ipv4Addr, u16) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    run_server::<AsyncDummyServer, _, _>(options, client).await
+}
+
+async fn run_server<T, F, Fut>(options: server::ServerOptions, client: F) -> io::Result<()>
+where
+    T: async_server::MessageProcessing,
+    F: FnOnce(Ipv4Addr, u16) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = Arc::new(async_server::Server::<T>::with_options(
+        port, service, options,
+    ));
+    let shutdown_handle = server.shutdown_handle();
+    let runner = {
+        let server = server.clone();
+        tokio::spawn(async move { server.run().await })
+    };
+
+    client(ip, port).await;
+
+    shutdown_handle.shutdown();
+    runner.await.expect("joining server")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_simple_request() -> io::Result<()> {
+    run_async_server(Default::default(), |ip, port| async move {
+        let connection = connect(ip, port).await;
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping).await,
+            Ok(DummyResponse::Pong)
+        ));
+        assert!(matches!(
+            connection.transceive(DummyRequest::Fail).await,
+            Err(Error::Remote(_))
+        ));
+
+        let first = connection.send(DummyRequest::Add(1)).await.unwrap();
+        let second = connection.send(DummyRequest::Add(2)).await.unwrap();
+        assert!(matches!(second.wait().await, Ok(DummyResponse::Number(2))));
+        assert!(matches!(first.wait().await, Ok(DummyResponse::Number(1))));
+        assert!(connection.close().await.is_ok());
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_rpc_impostor() -> io::Result<()> {
+    run_async_server(Default::default(), |ip, port| async move {
+        tokio::task::spawn_blocking(move || rpc_impostor_refused(ip, port))
+            .await
+            .unwrap();
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_streams() -> io::Result<()> {
+    run_async_server(Default::default(), |ip, port| async move {
+        let connection = connect(ip, port).await;
+
+        let mut numbers = Vec::new();
+        let mut stream = connection
+            .transceive_stream(DummyRequest::Count(3))
+            .await
+            .unwrap();
+        while let Some(number) = stream.next().await {
+            numbers.push(number.unwrap());
+        }
+        assert_eq!(
+            numbers,
+            (0..3).map(DummyResponse::Number).collect::<Vec<_>>()
+        );
+
+        let sum = connection
+            .transceive_client_stream((1..=4).map(DummyRequest::Add))
+            .await;
+        assert!(matches!(sum, Ok(DummyResponse::Number(10))));
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blocking_client_with_async_server() -> io::Result<()> {
+    let options = server::ServerOptions {
+        single_port: true,
+        ..Default::default()
+    };
+    run_async_server(options, |ip, port| async move {
+        // waits until the server is running
+        drop(connect(ip, port).await);
+
+        let client = tokio::task::spawn_blocking(move || {
+            let connection = Connection::new((ip, port), -1).unwrap();
+            assert!(matches!(
+                connection.transceive(DummyRequest::Ping),
+                Ok(DummyResponse::Pong)
+            ));
+            let numbers = connection
+                .transceive_stream(DummyRequest::Count(2))
+                .map(|stream| stream.count());
+            assert!(matches!(numbers, Ok(2)));
+
+            let mut bidi = connection.open_bidi_stream(DummyRequest::Count(1)).unwrap();
+            assert!(matches!(bidi.next(), Some(Ok(DummyResponse::Number(0)))));
+            assert!(bidi.close().is_ok());
+        });
+        assert!(client.await.is_ok());
+    })
+    .await
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn async_unix_socket() -> io::Result<()> {
+    for single_port in [false, true] {
+        let path = std::env::temp_dir().join(format!(
+            "etm-async-test-{}-{}.sock",
+            std::process::id(),
+            TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed)
+        ));
+        let options = server::ServerOptions {
+            addresses: Vec::new(),
+            unix_socket: Some(path.clone()),
+            single_port,
+            ..Default::default()
+        };
+        let client_path = path.clone();
+        run_async_server(options, |_, _| async move {
+            let client = tokio::task::spawn_blocking(move || {
+                let mut retries = 100;
+                let connection = loop {
+                    match Connection::unix(&client_path, -1) {
+                        Ok(connection) => break connection,
+                        Err(_) if retries > 0 => {
+                            retries -= 1;
+                            thread::sleep(Duration::from_millis(10));
+                        }
+                        Err(err) => panic!("could not connect to server: {:?}", err),
+                    }
+                };
+                assert!(matches!(
+                    connection.transceive(DummyRequest::Ping),
+                    Ok(DummyResponse::Pong)
+                ));
+                let numbers = connection
+                    .transceive_stream(DummyRequest::Count(3))
+                    .map(|stream| stream.count());
+                assert!(matches!(numbers, Ok(3)));
+            });
+            assert!(client.await.is_ok());
+        })
+        .await?;
+        // the socket file is removed with the listener
+        assert!(!path.exists());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test(flavor = "multi_thread")]
+async fn async_tls() -> io::Result<()> {
+    let ca = TestCa::new();
+    for single_port in [false, true] {
+        let options = server::ServerOptions {
+            tls: Some(ca.server_config(false)),
+            single_port,
+            ..Default::default()
+        };
+        let tls = ca.client_options(false);
+        run_async_server(options, |_, port| async move {
+            let client = tokio::task::spawn_blocking(move || {
+                let address = (Ipv4Addr::LOCALHOST, port);
+                let mut retries = 100;
+                let connection = loop {
+                    match Connection::with_options(address, -1, tls_options(tls.clone())) {
+                        Ok(connection) => break connection,
+                        Err(_) if retries > 0 => {
+                            retries -= 1;
+                            thread::sleep(Duration::from_millis(10));
+                        }
+                        Err(err) => panic!("could not connect to server: {:?}", err),
+                    }
+                };
+                assert!(matches!(
+                    connection.transceive(DummyRequest::Ping),
+                    Ok(DummyResponse::Pong)
+                ));
+                let numbers = connection
+                    .transceive_stream(DummyRequest::Count(3))
+                    .map(|stream| stream.count());
+                assert!(matches!(numbers, Ok(3)));
+
+                // a client without tls is refused
+                assert!(Connection::new(address, -1).is_err());
+            });
+            assert!(client.await.is_ok());
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_token_authentication() -> io::Result<()> {
+    let credentials = |token: &str| client::ConnectionOptions {
+        credentials: Some(Arc::new(auth::Token::new(token))),
+        ..Default::default()
+    };
+    let options = server::ServerOptions {
+        authenticator: Some(Arc::new(
+            auth::TokenAuthenticator::new().with_token("alice's token", "alice"),
+        )),
+        ..Default::default()
+    };
+    run_async_server(options, |ip, port| async move {
+        let alice = connect_with_options(ip, port, credentials("alice's token")).await;
+        assert!(matches!(
+            alice.transceive(DummyRequest::Principal).await,
+            Ok(DummyResponse::Principal(Some(principal))) if principal == "alice"
+        ));
+
+        let mallory = AsyncConnection::with_options((ip, port), -1, credentials("guessed")).await;
+        assert!(matches!(mallory, Err(Error::AuthenticationFailed)));
+        assert!(matches!(
+            AsyncConnection::new((ip, port), -1).await,
+            Err(Error::AuthenticationFailed)
+        ));
+
+        // the blocking client authenticates the same way
+        let client = tokio::task::spawn_blocking(move || {
+            let alice = Connection::with_options((ip, port), -1, credentials("alice's token"))
+                .expect("connecting");
+            assert!(matches!(
+                alice.transceive(DummyRequest::Principal),
+                Ok(DummyResponse::Principal(Some(principal))) if principal == "alice"
+            ));
+        });
+        assert!(client.await.is_ok());
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_client_with_blocking_server() -> io::Result<()> {
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = Arc::new(server::Server::<DummyServer>::new(port, service));
+    let shutdown_handle = server.shutdown_handle();
+    let runner = {
+        let server = server.clone();
+        thread::spawn(move || server.run())
+    };
+
+    let connection = connect(ip, port).await;
+    assert!(matches!(
+        connection.transceive(DummyRequest::Ping).await,
+        Ok(DummyResponse::Pong)
+    ));
+    let mut stream = connection
+        .transceive_stream(DummyRequest::Add(7))
+        .await
+        .unwrap();
+    assert!(matches!(
+        stream.next().await,
+        Some(Ok(DummyResponse::Number(7)))
+    ));
+    assert!(stream.next().await.is_none());
+    drop(stream);
+    drop(connection);
+
+    shutdown_handle.shutdown();
+    assert!(matches!(runner.join(), Ok(Ok(()))));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_codecs() -> io::Result<()> {
+    for format in codec::Format::supported() {
+        let options = server::ServerOptions {
+            formats: vec![format],
+            ..Default::default()
+        };
+        run_async_server(options, |ip, port| async move {
+            let connection = connect(ip, port).await;
+            assert_eq!(connection.format(), format);
+            assert!(matches!(
+                connection.transceive(DummyRequest::Ping).await,
+                Ok(DummyResponse::Pong)
+            ));
+            assert!(matches!(
+                connection.transceive(DummyRequest::Fail).await,
+                Err(Error::Remote(_))
+            ));
+            let sum = connection
+                .transceive_client_stream((1..=4).map(DummyRequest::Add))
+                .await;
+            assert!(matches!(sum, Ok(DummyResponse::Number(10))));
+
+            // the blocking client negotiates the same codec
+            let client = tokio::task::spawn_blocking(move || {
+                let connection = Connection::new((ip, port), -1).unwrap();
+                assert_eq!(connection.format(), format);
+                let numbers = connection
+                    .transceive_stream(DummyRequest::Count(2))
+                    .map(|stream| stream.count());
+                assert!(matches!(numbers, Ok(2)));
+            });
+            assert!(client.await.is_ok());
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_compression() -> io::Result<()> {
+    for algorithm in compression::Algorithm::supported() {
+        let options = server::ServerOptions {
+            compression: vec![algorithm],
+            compression_threshold: 0,
+            ..Default::default()
+        };
+        run_async_server(options, |ip, port| async move {
+            let connection = connect(ip, port).await;
+            assert_eq!(
+                connection
+                    .compression()
+                    .map(|compression| compression.algorithm),
+                Some(algorithm)
+            );
+            let blob = (0..64 * 1024).map(|n| (n % 7) as u8).collect::<Vec<_>>();
+            assert!(matches!(
+                connection.transceive(DummyRequest::Blob(blob.clone())).await,
+                Ok(DummyResponse::Blob(echo)) if echo == blob
+            ));
+            assert!(matches!(
+                connection.transceive(DummyRequest::Ping).await,
+                Ok(DummyResponse::Pong)
+            ));
+
+            // the blocking client compresses as well
+            let client = tokio::task::spawn_blocking(move || {
+                let connection = Connection::new((ip, port), -1).unwrap();
+                assert_eq!(
+                    connection.compression(),
+                    Some(compression::Compression {
+                        algorithm,
+                        threshold: 0,
+                    })
+                );
+                assert!(matches!(
+                    connection.transceive(DummyRequest::Blob(blob.clone())),
+                    Ok(DummyResponse::Blob(echo)) if echo == blob
+                ));
+            });
+            assert!(client.await.is_ok());
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_deadlines() -> io::Result<()> {
+    run_async_server(Default::default(), |ip, port| async move {
+        let connection = connect(ip, port).await;
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert!(matches!(
+            connection.transceive_with_deadline(DummyRequest::Remaining, deadline).await,
+            Ok(DummyResponse::Number(remaining)) if remaining > 0 && remaining <= 1000
+        ));
+        assert!(matches!(
+            connection.transceive(DummyRequest::Remaining).await,
+            Err(Error::Remote(err)) if err == "no deadline"
+        ));
+
+        // client and server give up at the same time, either may be first
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(100);
+        assert!(matches!(
+            connection
+                .transceive_with_deadline(DummyRequest::Sleep(300), deadline)
+                .await,
+            Err(Error::Timeout) | Err(Error::Rejected(transport::Fault::DeadlineExceeded))
+        ));
+        assert!(start.elapsed() < Duration::from_millis(300));
+
+        // the async server drops the execution at the deadline
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let pending = connection
+            .send_with_deadline(DummyRequest::Sleep(300), deadline)
+            .await
+            .unwrap();
+        assert!(matches!(
+            pending.wait().await,
+            Err(Error::Rejected(transport::Fault::DeadlineExceeded))
+        ));
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping).await,
+            Ok(DummyResponse::Pong)
+        ));
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_legacy_identify() -> io::Result<()> {
+    run_async_server(Default::default(), |ip, port| async move {
+        // a client of protocol version 0 sends the Identify without formats
+        let identify = transport::Transmission::<mgmt::LegacyRequest> {
+            id: 7,
+            r#type: transport::Type::Request(mgmt::LegacyRequest::Identify {
+                protocol_version: 0,
+            }),
+        };
+        let mut stream = loop {
+            if let Ok(stream) = tokio::net::TcpStream::connect((ip, port)).await {
+                break stream;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let identify = codec::Bincode.encode(&identify).unwrap();
+        assert!(async_util::write_transmission(&mut stream, identify, None)
+            .await
+            .is_ok());
+
+        // and decodes the answer in the layout of its version
+        let length_prefix = async_util::wait_for_transmission(&mut stream)
+            .await
+            .unwrap();
+        let response =
+            async_util::read_transmission(&mut stream, length_prefix, DEFAULT_MAX_FRAME_SIZE, None)
+                .await
+                .unwrap();
+        assert!(matches!(
+            codec::Bincode.decode::<transport::Transmission<mgmt::LegacyResponse>>(&response),
+            Ok(transport::Transmission {
+                id: 7,
+                r#type: transport::Type::Response(mgmt::LegacyResponse::Identify(identity)),
+            }) if identity.protocol_version == ProtocolVersion::entity().version()
+        ));
+    })
+    .await
+}
+
+// a service with an error type which the encoding of a fault could be mistaken for
+struct AsyncCodedServer;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum ErrorCode {
+    NotFound,
+    Denied,
+    Busy,
+    Unavailable,
+    Aborted,
+}
+
+impl async_server::MessageProcessing for AsyncCodedServer {
+    type Rq = DummyRequest;
+    type Rsp = DummyResponse;
+    type E = ErrorCode;
+
+    fn new() -> Arc<Self> {
+        Arc::new(AsyncCodedServer)
+    }
+
+    async fn execute(&self, _connection_id: u32, rpc: Self::Rq) -> Result<Self::Rsp, Self::E> {
+        match rpc {
+            DummyRequest::Sleep(ms) => {
+                tokio::time::sleep(Duration::from_millis(ms)).await;
+                Ok(DummyResponse::Slept)
+            }
+            _ => Err(ErrorCode::Aborted),
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_faults_with_enum_error() -> io::Result<()> {
+    run_server::<AsyncCodedServer, _, _>(Default::default(), |ip, port| async move {
+        type CodedConnection =
+            async_client::AsyncConnection<DummyRequest, DummyResponse, ErrorCode>;
+        let connection = loop {
+            if let Ok(connection) = CodedConnection::new((ip, port), -1).await {
+                break connection;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        // a fault isn't decoded as an error code
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let pending = connection
+            .send_with_deadline(DummyRequest::Sleep(300), deadline)
+            .await
+            .unwrap();
+        assert!(matches!(
+            pending.wait().await,
+            Err(Error::Rejected(transport::Fault::DeadlineExceeded))
+        ));
+
+        let pending = connection.send(DummyRequest::Sleep(5000)).await.unwrap();
+        assert!(pending.cancel().await.is_ok());
+        assert!(matches!(pending.wait().await, Err(Error::Cancelled)));
+
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping).await,
+            Err(Error::Remote(ErrorCode::Aborted))
+        ));
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_cancel() -> io::Result<()> {
+    run_async_server(Default::default(), |ip, port| async move {
+        let connection = connect(ip, port).await;
+
+        // the async server drops the execution
+        let start = Instant::now();
+        let pending = connection.send(DummyRequest::Sleep(5000)).await.unwrap();
+        assert!(pending.cancel().await.is_ok());
+        assert!(matches!(pending.wait().await, Err(Error::Cancelled)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let pending = connection.send(DummyRequest::Sleep(5000)).await.unwrap();
+        let canceller = pending.canceller();
+        let (response, cancelled) = tokio::join!(pending.wait(), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            canceller.cancel().await
+        });
+        assert!(matches!(response, Err(Error::Cancelled)));
+        assert!(cancelled.is_ok());
+
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping).await,
+            Ok(DummyResponse::Pong)
+        ));
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_cancel_client_stream() -> io::Result<()> {
+    run_async_server(Default::default(), |ip, port| async move {
+        // waits until the server is running
+        drop(connect(ip, port).await);
+
+        let client = tokio::task::spawn_blocking(move || {
+            let connection = Connection::new((ip, port), -1).unwrap();
+            let mut stream = connection.open_client_stream().unwrap();
+            assert!(stream.send(DummyRequest::Add(1)).is_ok());
+            assert!(stream.cancel().is_ok());
+            assert!(stream.send(DummyRequest::Add(2)).is_ok());
+            assert!(matches!(stream.finish(), Err(Error::Cancelled)));
+
+            assert!(matches!(
+                connection.transceive_client_stream((1..=4).map(DummyRequest::Add)),
+                Ok(DummyResponse::Number(10))
+            ));
+        });
+        assert!(client.await.is_ok());
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_notifications() -> io::Result<()> {
+    run_async_server(Default::default(), |ip, port| async move {
+        let connection = connect(ip, port).await;
+
+        for n in 1..=100 {
+            assert!(connection.notify(DummyRequest::Add(n)).await.is_ok());
+        }
+        assert!(connection.notify(DummyRequest::Fail).await.is_ok());
+
+        let start = Instant::now();
+        loop {
+            match connection.transceive(DummyRequest::Notified).await {
+                Ok(DummyResponse::Number(5050)) => break,
+                Ok(DummyResponse::Number(_)) if start.elapsed() < Duration::from_secs(1) => {
+                    tokio::time::sleep(Duration::from_millis(10)).await
+                }
+                unexpected => panic!("unexpected response: {:?}", unexpected),
+            }
+        }
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_max_connections() -> io::Result<()> {
+    let options = server::ServerOptions {
+        max_connections_per_peer: Some(1),
+        ..Default::default()
+    };
+    run_async_server(options, |ip, port| async move {
+        let connection = connect(ip, port).await;
+        assert!(matches!(
+            AsyncConnection::new((ip, port), -1).await,
+            Err(Error::ServerBusy(ConnectionLimit::Peer(1)))
+        ));
+
+        // the connection can be opened after the other connection is closed
+        assert!(connection.close().await.is_ok());
+        let mut retries = 100;
+        let connection = loop {
+            match AsyncConnection::new((ip, port), -1).await {
+                Err(Error::ServerBusy(_)) if retries > 0 => {
+                    retries -= 1;
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                connection => break connection,
+            }
+        };
+        assert!(connection.is_ok());
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_ipv6() -> io::Result<()> {
+    let options = server::ServerOptions {
+        addresses: vec![Ipv6Addr::UNSPECIFIED.into()],
+        ..Default::default()
+    };
+    run_async_server(options, |_, port| async move {
+        let connection = connect(Ipv6Addr::LOCALHOST, port).await;
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping).await,
+            Ok(DummyResponse::Pong)
+        ));
+        assert!(connection.close().await.is_ok());
+    })
+    .await
+}
@@ -0,0 +1,76 @@
+// the async counterparts of the transmission functions in util.rs
+use crate::compression::{self, Compression};
+use crate::util::FrameSizeExceeded;
+
+use std::io;
+use std::io::{Error, ErrorKind};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub async fn wait_for_transmission<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<u64> {
+    let mut datalengthbuffer = [0u8; 8];
+    stream
+        .read_exact(&mut datalengthbuffer[..])
+        .await
+        .map_err(|err| {
+            log::error!("waiting for transmission: {:?}", err);
+            err
+        })?;
+    Ok(u64::from_be_bytes(datalengthbuffer))
+}
+
+pub async fn read_transmission<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    length_prefix: u64,
+    max_frame_size: u64,
+    compression: Option<Compression>,
+) -> io::Result<Vec<u8>> {
+    let (payload_size, compressed) = compression::payload_size(length_prefix, compression);
+    if payload_size > max_frame_size {
+        let mut head = vec![0u8; 8];
+        if payload_size < head.len() as u64
+            || compressed.is_some()
+            || stream.read_exact(&mut head).await.is_err()
+        {
+            head.clear();
+        }
+        let err = FrameSizeExceeded {
+            payload_size,
+            max_frame_size,
+            head,
+        };
+        log::error!("waiting for transmission: {}", err);
+        return Err(Error::new(ErrorKind::InvalidData, err));
+    }
+
+    let mut databuffer = vec![0u8; payload_size as usize];
+    stream
+        .read_exact(&mut databuffer[..])
+        .await
+        .map_err(|err| {
+            log::error!("waiting for transmission: {:?}", err);
+            err
+        })?;
+    match compressed {
+        Some(compression) => compression::decompress(compression, &databuffer, max_frame_size),
+        None => Ok(databuffer),
+    }
+}
+
+pub async fn write_transmission<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    serialized: Vec<u8>,
+    compression: Option<Compression>,
+) -> io::Result<usize> {
+    let (length_prefix, payload) = compression::compress(compression, serialized)?;
+    let mut senddata = length_prefix.to_be_bytes().to_vec();
+    senddata.extend(payload);
+
+    stream
+        .write_all(&senddata)
+        .await
+        .map(|_| senddata.len())
+        .map_err(|err| {
+            log::error!("writing transmission: {:?}", err);
+            err
+        })
+}
@@ -0,0 +1,122 @@
+// authentication of the clients on the management connection, before the server assigns a connection
+use crate::server::Peer;
+
+use std::fmt;
+
+// validates the credentials of the clients for ServerOptions::authenticator
+pub trait Authenticator: Send + Sync + fmt::Debug {
+    // sent to the client before it presents its credentials, e.g. a nonce for a challenge/response scheme
+    fn challenge(&self, _peer: &Peer) -> Vec<u8> {
+        // default implementation sends an empty challenge, e.g. for pre-shared tokens
+        Vec::new()
+    }
+
+    // returns the principal the credentials belong to, None if they are invalid
+    fn authenticate(&self, peer: &Peer, challenge: &[u8], credentials: &[u8]) -> Option<String>;
+}
+
+// presents the credentials of a client for ConnectionOptions::credentials
+pub trait Credentials: Send + Sync + fmt::Debug {
+    fn respond(&self, challenge: &[u8]) -> Vec<u8>;
+}
+
+// pre-shared tokens, each identifying a principal
+#[derive(Clone, Default)]
+pub struct TokenAuthenticator {
+    tokens: Vec<(Vec<u8>, String)>,
+}
+
+impl TokenAuthenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_token(mut self, token: impl Into<Vec<u8>>, principal: impl Into<String>) -> Self {
+        self.tokens.push((token.into(), principal.into()));
+        self
+    }
+}
+
+impl Authenticator for TokenAuthenticator {
+    fn authenticate(&self, _peer: &Peer, _challenge: &[u8], credentials: &[u8]) -> Option<String> {
+        // all tokens are compared, so that the time doesn't reveal which one matched
+        self.tokens
+            .iter()
+            .fold(None, |principal, (token, candidate)| {
+                if constant_time_eq(token, credentials) {
+                    Some(candidate)
+                } else {
+                    principal
+                }
+            })
+            .cloned()
+    }
+}
+
+// the tokens are secret, only the principals are printed
+impl fmt::Debug for TokenAuthenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenAuthenticator")
+            .field(
+                "principals",
+                &self
+                    .tokens
+                    .iter()
+                    .map(|(_, principal)| principal)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+// a pre-shared token, see TokenAuthenticator
+#[derive(Clone)]
+pub struct Token(Vec<u8>);
+
+impl Token {
+    pub fn new(token: impl Into<Vec<u8>>) -> Self {
+        Token(token.into())
+    }
+}
+
+impl Credentials for Token {
+    fn respond(&self, _challenge: &[u8]) -> Vec<u8> {
+        self.0.clone()
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Token(..)")
+    }
+}
+
+// the time depends only on the lengths, not on the position of the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_authenticator() {
+        let authenticator = TokenAuthenticator::new()
+            .with_token("secret", "alice")
+            .with_token(b"other".to_vec(), "bob");
+        let peer = Peer::new("127.0.0.1:5000".to_string());
+        assert_eq!(
+            authenticator.authenticate(&peer, &[], b"secret"),
+            Some("alice".to_string())
+        );
+        assert_eq!(
+            authenticator.authenticate(&peer, &[], b"other"),
+            Some("bob".to_string())
+        );
+        assert_eq!(authenticator.authenticate(&peer, &[], b"secre"), None);
+        assert_eq!(authenticator.authenticate(&peer, &[], b""), None);
+        assert!(!format!("{:?}", authenticator).contains("secret"));
+        assert!(!format!("{:?}", Token::new("secret")).contains("secret"));
+    }
+}
@@ -1,15 +1,233 @@
+use crate::auth::Credentials;
+use crate::codec::{self, Codec, Format};
+use crate::compression::{Algorithm, Compression};
 use crate::mgmt;
+use crate::stream::{Address, Stream};
 use crate::transport;
 use crate::util;
 use crate::{ProtocolVersion, Service};
 
-use bincode::Options;
 use serde::{de::DeserializeOwned, Serialize};
 
+#[cfg(feature = "tls")]
+use rustls::pki_types::ServerName;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::io;
+use std::io::Write;
 use std::marker::PhantomData;
-use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpStream};
-use std::time;
+use std::net::{Shutdown, SocketAddr, ToSocketAddrs};
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// routes the transmissions received on the rpc stream to the callers waiting for the transmission id
+#[derive(Debug, Default)]
+struct Dispatcher {
+    pending: Mutex<HashMap<u64, mpsc::Sender<Vec<u8>>>>,
+    closed: AtomicBool,
+}
+
+impl Dispatcher {
+    fn register(&self, transmission_id: u64) -> io::Result<mpsc::Receiver<Vec<u8>>> {
+        let mut pending = self.pending.lock().expect("getting lock");
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "connection closed",
+            ));
+        }
+        let (sender, receiver) = mpsc::channel();
+        pending.insert(transmission_id, sender);
+        Ok(receiver)
+    }
+
+    fn unregister(&self, transmission_id: u64) {
+        self.pending
+            .lock()
+            .expect("getting lock")
+            .remove(&transmission_id);
+    }
+
+    fn dispatch(&self, transmission_id: u64, payload: Vec<u8>) {
+        let pending = self.pending.lock().expect("getting lock");
+        match pending.get(&transmission_id) {
+            Some(sender) => {
+                // the receiver might already be gone if the caller dropped the pending response
+                let _ = sender.send(payload);
+            }
+            None => log::warn!("no caller waiting for transmission id {}", transmission_id),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    // dropping all senders wakes up all waiting callers
+    fn close(&self) {
+        let mut pending = self.pending.lock().expect("getting lock");
+        self.closed.store(true, Ordering::Relaxed);
+        pending.clear();
+    }
+
+    fn receive(
+        dispatcher: Arc<Dispatcher>,
+        mut stream: Stream,
+        format: Format,
+        compression: Option<Compression>,
+        max_frame_size: u64,
+    ) {
+        loop {
+            let payload = util::wait_for_transmission(&mut stream).and_then(|length_prefix| {
+                util::read_transmission(&mut stream, length_prefix, max_frame_size, compression)
+            });
+            let payload = match payload {
+                Ok(payload) => payload,
+                Err(err) => {
+                    // the remaining transmissions can't be read, therefore the connection is closed
+                    if util::frame_size_exceeded(&err).is_some() {
+                        let _ = stream.shutdown(Shutdown::Both);
+                    }
+                    break;
+                }
+            };
+            match format.transmission_id(&payload) {
+                Some(transmission_id) => dispatcher.dispatch(transmission_id, payload),
+                None => log::error!("transmission without transmission id"),
+            }
+        }
+        dispatcher.close();
+        log::debug!("end client receiver");
+    }
+}
+
+// the sending side of the rpc stream
+#[derive(Debug)]
+struct Writer {
+    stream: Stream,
+    compression: Option<Compression>,
+    last_transmission: Instant,
+}
+
+impl Writer {
+    fn write(&mut self, transmission: Vec<u8>) -> io::Result<usize> {
+        self.last_transmission = Instant::now();
+        util::write_transmission(&mut self.stream, transmission, self.compression)
+    }
+
+    // sends a KeepAlive transmission whenever nothing else was sent for half of the rpc interval timeout
+    fn keep_alive(
+        writer: Arc<Mutex<Writer>>,
+        format: Format,
+        interval: Duration,
+        stop: mpsc::Receiver<()>,
+    ) {
+        let interval = interval / 2;
+        while let Err(mpsc::RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+            let mut writer = writer.lock().expect("getting lock");
+            if writer.last_transmission.elapsed() < interval {
+                continue;
+            }
+            let keep_alive = serialize::<(), ()>(&format, 0, transport::Type::KeepAlive);
+            if let Err(err) = keep_alive.and_then(|keep_alive| Ok(writer.write(keep_alive)?)) {
+                log::error!("sending keep alive: {:?}", err);
+                break;
+            }
+        }
+        log::debug!("end client keep alive");
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionOptions {
+    // the timeout for opening the management and rpc connections
+    pub connect_timeout: Duration,
+    // the timeout for each management response and the tls handshakes
+    pub handshake_timeout: Duration,
+    // the server closes the connection if it doesn't receive a transmission within this interval; None is infinite
+    pub rpc_interval_timeout: Option<Duration>,
+    // sends KeepAlive transmissions to prevent the server from closing an idle connection
+    pub keep_alive: bool,
+    // the connection is closed if the server transmits a larger payload
+    pub max_frame_size: u64,
+    // the management and rpc connections are encrypted; the server must be configured for tls as well
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsOptions>,
+    // presented to the server before the connection is requested, see ServerOptions::authenticator
+    pub credentials: Option<Arc<dyn Credentials>>,
+    // the codecs offered to the server for the rpcs; the server chooses one of them
+    pub formats: Vec<Format>,
+    // the compression algorithms offered to the server for the rpcs; the server decides whether to compress
+    pub compression: Vec<Algorithm>,
+    // reestablishes a lost connection with the same connection id; None reports the lost connection as io error.
+    // only the blocking Connection reconnects
+    pub reconnect: Option<ReconnectPolicy>,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            connect_timeout: Duration::from_secs(2),
+            handshake_timeout: Duration::from_secs(2),
+            rpc_interval_timeout: None,
+            keep_alive: true,
+            max_frame_size: util::DEFAULT_MAX_FRAME_SIZE,
+            #[cfg(feature = "tls")]
+            tls: None,
+            credentials: None,
+            formats: Format::supported(),
+            compression: Algorithm::supported(),
+            reconnect: None,
+        }
+    }
+}
+
+// the attempts to reestablish a lost connection; the delay between the attempts is doubled after each one
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    pub max_attempts: u32,
+    // the delay before the second attempt, the first one is immediate
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+#[derive(Clone, Debug)]
+pub struct TlsOptions {
+    // the trust roots for the server certificate; a client certificate makes it mutual tls
+    pub config: Arc<rustls::ClientConfig>,
+    // the name the server certificate is verified against, e.g. the host name of the server
+    pub server_name: ServerName<'static>,
+}
+
+// performs the tls handshake within the read timeout of the stream
+#[cfg(feature = "tls")]
+fn secure(stream: Stream, options: &ConnectionOptions) -> io::Result<Stream> {
+    match &options.tls {
+        Some(tls) => crate::tls::connect(stream, tls.config.clone(), tls.server_name.clone()),
+        None => Ok(stream),
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+fn secure(stream: Stream, _options: &ConnectionOptions) -> io::Result<Stream> {
+    Ok(stream)
+}
 
 #[derive(Debug)]
 pub struct Connection<Req, Resp, Error>
@@ -18,218 +236,1281 @@ where
     Resp: DeserializeOwned + std::fmt::Debug,
     Error: DeserializeOwned + std::fmt::Debug,
 {
+    session: Mutex<Arc<Session>>,
+    // the management addresses and the options for reestablishing a lost connection
+    addresses: Vec<Address>,
+    options: ConnectionOptions,
+    _req: PhantomData<Req>,
+    _resp: PhantomData<Resp>,
+    _error: PhantomData<Error>,
+}
+
+// the rpc connection of a Connection; replaced when a lost connection is reestablished
+#[derive(Debug)]
+struct Session {
     id: u32,
+    // the management address which answered Identify
+    address: Address,
     port: u16,
-    stream: TcpStream,
+    format: Format,
+    compression: Option<Compression>,
+    writer: Arc<Mutex<Writer>>,
+    transmission_id: AtomicU64,
+    dispatcher: Arc<Dispatcher>,
+    receiver: Option<thread::JoinHandle<()>>,
+    keep_alive: Option<(mpsc::Sender<()>, thread::JoinHandle<()>)>,
     server_protocol_version: u32,
     server_service: Service,
-    _req: PhantomData<Req>,
+}
+
+impl Session {
+    fn next_transmission_id(&self) -> u64 {
+        self.transmission_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn write(&self, transmission: Vec<u8>) -> io::Result<usize> {
+        self.writer
+            .lock()
+            .expect("getting lock")
+            .write(transmission)
+    }
+
+    // ends the receiver, which wakes up all callers waiting for responses
+    fn shutdown(&self) {
+        let writer = self.writer.lock().expect("getting lock");
+        if let Err(err) = writer.stream.shutdown(Shutdown::Both) {
+            log::error!("shutdown stream: {:?}", err);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        log::info!("shutdown stream");
+
+        if let Some((stop, thread)) = self.keep_alive.take() {
+            drop(stop);
+            let _ = thread.join();
+        }
+
+        // a lost connection can't transmit the End
+        if !self.dispatcher.is_closed() {
+            let end = serialize::<(), ()>(
+                &self.format,
+                self.next_transmission_id(),
+                transport::Type::End,
+            );
+            if let Err(err) = end.and_then(|end| Ok(self.write(end)?)) {
+                log::error!("sending transmission end: {:?}", err);
+            }
+        }
+
+        self.shutdown();
+
+        if let Some(receiver) = self.receiver.take() {
+            let _ = receiver.join();
+        }
+    }
+}
+
+// the transmissions for one transmission id; the id is released on drop
+#[derive(Debug)]
+struct Subscription<'a> {
+    transmission_id: u64,
+    // the codec of the received transmissions
+    format: Format,
+    receiver: mpsc::Receiver<Vec<u8>>,
+    // the dispatcher of the session, which may be replaced on the connection in the meantime
+    dispatcher: Arc<Dispatcher>,
+    // the writer of the session for the Cancel transmission
+    writer: Arc<Mutex<Writer>>,
+    _connection: PhantomData<&'a ()>,
+}
+
+impl<'a> Subscription<'a> {
+    fn new(session: &Session, transmission_id: u64) -> io::Result<Self> {
+        Ok(Subscription {
+            transmission_id,
+            format: session.format,
+            receiver: session.dispatcher.register(transmission_id)?,
+            dispatcher: session.dispatcher.clone(),
+            writer: session.writer.clone(),
+            _connection: PhantomData,
+        })
+    }
+
+    fn canceller<Error>(&self) -> Canceller<Error> {
+        Canceller {
+            transmission_id: self.transmission_id,
+            format: self.format,
+            writer: self.writer.clone(),
+            _error: PhantomData,
+        }
+    }
+
+    fn recv(&self) -> io::Result<Vec<u8>> {
+        self.receiver.recv().map_err(|_| Self::closed())
+    }
+
+    fn recv_until(&self, deadline: Instant) -> io::Result<Vec<u8>> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.receiver
+            .recv_timeout(timeout)
+            .map_err(|err| match err {
+                mpsc::RecvTimeoutError::Timeout => {
+                    log::error!("no response within the deadline");
+                    io::Error::new(io::ErrorKind::TimedOut, "no response within the deadline")
+                }
+                mpsc::RecvTimeoutError::Disconnected => Self::closed(),
+            })
+    }
+
+    fn closed() -> io::Error {
+        log::error!("connection closed while waiting for response");
+        io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "connection closed while waiting for response",
+        )
+    }
+}
+
+impl<'a> Drop for Subscription<'a> {
+    fn drop(&mut self) {
+        self.dispatcher.unregister(self.transmission_id);
+    }
+}
+
+// cancels an outstanding request, e.g. from another thread than the one waiting for the response
+#[derive(Debug)]
+pub struct Canceller<Error> {
+    transmission_id: u64,
+    format: Format,
+    writer: Arc<Mutex<Writer>>,
+    _error: PhantomData<Error>,
+}
+
+impl<Error> Canceller<Error> {
+    // sends the Cancel transmission; the waiting caller receives Error::Cancelled, or the response if the server
+    // sent it before it received the Cancel
+    pub fn cancel(&self) -> Result<(), crate::Error<Error>> {
+        let cancel =
+            serialize::<(), Error>(&self.format, self.transmission_id, transport::Type::Cancel)?;
+        self.writer.lock().expect("getting lock").write(cancel)?;
+        Ok(())
+    }
+}
+
+// a request which was sent to the server and whose response is not yet received
+#[derive(Debug)]
+pub struct PendingResponse<'a, Resp, Error> {
+    subscription: Subscription<'a>,
     _resp: PhantomData<Resp>,
     _error: PhantomData<Error>,
 }
 
+impl<'a, Resp, Error> PendingResponse<'a, Resp, Error>
+where
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    pub fn transmission_id(&self) -> u64 {
+        self.subscription.transmission_id
+    }
+
+    // blocks until the response for this request is received
+    pub fn wait(self) -> Result<Resp, crate::Error<Error>> {
+        let response = self.subscription.recv()?;
+        decode_response::<Resp, Error>(&self.subscription.format, &response)
+    }
+
+    // like wait, but gives up with Error::Timeout at the deadline; a late response is discarded
+    pub fn wait_until(self, deadline: Instant) -> Result<Resp, crate::Error<Error>> {
+        let response = self.subscription.recv_until(deadline)?;
+        decode_response::<Resp, Error>(&self.subscription.format, &response)
+    }
+
+    // asks the server to cancel the request; wait still has to be called for the outcome
+    pub fn cancel(&self) -> Result<(), crate::Error<Error>> {
+        self.canceller().cancel()
+    }
+
+    pub fn canceller(&self) -> Canceller<Error> {
+        self.subscription.canceller()
+    }
+}
+
+// the responses of a stream request; the iteration ends with the End transmission of the server or after the first error
+#[derive(Debug)]
+pub struct StreamingResponse<'a, Resp, Error> {
+    subscription: Subscription<'a>,
+    finished: bool,
+    _resp: PhantomData<Resp>,
+    _error: PhantomData<Error>,
+}
+
+impl<'a, Resp, Error> StreamingResponse<'a, Resp, Error> {
+    pub fn transmission_id(&self) -> u64 {
+        self.subscription.transmission_id
+    }
+
+    // asks the server to stop the stream; the iteration ends with Error::Cancelled after the items already sent
+    pub fn cancel(&self) -> Result<(), crate::Error<Error>> {
+        self.canceller().cancel()
+    }
+
+    pub fn canceller(&self) -> Canceller<Error> {
+        self.subscription.canceller()
+    }
+}
+
+impl<'a, Resp, Error> Iterator for StreamingResponse<'a, Resp, Error>
+where
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    type Item = Result<Resp, crate::Error<Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let item = match self.subscription.recv() {
+            Ok(item) => decode_stream_item::<Resp, Error>(&self.subscription.format, &item),
+            Err(err) => Some(Err(err.into())),
+        };
+        if !matches!(item, Some(Ok(_))) {
+            self.finished = true;
+        }
+        item
+    }
+}
+
+// the requests of a client stream or bidirectional stream; the End transmission is sent on close or drop
+#[derive(Debug)]
+pub struct StreamSender<'a, Req, Error> {
+    transmission_id: u64,
+    format: Format,
+    writer: Arc<Mutex<Writer>>,
+    // a client stream is opened on the server side with the first Stream transmission
+    opened: bool,
+    closed: bool,
+    _connection: PhantomData<&'a ()>,
+    _req: PhantomData<Req>,
+    _error: PhantomData<Error>,
+}
+
+impl<'a, Req, Error> StreamSender<'a, Req, Error>
+where
+    Req: Serialize,
+{
+    pub fn transmission_id(&self) -> u64 {
+        self.transmission_id
+    }
+
+    pub fn send(&mut self, request: Req) -> Result<(), crate::Error<Error>> {
+        if self.closed {
+            return Err(crate::Error::Protocol("stream already closed".to_string()));
+        }
+        let transmission = serialize(
+            &self.format,
+            self.transmission_id,
+            transport::Type::Stream(request),
+        )?;
+        self.writer
+            .lock()
+            .expect("getting lock")
+            .write(transmission)?;
+        self.opened = true;
+        Ok(())
+    }
+
+    // signals the server that there are no further requests for this stream
+    pub fn close(&mut self) -> Result<(), crate::Error<Error>> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        if !self.opened {
+            // an End for an unknown transmission id would terminate the connection
+            return Ok(());
+        }
+        let end = serialize::<(), Error>(&self.format, self.transmission_id, transport::Type::End)?;
+        self.writer.lock().expect("getting lock").write(end)?;
+        Ok(())
+    }
+}
+
+impl<'a, Req, Error> Drop for StreamSender<'a, Req, Error> {
+    fn drop(&mut self) {
+        if self.closed || !self.opened {
+            return;
+        }
+        let end = serialize::<(), ()>(&self.format, self.transmission_id, transport::Type::End);
+        if let Err(err) = end.and_then(|end| {
+            self.writer
+                .lock()
+                .expect("getting lock")
+                .write(end)
+                .map_err(crate::Error::from)
+        }) {
+            log::error!("sending stream end: {:?}", err);
+        }
+    }
+}
+
+// a client stream; the server responds with a single response after the stream was finished
+#[derive(Debug)]
+pub struct ClientStream<'a, Req, Resp, Error> {
+    sender: StreamSender<'a, Req, Error>,
+    subscription: Subscription<'a>,
+    _resp: PhantomData<Resp>,
+}
+
+impl<'a, Req, Resp, Error> ClientStream<'a, Req, Resp, Error>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    pub fn transmission_id(&self) -> u64 {
+        self.sender.transmission_id
+    }
+
+    pub fn send(&mut self, request: Req) -> Result<(), crate::Error<Error>> {
+        self.sender.send(request)
+    }
+
+    // asks the server to cancel the stream; finish returns Error::Cancelled unless the server responded before
+    pub fn cancel(&self) -> Result<(), crate::Error<Error>> {
+        self.canceller().cancel()
+    }
+
+    pub fn canceller(&self) -> Canceller<Error> {
+        self.subscription.canceller()
+    }
+
+    // closes the stream and waits for the response of the server
+    pub fn finish(mut self) -> Result<Resp, crate::Error<Error>> {
+        if !self.sender.opened {
+            return Err(crate::Error::Protocol(
+                "client stream without requests".to_string(),
+            ));
+        }
+        self.sender.close()?;
+        let response = self.subscription.recv()?;
+        decode_response::<Resp, Error>(&self.subscription.format, &response)
+    }
+}
+
+// a bidirectional stream; requests and responses can be exchanged independently
+#[derive(Debug)]
+pub struct BidiStream<'a, Req, Resp, Error> {
+    sender: StreamSender<'a, Req, Error>,
+    responses: StreamingResponse<'a, Resp, Error>,
+}
+
+impl<'a, Req, Resp, Error> BidiStream<'a, Req, Resp, Error>
+where
+    Req: Serialize,
+{
+    pub fn transmission_id(&self) -> u64 {
+        self.sender.transmission_id
+    }
+
+    pub fn send(&mut self, request: Req) -> Result<(), crate::Error<Error>> {
+        self.sender.send(request)
+    }
+
+    pub fn close(&mut self) -> Result<(), crate::Error<Error>> {
+        self.sender.close()
+    }
+
+    // asks the server to stop the stream; the iteration ends with Error::Cancelled after the items already sent
+    pub fn cancel(&self) -> Result<(), crate::Error<Error>> {
+        self.responses.cancel()
+    }
+
+    pub fn canceller(&self) -> Canceller<Error> {
+        self.responses.canceller()
+    }
+
+    // allows to send and receive on different threads
+    pub fn split(
+        self,
+    ) -> (
+        StreamSender<'a, Req, Error>,
+        StreamingResponse<'a, Resp, Error>,
+    ) {
+        (self.sender, self.responses)
+    }
+}
+
+impl<'a, Req, Resp, Error> Iterator for BidiStream<'a, Req, Resp, Error>
+where
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    type Item = Result<Resp, crate::Error<Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.responses.next()
+    }
+}
+
+pub(crate) fn compatibility_check(
+    server_protocol_version: u32,
+    server_service: &Service,
+    service: &Service,
+) -> bool {
+    let mut compatiblity = true;
+    let protocol_version = ProtocolVersion::entity().version();
+
+    if protocol_version != server_protocol_version {
+        compatiblity = false;
+        log::error!(
+            "incompatible ETM versions detected! client on v{} and server on v{}!",
+            protocol_version,
+            server_protocol_version
+        );
+    }
+
+    if service.id() != server_service.id() {
+        compatiblity = false;
+        log::error!(
+            "incompatible Services detected! client expects '{}' and server supplies '{}'!",
+            service.id(),
+            server_service.id()
+        );
+    }
+
+    if service.protocol_version() != server_service.protocol_version() {
+        compatiblity = false;
+        log::error!(
+            "incompatible ETM versions detected! client on v{} and server on v{}!",
+            service.protocol_version(),
+            server_service.protocol_version()
+        );
+    }
+
+    compatiblity
+}
+
+// the codec the server chose for the rpcs
+// the layout of the management and transport types may change with the protocol version, therefore the client
+// doesn't connect to a server of another version
+pub(crate) fn compatible_protocol_version<E>(
+    identity: &mgmt::Identity,
+) -> Result<(), crate::Error<E>> {
+    let protocol_version = ProtocolVersion::entity().version();
+    if identity.protocol_version != protocol_version {
+        log::error!(
+            "incompatible ETM versions detected! client on v{} and server on v{}!",
+            protocol_version,
+            identity.protocol_version
+        );
+        return Err(crate::Error::Protocol(format!(
+            "the server uses the protocol version {} instead of {}",
+            identity.protocol_version, protocol_version
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn negotiated_format<E>(identity: &mgmt::Identity) -> Result<Format, crate::Error<E>> {
+    identity.format.ok_or_else(|| {
+        log::error!("the server supports none of the offered codecs");
+        crate::Error::Protocol("the server supports none of the offered codecs".to_string())
+    })
+}
+
+// the server must choose one of the offered compression algorithms
+pub(crate) fn negotiated_compression<E>(
+    comm_settings: &mgmt::CommSettings,
+    options: &ConnectionOptions,
+) -> Result<Option<Compression>, crate::Error<E>> {
+    match comm_settings.compression {
+        Some(compression)
+            if !compression.algorithm.is_supported()
+                || !options.compression.contains(&compression.algorithm) =>
+        {
+            log::error!("the server chose the compression {}", compression.algorithm);
+            Err(crate::Error::Protocol(format!(
+                "the server chose the compression {} which was not offered",
+                compression.algorithm
+            )))
+        }
+        compression => Ok(compression),
+    }
+}
+
+pub(crate) fn serialize<Rq, E>(
+    codec: &impl Codec,
+    transmission_id: u64,
+    r#type: transport::Type<Rq>,
+) -> Result<Vec<u8>, crate::Error<E>>
+where
+    Rq: Serialize,
+{
+    let transmission = transport::Transmission::<Rq> {
+        id: transmission_id,
+        r#type,
+    };
+
+    let transmission = codec.encode(&transmission).map_err(|err| {
+        log::error!("serializing request: {:?}", err);
+        err
+    })?;
+    Ok(transmission)
+}
+
+fn decode_transmission<Rsp, E>(
+    codec: &impl Codec,
+    response: &[u8],
+) -> Result<transport::Transmission<Rsp, E>, crate::Error<E>>
+where
+    Rsp: DeserializeOwned,
+    E: DeserializeOwned,
+{
+    let response = codec
+        .decode::<transport::Transmission<Rsp, E>>(response)
+        .map_err(|err| {
+            log::error!("deserializing response: {:?}", err);
+            err
+        })?;
+
+    // the server rejects a transmission with a fault instead of an error of type E
+    match response.r#type {
+        transport::Type::Fault(transport::Fault::Cancelled) => {
+            log::debug!("request cancelled");
+            Err(crate::Error::Cancelled)
+        }
+        transport::Type::Fault(fault) => {
+            log::error!("rejected by the server: {}", fault);
+            Err(crate::Error::Rejected(fault))
+        }
+        _ => Ok(response),
+    }
+}
+
+pub(crate) fn decode_response<Rsp, E>(
+    codec: &impl Codec,
+    response: &[u8],
+) -> Result<Rsp, crate::Error<E>>
+where
+    Rsp: DeserializeOwned + std::fmt::Debug,
+    E: DeserializeOwned + std::fmt::Debug,
+{
+    match decode_transmission::<Rsp, E>(codec, response)?.r#type {
+        transport::Type::Response(response) => Ok(response),
+        transport::Type::Error(err) => {
+            log::error!("response: {:?}", err);
+            Err(crate::Error::Remote(err))
+        }
+        unexpected => {
+            log::error!("unexpected response: {:?}", unexpected);
+            Err(crate::Error::Protocol(format!(
+                "unexpected response: {:?}",
+                unexpected
+            )))
+        }
+    }
+}
+
+// returns None for the End transmission
+pub(crate) fn decode_stream_item<Rsp, E>(
+    codec: &impl Codec,
+    item: &[u8],
+) -> Option<Result<Rsp, crate::Error<E>>>
+where
+    Rsp: DeserializeOwned + std::fmt::Debug,
+    E: DeserializeOwned + std::fmt::Debug,
+{
+    let item = match decode_transmission::<Rsp, E>(codec, item) {
+        Ok(item) => item,
+        Err(err) => return Some(Err(err)),
+    };
+
+    match item.r#type {
+        transport::Type::Stream(item) => Some(Ok(item)),
+        transport::Type::End => None,
+        transport::Type::Error(err) => {
+            log::error!("stream: {:?}", err);
+            Some(Err(crate::Error::Remote(err)))
+        }
+        unexpected => {
+            log::error!("unexpected stream item: {:?}", unexpected);
+            Some(Err(crate::Error::Protocol(format!(
+                "unexpected stream item: {:?}",
+                unexpected
+            ))))
+        }
+    }
+}
+
 impl<Req, Resp, Error> Connection<Req, Resp, Error>
 where
     Req: Serialize,
     Resp: DeserializeOwned + std::fmt::Debug,
     Error: DeserializeOwned + std::fmt::Debug,
 {
+    // the address of the service management port, e.g. ("localhost", 5000) or "[::1]:5000"
     pub fn new(
-        ip: Ipv4Addr,
-        service_management_port: u16,
+        address: impl ToSocketAddrs,
         connection_id: i32,
-    ) -> Option<Box<Connection<Req, Resp, Error>>> {
-        let addr = SocketAddr::from((ip, service_management_port));
+    ) -> Result<Box<Connection<Req, Resp, Error>>, crate::Error<Error>> {
+        Self::with_options(address, connection_id, ConnectionOptions::default())
+    }
 
+    pub fn with_options(
+        address: impl ToSocketAddrs,
+        connection_id: i32,
+        options: ConnectionOptions,
+    ) -> Result<Box<Connection<Req, Resp, Error>>, crate::Error<Error>> {
+        let addresses = address.to_socket_addrs()?.map(Address::Tcp).collect();
+        Self::connect(addresses, connection_id, options)
+    }
+
+    // connects to a server which listens on the unix domain socket at the path
+    #[cfg(unix)]
+    pub fn unix(
+        path: impl AsRef<Path>,
+        connection_id: i32,
+    ) -> Result<Box<Connection<Req, Resp, Error>>, crate::Error<Error>> {
+        Self::unix_with_options(path, connection_id, ConnectionOptions::default())
+    }
+
+    #[cfg(unix)]
+    pub fn unix_with_options(
+        path: impl AsRef<Path>,
+        connection_id: i32,
+        options: ConnectionOptions,
+    ) -> Result<Box<Connection<Req, Resp, Error>>, crate::Error<Error>> {
+        let address = Address::Unix(path.as_ref().to_path_buf());
+        Self::connect(vec![address], connection_id, options)
+    }
+
+    pub(crate) fn connect(
+        addresses: Vec<Address>,
+        connection_id: i32,
+        options: ConnectionOptions,
+    ) -> Result<Box<Connection<Req, Resp, Error>>, crate::Error<Error>> {
+        // a negative connection id lets the server assign an unused one
+        let connection_id = u32::try_from(connection_id).unwrap_or(mgmt::DYNAMIC_CONNECTION_ID);
+        let session = Self::open(&addresses, connection_id, &options)?;
+        Ok(Box::new(Connection::<Req, Resp, Error> {
+            session: Mutex::new(Arc::new(session)),
+            addresses,
+            options,
+            _req: PhantomData,
+            _resp: PhantomData,
+            _error: PhantomData,
+        }))
+    }
+
+    // performs the management handshake and opens the rpc connection
+    fn open(
+        addresses: &[Address],
+        connection_id: u32,
+        options: &ConnectionOptions,
+    ) -> Result<Session, crate::Error<Error>> {
         let protocol_version = ProtocolVersion::entity().version();
-        let identify = mgmt::Request::Identify { protocol_version };
 
-        let response = Self::mgmt_transceive(&addr, identify)?;
+        let (addr, response) =
+            Self::mgmt_identify(addresses.iter().cloned(), protocol_version, options)
+                .map_err(Self::mgmt_error)?;
         let identity = if let mgmt::Response::Identify(identity) = response {
-            Some(identity)
+            Ok(identity)
         } else {
             log::error!("wrong response to Identify");
-            None
+            Err(crate::Error::Protocol(
+                "wrong response to Identify".to_string(),
+            ))
         }?;
+        compatible_protocol_version(&identity)?;
+        let format = negotiated_format(&identity)?;
 
         let comm_params = mgmt::Request::Connect(mgmt::CommParams {
             protocol_version,
-            connection_id: std::u32::MAX,
-            rpc_interval_timeout_ms: std::u32::MAX,
+            connection_id,
+            rpc_interval_timeout_ms: mgmt::rpc_interval_timeout_ms(options.rpc_interval_timeout),
+            format,
+            compression: options.compression.clone(),
         });
-        let response = Self::mgmt_transceive(&addr, comm_params)?;
-        let comm_settings = if let mgmt::Response::Connect(comm_settings) = response {
-            Some(comm_settings)
-        } else {
-            log::error!("wrong response to Connect");
-            None
+        // in single port mode the server keeps the management connection open for the rpcs
+        let mut mgmt_stream = Self::mgmt_open(&addr, options).map_err(Self::mgmt_error)?;
+        if let Some(credentials) = &options.credentials {
+            Self::mgmt_authenticate(&mut mgmt_stream, credentials.as_ref())
+                .map_err(Self::mgmt_error)?;
+        }
+        let response =
+            Self::mgmt_request(&mut mgmt_stream, comm_params).map_err(Self::mgmt_error)?;
+        let comm_settings = match response {
+            mgmt::Response::Connect(comm_settings) => Ok(comm_settings),
+            mgmt::Response::ConnectionIdInUse(connection_id) => {
+                log::error!("connection id {} already in use", connection_id);
+                Err(crate::Error::ConnectionIdInUse(connection_id))
+            }
+            mgmt::Response::ServerBusy(limit) => {
+                log::error!("server busy: {}", limit);
+                Err(crate::Error::ServerBusy(limit))
+            }
+            mgmt::Response::AuthenticationFailed => {
+                log::error!("the server requires authentication");
+                Err(crate::Error::AuthenticationFailed)
+            }
+            mgmt::Response::UnsupportedFormat(format) => {
+                log::error!("the server doesn't support the codec {}", format);
+                Err(crate::Error::Protocol(format!(
+                    "the server doesn't support the codec {}",
+                    format
+                )))
+            }
+            _ => {
+                log::error!("wrong response to Connect");
+                Err(crate::Error::Protocol(
+                    "wrong response to Connect".to_string(),
+                ))
+            }
         }?;
+        let compression = negotiated_compression(&comm_settings, options)?;
+
+        let rpc_addr = match (&comm_settings.endpoint, &addr) {
+            (mgmt::RpcEndpoint::Management, _) => None,
+            (mgmt::RpcEndpoint::Port(port), Address::Tcp(addr)) => {
+                Some(Address::Tcp(SocketAddr::new(addr.ip(), *port)))
+            }
+            #[cfg(unix)]
+            (mgmt::RpcEndpoint::Path(path), Address::Unix(_)) => Some(Address::Unix(path.into())),
+            (endpoint, _) => {
+                log::error!("rpc endpoint {:?} doesn't match {}", endpoint, addr);
+                return Err(crate::Error::Protocol(format!(
+                    "rpc endpoint {:?} doesn't match the management address {}",
+                    endpoint, addr
+                )));
+            }
+        };
+        let stream = match rpc_addr {
+            None => {
+                log::info!("using the management port for rpcs");
+                mgmt_stream
+            }
+            Some(rpc_addr) => {
+                log::info!("assigned rpc endpoint: {}", rpc_addr);
+                let stream = rpc_addr.connect(options.connect_timeout).map_err(|err| {
+                    log::error!("failed to open communication port: {:?}", err);
+                    err
+                })?;
+                stream.set_read_timeout(Some(options.handshake_timeout))?;
+                let mut stream = secure(stream, options)?;
+                // authorizes the connection, the server doesn't serve it otherwise
+                stream.write_all(&comm_settings.rpc_token.0)?;
+                stream
+            }
+        };
+        // the tcp port of the rpcs; 0 for a unix domain socket
+        let port = stream.peer_addr().map_or(0, |address| address.port());
+
+        util::adjust_stream(&stream, None)?;
 
-        log::info!("assigned port: {}", comm_settings.port);
-        let addr = SocketAddr::from((ip, comm_settings.port));
-        let stream = TcpStream::connect_timeout(&addr, time::Duration::from_secs(2))
-            .map_err(|err| {
-                log::error!("failed to open communication port: {:?}", err);
-                err
+        let dispatcher = Arc::new(Dispatcher::default());
+        let receiver = {
+            let dispatcher = dispatcher.clone();
+            let stream = stream.try_clone()?;
+            let max_frame_size = options.max_frame_size;
+            thread::spawn(move || {
+                Dispatcher::receive(dispatcher, stream, format, compression, max_frame_size)
             })
-            .ok()?;
+        };
 
-        util::adjust_stream(&stream, None).ok()?;
+        let writer = Arc::new(Mutex::new(Writer {
+            stream,
+            compression,
+            last_transmission: Instant::now(),
+        }));
+
+        let keep_alive = match options.rpc_interval_timeout {
+            Some(interval) if options.keep_alive => {
+                let (stop, stopped) = mpsc::channel();
+                let writer = writer.clone();
+                let thread =
+                    thread::spawn(move || Writer::keep_alive(writer, format, interval, stopped));
+                Some((stop, thread))
+            }
+            _ => None,
+        };
 
         log::info!("connected to service: '{}'", identity.service.id());
-        Some(Box::new(Connection::<Req, Resp, Error> {
+        Ok(Session {
             id: comm_settings.connection_id,
-            port: comm_settings.port,
-            stream: stream,
+            address: addr,
+            port,
+            format,
+            compression,
+            writer,
+            transmission_id: AtomicU64::new(0),
+            dispatcher,
+            receiver: Some(receiver),
+            keep_alive,
             server_protocol_version: identity.protocol_version,
             server_service: identity.service,
-            _req: PhantomData,
-            _resp: PhantomData,
-            _error: PhantomData,
-        }))
+        })
     }
 
-    pub(crate) fn mgmt_transceive(addr: &SocketAddr, req: mgmt::Request) -> Option<mgmt::Response> {
-        let mut stream = TcpStream::connect_timeout(addr, time::Duration::from_secs(2))
-            .map_err(|err| {
-                log::error!("failed to open tcp port: {:?}", err);
-                err
-            })
-            .ok()?;
-        let read_timeout = Some(time::Duration::from_secs(2));
-        util::adjust_stream(&stream, read_timeout).ok()?;
-
-        Self::transceive_generic::<mgmt::Request, mgmt::Response, transport::Error>(
-            &mut stream,
-            req,
-        )
+    fn session(&self) -> Arc<Session> {
+        self.session.lock().expect("getting lock").clone()
     }
 
-    pub fn compatibility_check(&self, service: Service) -> bool {
-        let mut compatiblity = true;
-        let protocol_version = ProtocolVersion::entity().version();
+    // reestablishes the lost session according to the reconnect policy with the same connection id;
+    // callers which lost the same session concurrently wait for the reconnection and share the new session
+    fn reconnect(&self, lost: &Arc<Dispatcher>) -> Option<Arc<Session>> {
+        let policy = self.options.reconnect.as_ref()?;
+        let mut session = self.session.lock().expect("getting lock");
+        if !Arc::ptr_eq(&session.dispatcher, lost) {
+            return Some(session.clone());
+        }
+        // wakes up the callers which still wait for responses on the lost session
+        session.shutdown();
 
-        if protocol_version != self.server_protocol_version {
-            compatiblity = false;
-            log::error!(
-                "incompatible ETM versions detected! client on v{} and server on v{}!",
-                protocol_version,
-                self.server_protocol_version
-            );
+        let mut backoff = policy.initial_backoff;
+        for attempt in 1..=policy.max_attempts {
+            if attempt > 1 {
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(policy.max_backoff);
+            }
+            let resumed = Self::open(&self.addresses, session.id, &self.options)
+                .and_then(|resumed| Self::resume(&session, resumed));
+            match resumed {
+                Ok(resumed) => {
+                    log::info!("connection {} reestablished", resumed.id);
+                    *session = Arc::new(resumed);
+                    return Some(session.clone());
+                }
+                // the server may not have noticed the lost connection yet or may still be starting
+                Err(
+                    err @ (crate::Error::Io(_)
+                    | crate::Error::Timeout
+                    | crate::Error::ConnectionIdInUse(_)
+                    | crate::Error::ServerBusy(_)),
+                ) => {
+                    log::warn!(
+                        "attempt {} to reestablish connection {} failed: {}",
+                        attempt,
+                        session.id,
+                        err
+                    );
+                }
+                Err(err) => {
+                    log::error!("connection {} not reestablished: {}", session.id, err);
+                    return None;
+                }
+            }
         }
+        log::error!(
+            "connection {} not reestablished within {} attempts",
+            session.id,
+            policy.max_attempts
+        );
+        None
+    }
 
-        if service.id() != self.server_service.id() {
-            compatiblity = false;
-            log::error!(
-                "incompatible Services detected! client expects '{}' and server supplies '{}'!",
-                service.id(),
-                self.server_service.id()
-            );
+    // the reestablished connection must be served by the same service
+    fn resume(lost: &Session, resumed: Session) -> Result<Session, crate::Error<Error>> {
+        if !compatibility_check(
+            resumed.server_protocol_version,
+            &resumed.server_service,
+            &lost.server_service,
+        ) {
+            return Err(crate::Error::Protocol(
+                "the reestablished connection is served by an incompatible service".to_string(),
+            ));
         }
+        Ok(resumed)
+    }
 
-        if service.protocol_version() != self.server_service.protocol_version() {
-            compatiblity = false;
-            log::error!(
-                "incompatible ETM versions detected! client on v{} and server on v{}!",
-                service.protocol_version(),
-                self.server_service.protocol_version()
-            );
+    // starts an rpc on the current session; an io error means that the rpc was not sent because the
+    // connection is lost, then the rpc is started again on the reestablished session
+    fn start<T>(
+        &self,
+        start: impl Fn(&Session) -> Result<T, crate::Error<Error>>,
+    ) -> Result<T, crate::Error<Error>> {
+        let session = self.session();
+        match start(&session) {
+            Err(crate::Error::Io(err)) if self.options.reconnect.is_some() => {
+                log::warn!("connection {} lost: {}", session.id, err);
+                match self.reconnect(&session.dispatcher) {
+                    Some(session) => start(&session),
+                    None => Err(crate::Error::ConnectionLost {
+                        maybe_executed: false,
+                        reconnected: false,
+                    }),
+                }
+            }
+            result => result,
         }
+    }
 
-        compatiblity
+    // the connection id is kept when the connection is reestablished
+    pub fn id(&self) -> u32 {
+        self.session().id
     }
 
-    pub fn transceive(&mut self, request: Req) -> Option<Resp> {
-        Self::transceive_generic::<Req, Resp, Error>(&mut self.stream, request)
+    pub fn port(&self) -> u16 {
+        self.session().port
     }
 
-    fn transceive_generic<Rq, Rsp, E>(stream: &mut TcpStream, request: Rq) -> Option<Rsp>
-    where
-        Rq: Serialize,
-        Rsp: DeserializeOwned + std::fmt::Debug,
-        E: DeserializeOwned + std::fmt::Debug,
-    {
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
+    // the codec of the rpcs as negotiated with the server
+    pub fn format(&self) -> Format {
+        self.session().format
+    }
 
-        let transmission = transport::Transmission {
-            id: 42,
-            r#type: transport::Type::Request(request),
-        };
+    // the compression of the rpcs as chosen by the server; None if the transmissions are uncompressed
+    pub fn compression(&self) -> Option<Compression> {
+        self.session().compression
+    }
 
-        let transmission = serde
-            .serialize(&transmission)
-            .map_err(|err| {
-                log::error!("serializing request: {:?}", err);
-                err
-            })
-            .ok()?;
+    pub(crate) fn mgmt_transceive(
+        addr: &Address,
+        req: mgmt::Request,
+        options: &ConnectionOptions,
+    ) -> Result<mgmt::Response, crate::Error<transport::Error>> {
+        let mut stream = Self::mgmt_open(addr, options)?;
+        Self::mgmt_request(&mut stream, req)
+    }
 
-        let response = Self::send_receive(stream, transmission).ok()?;
+    // a host name may resolve to several addresses, e.g. to an IPv6 and an IPv4 address;
+    // the first address which answers the Identify request is used for the connection
+    fn mgmt_identify(
+        addrs: impl Iterator<Item = Address>,
+        protocol_version: u32,
+        options: &ConnectionOptions,
+    ) -> Result<(Address, mgmt::Response), crate::Error<transport::Error>> {
+        let mut last_err = None;
+        for addr in addrs {
+            let identify = mgmt::Request::Identify {
+                protocol_version,
+                formats: options.formats.clone(),
+            };
+            match Self::mgmt_transceive(&addr, identify, options) {
+                Ok(response) => return Ok((addr, response)),
+                Err(err @ (crate::Error::Io(_) | crate::Error::Timeout)) => {
+                    log::warn!("service management port {} unreachable: {}", addr, err);
+                    last_err = Some(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            crate::Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no address to connect to",
+            ))
+        }))
+    }
 
-        let response = serde
-            .deserialize::<transport::Transmission<Rsp>>(&response)
-            .map_err(|err| {
-                log::error!("deserializing response: {:?}", err);
-                err
-            })
-            .ok()?;
+    fn mgmt_open(
+        addr: &Address,
+        options: &ConnectionOptions,
+    ) -> Result<Stream, crate::Error<transport::Error>> {
+        let stream = addr.connect(options.connect_timeout).map_err(|err| {
+            log::error!("failed to open management port {}: {:?}", addr, err);
+            err
+        })?;
+        util::adjust_stream(&stream, Some(options.handshake_timeout))?;
+        Ok(secure(stream, options)?)
+    }
+
+    fn mgmt_request(
+        stream: &mut Stream,
+        req: mgmt::Request,
+    ) -> Result<mgmt::Response, crate::Error<transport::Error>> {
+        const MGMT_TRANSMISSION_ID: u64 = 0;
+        let transmission = serialize(
+            &codec::Bincode,
+            MGMT_TRANSMISSION_ID,
+            transport::Type::Request(req),
+        )?;
+        let response = Self::send_receive(stream, transmission)?;
+        decode_response::<mgmt::Response, transport::Error>(&codec::Bincode, &response)
+    }
 
-        match response.r#type {
-            transport::Type::Response(response) => Some(response),
-            transport::Type::Error(err) => {
-                log::error!("response: {:?}", err);
-                None
+    // answers the challenge of the server on the management connection, before the Connect request
+    fn mgmt_authenticate(
+        stream: &mut Stream,
+        credentials: &dyn Credentials,
+    ) -> Result<(), crate::Error<transport::Error>> {
+        let challenge = match Self::mgmt_request(stream, mgmt::Request::Authenticate)? {
+            mgmt::Response::Challenge(challenge) => challenge,
+            _ => {
+                log::error!("wrong response to Authenticate");
+                return Err(crate::Error::Protocol(
+                    "wrong response to Authenticate".to_string(),
+                ));
             }
-            unexpected @ _ => {
-                log::error!("unexpected response: {:?}", unexpected);
-                None
+        };
+        let request = mgmt::Request::Credentials(credentials.respond(&challenge));
+        match Self::mgmt_request(stream, request)? {
+            mgmt::Response::Authenticated => Ok(()),
+            mgmt::Response::AuthenticationFailed => {
+                log::error!("authentication failed");
+                Err(crate::Error::AuthenticationFailed)
+            }
+            _ => {
+                log::error!("wrong response to Credentials");
+                Err(crate::Error::Protocol(
+                    "wrong response to Credentials".to_string(),
+                ))
             }
         }
-        //TODO use map_or_else once feature is in stable rust
-        // response.map_or_else(|err| { log::error!("deserializing response: {:?}", err); None }, |response| { Some(response.r#type) } )
     }
 
-    fn send_receive(stream: &mut TcpStream, serialized: Vec<u8>) -> io::Result<Vec<u8>> {
-        util::write_transmission(stream, serialized)?;
-        let payload_size = util::wait_for_transmission(stream)?;
-        util::read_transmission(stream, payload_size)
+    // errors of the management protocol are not errors of the service, therefore a remote error is a protocol error
+    fn mgmt_error(err: crate::Error<transport::Error>) -> crate::Error<Error> {
+        err.map_remote(crate::Error::Protocol)
     }
-}
 
-impl<Req, Resp, Error> Drop for Connection<Req, Resp, Error>
-where
-    Req: Serialize,
-    Resp: DeserializeOwned + std::fmt::Debug,
-    Error: DeserializeOwned + std::fmt::Debug,
-{
-    fn drop(&mut self) {
-        log::info!("shutdown stream");
+    // true if the connection was lost and not reestablished; requests fail or reconnect, see ReconnectPolicy
+    pub fn is_closed(&self) -> bool {
+        self.session().dispatcher.is_closed()
+    }
 
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
+    // asks the management port of the server whether it is running, e.g. to check the health of an idle connection
+    pub fn check_run_state(&self) -> Result<(), crate::Error<Error>> {
+        let address = self.session().address.clone();
+        let response = Self::mgmt_transceive(&address, mgmt::Request::CheckRunState, &self.options)
+            .map_err(Self::mgmt_error)?;
+        match response {
+            mgmt::Response::CheckRunState => Ok(()),
+            _ => {
+                log::error!("wrong response to CheckRunState");
+                Err(crate::Error::Protocol(
+                    "wrong response to CheckRunState".to_string(),
+                ))
+            }
+        }
+    }
+
+    pub fn compatibility_check(&self, service: Service) -> bool {
+        let session = self.session();
+        compatibility_check(
+            session.server_protocol_version,
+            &session.server_service,
+            &service,
+        )
+    }
 
-        let transmission = transport::Transmission::<()> {
-            id: 42,
-            r#type: transport::Type::End,
+    // sends the request and waits for the response; other threads may have requests in flight on the same connection.
+    // with a reconnect policy a request which was not sent is sent on the reestablished connection, while the loss
+    // of a sent request is reported with Error::ConnectionLost
+    pub fn transceive(&self, request: Req) -> Result<Resp, crate::Error<Error>> {
+        let pending = self.send(request)?;
+        self.complete(pending, None)
+    }
+
+    // like transceive, but gives up with Error::Timeout at the deadline; the server sees the deadline in the
+    // server::Context and doesn't execute the request once the deadline passed
+    pub fn transceive_with_deadline(
+        &self,
+        request: Req,
+        deadline: Instant,
+    ) -> Result<Resp, crate::Error<Error>> {
+        let pending = self.send_with_deadline(request, deadline)?;
+        self.complete(pending, Some(deadline))
+    }
+
+    fn complete(
+        &self,
+        pending: PendingResponse<'_, Resp, Error>,
+        deadline: Option<Instant>,
+    ) -> Result<Resp, crate::Error<Error>> {
+        let dispatcher = pending.subscription.dispatcher.clone();
+        let response = match deadline {
+            Some(deadline) => pending.wait_until(deadline),
+            None => pending.wait(),
         };
+        match response {
+            Err(crate::Error::Io(err)) if self.options.reconnect.is_some() => {
+                log::warn!("connection lost while waiting for the response: {}", err);
+                Err(crate::Error::ConnectionLost {
+                    maybe_executed: true,
+                    reconnected: self.reconnect(&dispatcher).is_some(),
+                })
+            }
+            result => result,
+        }
+    }
+
+    // sends the request without waiting for the response; the response is matched by the transmission id
+    pub fn send(
+        &self,
+        request: Req,
+    ) -> Result<PendingResponse<'_, Resp, Error>, crate::Error<Error>> {
+        self.send_request(|| transport::Type::Request(&request))
+    }
+
+    // sends a Notification, which the server passes to MessageProcessing::on_notification without responding;
+    // Ok only means that the notification was sent
+    pub fn notify(&self, request: Req) -> Result<(), crate::Error<Error>> {
+        self.start(|session| {
+            let transmission = serialize(
+                &session.format,
+                session.next_transmission_id(),
+                transport::Type::Notification(&request),
+            )?;
+            session.write(transmission)?;
+            Ok(())
+        })
+    }
+
+    // like send, transmitting the time until the deadline to the server
+    pub fn send_with_deadline(
+        &self,
+        request: Req,
+        deadline: Instant,
+    ) -> Result<PendingResponse<'_, Resp, Error>, crate::Error<Error>> {
+        if deadline <= Instant::now() {
+            return Err(crate::Error::Timeout);
+        }
+        self.send_request(|| {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let remaining_ms = u64::try_from(remaining.as_millis()).unwrap_or(u64::MAX);
+            transport::Type::RequestWithDeadline(&request, remaining_ms)
+        })
+    }
+
+    fn send_request<'r>(
+        &self,
+        request: impl Fn() -> transport::Type<&'r Req>,
+    ) -> Result<PendingResponse<'_, Resp, Error>, crate::Error<Error>>
+    where
+        Req: 'r,
+    {
+        self.start(|session| {
+            let transmission_id = session.next_transmission_id();
+            let transmission = serialize(&session.format, transmission_id, request())?;
+
+            let pending = PendingResponse {
+                subscription: Subscription::new(session, transmission_id)?,
+                _resp: PhantomData,
+                _error: PhantomData,
+            };
 
-        let transmission = serde
-            .serialize(&transmission)
-            .map_err(|err| {
-                log::error!("serializing request: {:?}", err);
-                err
+            session.write(transmission)?;
+
+            Ok(pending)
+        })
+    }
+
+    // sends a stream request; the server responds with an arbitrary number of responses
+    pub fn transceive_stream(
+        &self,
+        request: Req,
+    ) -> Result<StreamingResponse<'_, Resp, Error>, crate::Error<Error>> {
+        self.start(|session| {
+            let transmission_id = session.next_transmission_id();
+            let transmission = serialize(
+                &session.format,
+                transmission_id,
+                transport::Type::StreamRequest(&request),
+            )?;
+            // the client has no further requests for this stream
+            let end =
+                serialize::<(), Error>(&session.format, transmission_id, transport::Type::End)?;
+
+            let streaming = StreamingResponse {
+                subscription: Subscription::new(session, transmission_id)?,
+                finished: false,
+                _resp: PhantomData,
+                _error: PhantomData,
+            };
+
+            let mut writer = session.writer.lock().expect("getting lock");
+            writer.write(transmission)?;
+            if let Err(err) = writer.write(end) {
+                // the request was sent, therefore the responses end with the connection error
+                log::error!("sending stream end: {:?}", err);
+                let _ = writer.stream.shutdown(Shutdown::Both);
+            }
+
+            Ok(streaming)
+        })
+    }
+
+    // opens a client stream; the server responds with a single response after the stream was finished
+    pub fn open_client_stream(
+        &self,
+    ) -> Result<ClientStream<'_, Req, Resp, Error>, crate::Error<Error>> {
+        self.start(|session| {
+            let transmission_id = session.next_transmission_id();
+            Ok(ClientStream {
+                subscription: Subscription::new(session, transmission_id)?,
+                sender: Self::stream_sender(session, transmission_id, false),
+                _resp: PhantomData,
             })
-            .unwrap();
+        })
+    }
 
-        if let Err(err) = util::write_transmission(&mut self.stream, transmission) {
-            log::error!("sending transmission end: {:?}", err);
+    // sends all requests as client stream and waits for the response
+    pub fn transceive_client_stream<I>(&self, requests: I) -> Result<Resp, crate::Error<Error>>
+    where
+        I: IntoIterator<Item = Req>,
+    {
+        let mut stream = self.open_client_stream()?;
+        for request in requests {
+            stream.send(request)?;
         }
+        stream.finish()
+    }
 
-        if let Err(err) = self.stream.shutdown(Shutdown::Both) {
-            log::error!("shutdown stream: {:?}", err);
+    // opens a bidirectional stream with the initial request; further requests and the responses are
+    // exchanged independently of each other until both sides sent the End transmission
+    pub fn open_bidi_stream(
+        &self,
+        request: Req,
+    ) -> Result<BidiStream<'_, Req, Resp, Error>, crate::Error<Error>> {
+        self.start(|session| {
+            let transmission_id = session.next_transmission_id();
+            let transmission = serialize(
+                &session.format,
+                transmission_id,
+                transport::Type::StreamRequest(&request),
+            )?;
+
+            let responses = StreamingResponse {
+                subscription: Subscription::new(session, transmission_id)?,
+                finished: false,
+                _resp: PhantomData,
+                _error: PhantomData,
+            };
+
+            session.write(transmission)?;
+
+            Ok(BidiStream {
+                sender: Self::stream_sender(session, transmission_id, true),
+                responses,
+            })
+        })
+    }
+
+    fn stream_sender<'a>(
+        session: &Session,
+        transmission_id: u64,
+        opened: bool,
+    ) -> StreamSender<'a, Req, Error> {
+        StreamSender {
+            transmission_id,
+            format: session.format,
+            writer: session.writer.clone(),
+            opened,
+            closed: false,
+            _connection: PhantomData,
+            _req: PhantomData,
+            _error: PhantomData,
         }
     }
+
+    fn send_receive(stream: &mut Stream, serialized: Vec<u8>) -> io::Result<Vec<u8>> {
+        util::write_transmission(stream, serialized, None)?;
+        let payload_size = util::wait_for_transmission(stream)?;
+        util::read_transmission(stream, payload_size, util::DEFAULT_MAX_FRAME_SIZE, None)
+    }
 }
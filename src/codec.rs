@@ -0,0 +1,290 @@
+// the serialization of the transmissions on the rpc connections; the management requests are always bincode
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use std::convert::TryInto;
+use std::fmt;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+pub trait Codec {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error>;
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error>;
+
+    // the id of an encoded transport::Transmission, also if the rest of it can't be decoded
+    fn transmission_id(&self, transmission: &[u8]) -> Option<u64> {
+        // default implementation for self-describing formats, the other fields are ignored
+        #[derive(Deserialize)]
+        struct Header {
+            id: u64,
+        }
+        self.decode::<Header>(transmission)
+            .ok()
+            .map(|header| header.id)
+    }
+}
+
+// big endian with fixed size integers; a transmission starts with the 8 byte transmission id
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+impl Codec for Bincode {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        Ok(bincode_options().serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        Ok(bincode_options().deserialize(bytes)?)
+    }
+
+    fn transmission_id(&self, transmission: &[u8]) -> Option<u64> {
+        let id = transmission.get(..8)?;
+        Some(u64::from_be_bytes(id.try_into().ok()?))
+    }
+}
+
+fn bincode_options() -> impl Options + Copy {
+    bincode::DefaultOptions::new()
+        .with_big_endian()
+        .with_fixint_encoding()
+}
+
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        Ok(serde_cbor::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        Ok(serde_cbor::from_slice(bytes)?)
+    }
+}
+
+// structs are encoded as maps with the field names, like in the other self-describing formats
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        Ok(rmp_serde::to_vec_named(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+// the codec of a connection as negotiated with Identify; the formats exist regardless of the enabled features,
+// so that the peers can tell which ones the other side supports
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Format {
+    #[default]
+    Bincode,
+    Json,
+    Cbor,
+    MessagePack,
+}
+
+impl Format {
+    // the formats enabled by the crate features, Bincode first
+    pub fn supported() -> Vec<Format> {
+        [
+            Format::Bincode,
+            Format::Json,
+            Format::Cbor,
+            Format::MessagePack,
+        ]
+        .iter()
+        .copied()
+        .filter(|format| format.is_supported())
+        .collect()
+    }
+
+    pub fn is_supported(self) -> bool {
+        match self {
+            Format::Bincode => true,
+            Format::Json => cfg!(feature = "json"),
+            Format::Cbor => cfg!(feature = "cbor"),
+            Format::MessagePack => cfg!(feature = "msgpack"),
+        }
+    }
+
+    // the first of the preferred formats which the peer offers
+    pub(crate) fn negotiate(preferred: &[Format], offered: &[Format]) -> Option<Format> {
+        preferred
+            .iter()
+            .copied()
+            .find(|format| format.is_supported() && offered.contains(format))
+    }
+
+    fn unsupported(self) -> Error {
+        format!("codec {} is not enabled", self).into()
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Bincode => write!(f, "bincode"),
+            Format::Json => write!(f, "json"),
+            Format::Cbor => write!(f, "cbor"),
+            Format::MessagePack => write!(f, "messagepack"),
+        }
+    }
+}
+
+impl Codec for Format {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        match self {
+            Format::Bincode => Bincode.encode(value),
+            #[cfg(feature = "json")]
+            Format::Json => Json.encode(value),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => Cbor.encode(value),
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => MessagePack.encode(value),
+            #[allow(unreachable_patterns)]
+            format => Err(format.unsupported()),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        match self {
+            Format::Bincode => Bincode.decode(bytes),
+            #[cfg(feature = "json")]
+            Format::Json => Json.decode(bytes),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => Cbor.decode(bytes),
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => MessagePack.decode(bytes),
+            #[allow(unreachable_patterns)]
+            format => Err(format.unsupported()),
+        }
+    }
+
+    fn transmission_id(&self, transmission: &[u8]) -> Option<u64> {
+        match self {
+            Format::Bincode => Bincode.transmission_id(transmission),
+            #[cfg(feature = "json")]
+            Format::Json => Json.transmission_id(transmission),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => Cbor.transmission_id(transmission),
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => MessagePack.transmission_id(transmission),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{Transmission, Type};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum TestType {
+        U8(u8),
+        String(String),
+        Vec(Vec<u8>),
+    }
+
+    #[test]
+    fn round_trip() {
+        for format in Format::supported() {
+            let transmission = Transmission::<TestType> {
+                id: 0x42,
+                r#type: Type::Request(TestType::String("etm".to_string())),
+            };
+            let encoded = format.encode(&transmission).unwrap();
+            assert_eq!(format.transmission_id(&encoded), Some(0x42), "{}", format);
+            let decoded = format.decode::<Transmission<TestType>>(&encoded).unwrap();
+            assert_eq!(decoded, transmission, "{}", format);
+
+            let transmission = Transmission::<TestType> {
+                id: 7,
+                r#type: Type::KeepAlive,
+            };
+            let encoded = format.encode(&transmission).unwrap();
+            let decoded = format.decode::<Transmission<TestType>>(&encoded).unwrap();
+            assert_eq!(decoded, transmission, "{}", format);
+        }
+    }
+
+    #[test]
+    fn transmission_id_of_undecodable_payload() {
+        let encoded = Bincode
+            .encode(&Transmission::<TestType> {
+                id: 0x13,
+                r#type: Type::Request(TestType::Vec(vec![1, 2, 3])),
+            })
+            .unwrap();
+        assert!(Bincode.decode::<Transmission<u64>>(&encoded).is_err());
+        assert_eq!(Bincode.transmission_id(&encoded), Some(0x13));
+        assert_eq!(Bincode.transmission_id(&encoded[..7]), None);
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(Format::supported().first(), Some(&Format::Bincode));
+        assert_eq!(
+            Format::negotiate(&[Format::Bincode], &Format::supported()),
+            Some(Format::Bincode)
+        );
+        assert_eq!(Format::negotiate(&[Format::Bincode], &[]), None);
+        // a format which is not compiled in is never chosen
+        let offered = [
+            Format::Json,
+            Format::Cbor,
+            Format::MessagePack,
+            Format::Bincode,
+        ];
+        let preferred = [
+            Format::Json,
+            Format::Cbor,
+            Format::MessagePack,
+            Format::Bincode,
+        ];
+        let negotiated = Format::negotiate(&preferred, &offered);
+        assert!(negotiated.is_some_and(Format::is_supported));
+        if !cfg!(any(feature = "json", feature = "cbor", feature = "msgpack")) {
+            assert_eq!(negotiated, Some(Format::Bincode));
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_is_readable() {
+        let transmission = Transmission::<TestType> {
+            id: 1,
+            r#type: Type::Response(TestType::U8(2)),
+        };
+        let encoded = Json.encode(&transmission).unwrap();
+        assert_eq!(
+            String::from_utf8(encoded).unwrap(),
+            r#"{"id":1,"type":{"Response":{"U8":2}}}"#
+        );
+    }
+}
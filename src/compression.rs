@@ -0,0 +1,252 @@
+// the compression of the transmissions on the rpc connections, negotiated with Connect
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::io;
+#[cfg(any(feature = "zstd", feature = "lz4"))]
+use std::io::prelude::*;
+
+// the highest bit of the length prefix flags a compressed payload; a peer which doesn't know the flag
+// rejects the transmission because it exceeds the maximum frame size
+pub(crate) const COMPRESSED: u64 = 1 << 63;
+
+// transmissions with a smaller payload are sent uncompressed unless configured otherwise
+pub const DEFAULT_COMPRESSION_THRESHOLD: u64 = 1024;
+
+// the algorithms exist regardless of the enabled features, so that the peers can tell which ones
+// the other side supports
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Algorithm {
+    Zstd,
+    Lz4,
+}
+
+impl Algorithm {
+    // the algorithms enabled by the crate features
+    pub fn supported() -> Vec<Algorithm> {
+        [Algorithm::Zstd, Algorithm::Lz4]
+            .iter()
+            .copied()
+            .filter(|algorithm| algorithm.is_supported())
+            .collect()
+    }
+
+    pub fn is_supported(self) -> bool {
+        match self {
+            Algorithm::Zstd => cfg!(feature = "zstd"),
+            Algorithm::Lz4 => cfg!(feature = "lz4"),
+        }
+    }
+
+    // the first of the preferred algorithms which the peer offers
+    pub(crate) fn negotiate(preferred: &[Algorithm], offered: &[Algorithm]) -> Option<Algorithm> {
+        preferred
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.is_supported() && offered.contains(algorithm))
+    }
+
+    fn compress(self, serialized: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => zstd::bulk::compress(serialized, 0),
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(serialized)?;
+                encoder.finish().map_err(io::Error::other)
+            }
+            #[allow(unreachable_patterns)]
+            algorithm => {
+                let _ = serialized;
+                Err(algorithm.unsupported())
+            }
+        }
+    }
+
+    fn decompress(self, payload: &[u8], max_frame_size: u64) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => read_bounded(zstd::stream::Decoder::new(payload)?, max_frame_size),
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4 => {
+                read_bounded(lz4_flex::frame::FrameDecoder::new(payload), max_frame_size)
+            }
+            #[allow(unreachable_patterns)]
+            algorithm => {
+                let _ = (payload, max_frame_size);
+                Err(algorithm.unsupported())
+            }
+        }
+    }
+
+    fn unsupported(self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("compression {} is not enabled", self),
+        )
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::Zstd => write!(f, "zstd"),
+            Algorithm::Lz4 => write!(f, "lz4"),
+        }
+    }
+}
+
+// the compression of a connection as chosen by the server; both sides compress the payloads
+// from the threshold on
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Compression {
+    pub algorithm: Algorithm,
+    pub threshold: u64,
+}
+
+// the length prefix and the payload of a transmission; a payload is sent uncompressed if it is below the
+// threshold or if compressing doesn't make it smaller
+pub(crate) fn compress(
+    compression: Option<Compression>,
+    serialized: Vec<u8>,
+) -> io::Result<(u64, Vec<u8>)> {
+    let uncompressed = serialized.len() as u64;
+    match compression {
+        Some(compression) if uncompressed >= compression.threshold => {
+            let compressed = compression.algorithm.compress(&serialized)?;
+            if (compressed.len() as u64) < uncompressed {
+                Ok((compressed.len() as u64 | COMPRESSED, compressed))
+            } else {
+                Ok((uncompressed, serialized))
+            }
+        }
+        _ => Ok((uncompressed, serialized)),
+    }
+}
+
+// the size of the payload and its compression if it is compressed; the flag is only interpreted on a
+// compressed connection, otherwise the length prefix exceeds the maximum frame size like on an old peer
+pub(crate) fn payload_size(
+    length_prefix: u64,
+    compression: Option<Compression>,
+) -> (u64, Option<Compression>) {
+    match compression {
+        Some(compression) if length_prefix & COMPRESSED != 0 => {
+            (length_prefix & !COMPRESSED, Some(compression))
+        }
+        _ => (length_prefix, None),
+    }
+}
+
+// the decompressed payload must not exceed the maximum frame size either
+pub(crate) fn decompress(
+    compression: Compression,
+    payload: &[u8],
+    max_frame_size: u64,
+) -> io::Result<Vec<u8>> {
+    compression
+        .algorithm
+        .decompress(payload, max_frame_size)
+        .map_err(|err| {
+            log::error!("decompressing transmission: {:?}", err);
+            err
+        })
+}
+
+#[cfg(any(feature = "zstd", feature = "lz4"))]
+fn read_bounded(decoder: impl Read, max_frame_size: u64) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    decoder
+        .take(max_frame_size.saturating_add(1))
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() as u64 > max_frame_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "decompressed transmission exceeds the maximum frame size {}",
+                max_frame_size
+            ),
+        ));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threshold() {
+        for algorithm in Algorithm::supported() {
+            let compression = Compression {
+                algorithm,
+                threshold: 64,
+            };
+            let (length_prefix, payload) = compress(Some(compression), vec![7u8; 63]).unwrap();
+            assert_eq!(
+                payload_size(length_prefix, Some(compression)),
+                (63, None),
+                "{}",
+                algorithm
+            );
+            assert_eq!(payload, vec![7u8; 63]);
+
+            let (length_prefix, payload) = compress(Some(compression), vec![7u8; 4096]).unwrap();
+            let (size, compressed) = payload_size(length_prefix, Some(compression));
+            assert_eq!(compressed, Some(compression), "{}", algorithm);
+            assert_eq!(size, payload.len() as u64);
+            assert!(size < 4096);
+            assert_eq!(
+                decompress(compression, &payload, 4096).unwrap(),
+                vec![7u8; 4096]
+            );
+            // a compressed payload which exceeds the maximum frame size when decompressed
+            assert!(decompress(compression, &payload, 4095).is_err());
+        }
+    }
+
+    #[test]
+    fn incompressible_payload() {
+        for algorithm in Algorithm::supported() {
+            let compression = Compression {
+                algorithm,
+                threshold: 0,
+            };
+            let serialized = (0..=255u8).collect::<Vec<_>>();
+            let (length_prefix, payload) = compress(Some(compression), serialized.clone()).unwrap();
+            assert_eq!(
+                payload_size(length_prefix, Some(compression)),
+                (256, None),
+                "{}",
+                algorithm
+            );
+            assert_eq!(payload, serialized);
+        }
+    }
+
+    #[test]
+    fn without_compression() {
+        let (length_prefix, payload) = compress(None, vec![7u8; 4096]).unwrap();
+        assert_eq!(length_prefix, 4096);
+        assert_eq!(payload.len(), 4096);
+        // a connection without compression sees a length prefix beyond any maximum frame size, like an old peer
+        assert_eq!(
+            payload_size(4096 | COMPRESSED, None),
+            (4096 | COMPRESSED, None)
+        );
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(Algorithm::negotiate(&[], &Algorithm::supported()), None);
+        assert_eq!(
+            Algorithm::negotiate(&[Algorithm::Zstd, Algorithm::Lz4], &[Algorithm::Lz4]),
+            Some(Algorithm::Lz4).filter(|algorithm| algorithm.is_supported())
+        );
+        assert_eq!(
+            Algorithm::negotiate(&Algorithm::supported(), &Algorithm::supported()),
+            Algorithm::supported().first().copied()
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use crate::codec;
+use crate::mgmt;
+use crate::transport;
+
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    // the underlying stream failed, e.g. server unreachable or connection reset
+    Io(io::Error),
+    // serializing a request or deserializing a response failed
+    Codec(Box<dyn std::error::Error + Send + Sync>),
+    // the peer violated the etm protocol, e.g. wrong response type or service mismatch
+    Protocol(String),
+    // the server side MessageProcessing::execute returned an error
+    Remote(E),
+    // no response within the read timeout of the stream
+    Timeout,
+    // the server has already an open connection with the requested connection id
+    ConnectionIdInUse(u32),
+    // the server refused the connection because a connection limit is reached
+    ServerBusy(mgmt::ConnectionLimit),
+    // the server rejected a transmission which violates the etm protocol
+    Rejected(transport::Fault),
+    // the server rejected the credentials or requires them
+    AuthenticationFailed,
+    // the connection was lost during the request, see client::ReconnectPolicy; an unsent request was not
+    // executed, a sent one may have been executed before the connection was lost
+    ConnectionLost {
+        maybe_executed: bool,
+        // further requests can be sent on the reestablished connection
+        reconnected: bool,
+    },
+    // the server confirmed the cancellation of the request, see client::PendingResponse::cancel
+    Cancelled,
+}
+
+impl<E> Error<E> {
+    pub fn is_remote(&self) -> bool {
+        matches!(self, Error::Remote(_))
+    }
+
+    // converts the remote error type; used when e.g. a management error must be reported on an rpc error type
+    pub(crate) fn map_remote<F>(self, op: impl FnOnce(E) -> Error<F>) -> Error<F> {
+        match self {
+            Error::Io(err) => Error::Io(err),
+            Error::Codec(err) => Error::Codec(err),
+            Error::Protocol(msg) => Error::Protocol(msg),
+            Error::Remote(err) => op(err),
+            Error::Timeout => Error::Timeout,
+            Error::ConnectionIdInUse(id) => Error::ConnectionIdInUse(id),
+            Error::ServerBusy(limit) => Error::ServerBusy(limit),
+            Error::Rejected(fault) => Error::Rejected(fault),
+            Error::AuthenticationFailed => Error::AuthenticationFailed,
+            Error::ConnectionLost {
+                maybe_executed,
+                reconnected,
+            } => Error::ConnectionLost {
+                maybe_executed,
+                reconnected,
+            },
+            Error::Cancelled => Error::Cancelled,
+        }
+    }
+}
+
+impl<E> From<io::Error> for Error<E> {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout,
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl<E> From<bincode::Error> for Error<E> {
+    fn from(err: bincode::Error) -> Self {
+        Error::Codec(err)
+    }
+}
+
+impl<E> From<codec::Error> for Error<E> {
+    fn from(err: codec::Error) -> Self {
+        Error::Codec(err)
+    }
+}
+
+impl<E: fmt::Debug> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Codec(err) => write!(f, "codec error: {}", err),
+            Error::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            Error::Remote(err) => write!(f, "remote error: {:?}", err),
+            Error::Timeout => write!(f, "timeout"),
+            Error::ConnectionIdInUse(id) => write!(f, "connection id {} already in use", id),
+            Error::ServerBusy(limit) => write!(f, "server busy: {}", limit),
+            Error::Rejected(fault) => write!(f, "rejected by the server: {}", fault),
+            Error::AuthenticationFailed => write!(f, "authentication failed"),
+            Error::ConnectionLost {
+                maybe_executed,
+                reconnected,
+            } => write!(
+                f,
+                "connection lost, the request {} executed and the connection {} reestablished",
+                if *maybe_executed {
+                    "may have been"
+                } else {
+                    "was not"
+                },
+                if *reconnected { "was" } else { "was not" }
+            ),
+            Error::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl<E: fmt::Debug> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Codec(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_timeout_is_mapped_to_timeout() {
+        let err = Error::<String>::from(io::Error::new(io::ErrorKind::WouldBlock, "read"));
+        assert!(matches!(err, Error::Timeout));
+        let err = Error::<String>::from(io::Error::new(io::ErrorKind::TimedOut, "read"));
+        assert!(matches!(err, Error::Timeout));
+        let err = Error::<String>::from(io::Error::new(io::ErrorKind::ConnectionReset, "read"));
+        assert!(matches!(err, Error::Io(_)));
+    }
+
+    #[test]
+    fn map_remote_keeps_other_variants() {
+        let err = Error::<String>::Protocol("wrong response".to_string());
+        let err = err.map_remote(Error::<u32>::Protocol);
+        assert!(matches!(err, Error::Protocol(msg) if msg == "wrong response"));
+
+        let err = Error::<String>::Remote("busy".to_string());
+        let err = err.map_remote(Error::<u32>::Protocol);
+        assert!(matches!(err, Error::Protocol(msg) if msg == "busy"));
+    }
+}
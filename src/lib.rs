@@ -1,16 +1,43 @@
+pub mod auth;
 pub mod client;
+pub mod codec;
+pub mod compression;
+pub mod pool;
 pub mod server;
 pub mod transport;
 
+#[cfg(feature = "tokio")]
+pub mod async_client;
+#[cfg(feature = "tokio")]
+pub mod async_server;
+
+mod error;
 mod mgmt;
+mod multiplexer;
+mod stream;
+#[cfg(feature = "tls")]
+mod tls;
 mod util;
 
+#[cfg(feature = "tokio")]
+mod async_stream;
+#[cfg(feature = "tokio")]
+mod async_util;
+
+#[cfg(all(test, feature = "tokio"))]
+mod async_test;
 #[cfg(test)]
 mod test;
 
 use serde::{Deserialize, Serialize};
 
+pub use error::Error;
+pub use mgmt::ConnectionLimit;
 pub use util::listener_accept_nonblocking;
+pub use util::DEFAULT_MAX_FRAME_SIZE;
+
+#[cfg(feature = "tls")]
+pub use rustls;
 
 pub struct ProtocolVersion {
     version: u32,
@@ -18,7 +45,7 @@ pub struct ProtocolVersion {
 
 impl ProtocolVersion {
     pub fn entity() -> Self {
-        ProtocolVersion { version: 0 }
+        ProtocolVersion { version: 4 }
     }
 
     pub fn version(&self) -> u32 {
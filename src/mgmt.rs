@@ -1,31 +1,108 @@
+use crate::codec::Format;
+use crate::compression::{Algorithm, Compression};
 use crate::Service;
 
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io;
+use std::time::Duration;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct Identity {
     pub protocol_version: u32,
     pub service: Service,
+    pub format: Option<Format>, // codec for the rpcs, None if the server enables none of the offered formats
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct CommParams {
     pub protocol_version: u32,
-    pub connection_id: u32,           // -1 dynamic
-    pub rpc_interval_timeout_ms: u32, // -1 infinite
+    pub connection_id: u32,           // -1 dynamic, see DYNAMIC_CONNECTION_ID
+    pub rpc_interval_timeout_ms: u32, // -1 infinite, see INFINITE_RPC_INTERVAL_TIMEOUT
+    pub format: Format,               // codec for the rpcs, as negotiated with Identify
+    pub compression: Vec<Algorithm>,  // the compression algorithms the client supports
+}
+
+// requests the server to assign an unused connection id
+pub const DYNAMIC_CONNECTION_ID: u32 = u32::MAX;
+
+// the server never closes an idle connection
+pub const INFINITE_RPC_INTERVAL_TIMEOUT: u32 = u32::MAX;
+
+pub fn rpc_interval_timeout(rpc_interval_timeout_ms: u32) -> Option<Duration> {
+    match rpc_interval_timeout_ms {
+        INFINITE_RPC_INTERVAL_TIMEOUT => None,
+        // a zero read timeout is not supported by the tcp stream
+        timeout_ms => Some(Duration::from_millis(u64::from(timeout_ms.max(1)))),
+    }
+}
+
+pub fn rpc_interval_timeout_ms(rpc_interval_timeout: Option<Duration>) -> u32 {
+    rpc_interval_timeout
+        .map(|timeout| {
+            u32::try_from(timeout.as_millis()).unwrap_or(INFINITE_RPC_INTERVAL_TIMEOUT - 1)
+        })
+        .unwrap_or(INFINITE_RPC_INTERVAL_TIMEOUT)
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct CommSettings {
     pub connection_id: u32, // assigned connection id
-    pub port: u16,
+    pub endpoint: RpcEndpoint,
+    pub compression: Option<Compression>, // chosen from the offered algorithms, None is uncompressed
+    pub rpc_token: RpcToken,              // sent first on the connection to a Port or Path endpoint
+}
+
+pub const RPC_TOKEN_SIZE: usize = 16;
+
+// binds the connection to the assigned rpc endpoint to the client which negotiated it, e.g. after the
+// authentication; the server only serves the connection which sends the token before its first transmission
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct RpcToken(pub [u8; RPC_TOKEN_SIZE]);
+
+impl RpcToken {
+    pub(crate) fn generate() -> io::Result<Self> {
+        let mut token = [0u8; RPC_TOKEN_SIZE];
+        getrandom::getrandom(&mut token)?;
+        Ok(RpcToken(token))
+    }
+}
+
+// the comparison takes the same time for every token, so that it doesn't reveal the matching prefix
+impl PartialEq for RpcToken {
+    fn eq(&self, other: &Self) -> bool {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+    }
+}
+
+// where the client opens the rpc connection
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum RpcEndpoint {
+    // the client keeps using the management connection for the rpcs
+    Management,
+    // a tcp port on the ip address of the management port
+    Port(u16),
+    // the path of a unix domain socket
+    Path(String),
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub enum Request {
-    Identify { protocol_version: u32 },
+    // formats are the codecs the client supports for the rpcs
+    Identify {
+        protocol_version: u32,
+        formats: Vec<Format>,
+    },
     Connect(CommParams),
     CheckRunState,
+    // starts the authentication on the management connection; answered with a Challenge
+    Authenticate,
+    // the answer to the Challenge, followed by Connect on the same connection
+    Credentials(Vec<u8>),
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -33,4 +110,46 @@ pub enum Response {
     Identify(Identity),
     Connect(CommSettings),
     CheckRunState,
+    ConnectionIdInUse(u32), // response to Connect if the requested connection id is already in use
+    ServerBusy(ConnectionLimit), // response to Connect if the server doesn't accept further connections
+    Challenge(Vec<u8>),          // response to Authenticate
+    Authenticated,               // response to Credentials if the server accepted them
+    AuthenticationFailed, // response to Credentials, or to Connect if the server requires authentication
+    UnsupportedFormat(Format), // response to Connect if the server doesn't enable the requested codec
+}
+
+// the Identify request and response of protocol version 0, before the codec negotiation; the server answers
+// the Identify of such a client in this layout, so that the client sees the mismatch of the protocol versions
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) enum LegacyRequest {
+    Identify { protocol_version: u32 },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) enum LegacyResponse {
+    Identify(LegacyIdentity),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) struct LegacyIdentity {
+    pub(crate) protocol_version: u32,
+    pub(crate) service: Service,
+}
+
+// the connection limit of the server which was reached
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ConnectionLimit {
+    // the maximum number of open connections of the server
+    Server(u32),
+    // the maximum number of open connections from the ip address of the client
+    Peer(u32),
+}
+
+impl std::fmt::Display for ConnectionLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionLimit::Server(max) => write!(f, "maximum of {} connections", max),
+            ConnectionLimit::Peer(max) => write!(f, "maximum of {} connections per peer", max),
+        }
+    }
 }
@@ -0,0 +1,819 @@
+// serves the rpc connections of a server with a single polling thread and a fixed number of worker threads
+use crate::compression;
+use crate::mgmt;
+use crate::server::{
+    decode_request, ConnectionState, Endpoint, MessageProcessing, Peer, ReceiveError, RpcChannel,
+    ServerOptions, Session, TransceiveLoopAction,
+};
+use crate::stream::{Listener, Stream};
+use crate::transport;
+use crate::util;
+
+use mio::event::Source;
+use mio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use mio::net::{UnixListener, UnixStream};
+use mio::{Events, Interest, Poll, Token, Waker};
+use serde::{de::DeserializeOwned, Serialize};
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+use std::io::Read;
+use std::net;
+#[cfg(unix)]
+use std::os::unix;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// a fixed number of threads executing the jobs in the order of submission
+pub(crate) struct WorkerPool {
+    sender: Mutex<Option<mpsc::Sender<Job>>>,
+    workers: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+impl WorkerPool {
+    pub(crate) fn new(workers: usize) -> Arc<Self> {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || loop {
+                    let job = receiver.lock().expect("getting lock").recv();
+                    match job {
+                        Ok(job) => {
+                            // a panicking job must not reduce the number of workers
+                            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                                log::error!("worker job panicked");
+                            }
+                        }
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        Arc::new(WorkerPool {
+            sender: Mutex::new(Some(sender)),
+            workers: Mutex::new(workers),
+        })
+    }
+
+    // after the pool is stopped, the job is executed on the calling thread
+    pub(crate) fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        let sender = self.sender.lock().expect("getting lock").clone();
+        let job: Job = Box::new(job);
+        match sender {
+            Some(sender) => {
+                if let Err(mpsc::SendError(job)) = sender.send(job) {
+                    job();
+                }
+            }
+            None => job(),
+        }
+    }
+
+    // executes the queued jobs and stops the workers
+    pub(crate) fn join(&self) {
+        self.sender.lock().expect("getting lock").take();
+        let workers = std::mem::take(&mut *self.workers.lock().expect("getting lock"));
+        for worker in workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+const WAKER: Token = Token(0);
+// the time the client has to connect to the assigned port, like in the thread per connection mode
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(2);
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+enum Command {
+    Register(RpcChannel, Arc<ConnectionState>),
+    Stop,
+}
+
+pub(crate) struct Multiplexer {
+    sender: mpsc::Sender<Command>,
+    waker: Waker,
+    poller: Option<thread::JoinHandle<()>>,
+    pool: Arc<WorkerPool>,
+}
+
+impl Multiplexer {
+    pub(crate) fn start<Req, Resp, Error, T>(
+        message_processing: Arc<T>,
+        options: ServerOptions,
+        workers: usize,
+    ) -> io::Result<Self>
+    where
+        Req: DeserializeOwned + Send + 'static,
+        Resp: Serialize + 'static,
+        Error: Serialize + std::fmt::Debug + 'static,
+        T: 'static + MessageProcessing<Rq = Req, Rsp = Resp, E = Error>,
+    {
+        let poll = Poll::new()?;
+        let waker = Waker::new(poll.registry(), WAKER)?;
+        let (sender, receiver) = mpsc::channel();
+        let pool = WorkerPool::new(workers);
+        let poller = Poller {
+            poll,
+            receiver,
+            message_processing,
+            options,
+            pool: pool.clone(),
+            connections: HashMap::new(),
+            next_token: WAKER.0 + 1,
+            chunk: vec![0u8; READ_CHUNK_SIZE],
+        };
+        Ok(Multiplexer {
+            sender,
+            waker,
+            poller: Some(thread::spawn(move || poller.run())),
+            pool,
+        })
+    }
+
+    pub(crate) fn register(
+        &self,
+        channel: RpcChannel,
+        state: Arc<ConnectionState>,
+    ) -> io::Result<()> {
+        self.sender
+            .send(Command::Register(channel, state))
+            .map_err(|_| io::Error::new(io::ErrorKind::NotConnected, "multiplexer stopped"))?;
+        self.waker.wake()
+    }
+
+    // closes all connections and waits for the outstanding executions
+    pub(crate) fn stop(mut self) {
+        let _ = self.sender.send(Command::Stop);
+        let _ = self
+            .waker
+            .wake()
+            .map_err(|err| log::error!("waking multiplexer: {:?}", err));
+        if let Some(poller) = self.poller.take() {
+            let _ = poller.join();
+        }
+        self.pool.join();
+    }
+}
+
+// the mio counterpart of stream::Listener
+enum PolledListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        // removes the socket file when the listener is dropped
+        _socket_file: crate::stream::SocketFile,
+    },
+}
+
+impl PolledListener {
+    fn from_std(listener: Listener) -> io::Result<Self> {
+        match listener {
+            Listener::Tcp(listener) => {
+                listener.set_nonblocking(true)?;
+                Ok(PolledListener::Tcp(TcpListener::from_std(listener)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, socket_file) => {
+                listener.set_nonblocking(true)?;
+                Ok(PolledListener::Unix {
+                    listener: UnixListener::from_std(listener),
+                    _socket_file: socket_file,
+                })
+            }
+        }
+    }
+
+    // the accepted stream is blocking
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            PolledListener::Tcp(listener) => listener
+                .accept()
+                .map(|(stream, _)| Stream::Tcp(net::TcpStream::from(stream))),
+            #[cfg(unix)]
+            PolledListener::Unix { listener, .. } => listener
+                .accept()
+                .map(|(stream, _)| Stream::Unix(unix::net::UnixStream::from(stream))),
+        }
+    }
+
+    fn source(&mut self) -> &mut dyn Source {
+        match self {
+            PolledListener::Tcp(listener) => listener,
+            #[cfg(unix)]
+            PolledListener::Unix { listener, .. } => listener,
+        }
+    }
+}
+
+// the mio counterpart of stream::Stream; only registers the socket, the stream is read through the stream::Stream
+#[derive(Debug)]
+enum PolledStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl PolledStream {
+    fn from_std(stream: &Stream) -> io::Result<Self> {
+        match stream {
+            Stream::Tcp(stream) => Ok(PolledStream::Tcp(TcpStream::from_std(stream.try_clone()?))),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Ok(PolledStream::Unix(UnixStream::from_std(
+                stream.try_clone()?,
+            ))),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => PolledStream::from_std(stream.socket()),
+        }
+    }
+
+    fn source(&mut self) -> &mut dyn Source {
+        match self {
+            PolledStream::Tcp(stream) => stream,
+            #[cfg(unix)]
+            PolledStream::Unix(stream) => stream,
+        }
+    }
+}
+
+// waits until a nonblocking stream can be written again, e.g. after the send buffer was full;
+// the polling thread only polls for readable streams, therefore the writer of a connection registers the stream once on its own
+#[derive(Debug)]
+pub(crate) struct Writability {
+    poll: Poll,
+    events: Events,
+    _source: PolledStream,
+}
+
+impl Writability {
+    pub(crate) fn new(stream: &Stream) -> io::Result<Self> {
+        let poll = Poll::new()?;
+        let mut source = PolledStream::from_std(stream)?;
+        poll.registry()
+            .register(source.source(), WAKER, Interest::WRITABLE)?;
+        Ok(Writability {
+            poll,
+            events: Events::with_capacity(1),
+            _source: source,
+        })
+    }
+
+    // the readiness is edge triggered, a stale event only causes another attempt to write
+    pub(crate) fn wait(&mut self) -> io::Result<()> {
+        loop {
+            match self.poll.poll(&mut self.events, None) {
+                Ok(()) if !self.events.is_empty() => return Ok(()),
+                Ok(()) => (),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+// a connection to the assigned port which is not yet served, i.e. during the tls handshake or before the rpc token
+struct Candidate {
+    // the plain socket until the tls handshake is finished
+    stream: Stream,
+    source: PolledStream,
+    #[cfg(feature = "tls")]
+    handshake: Option<rustls::Connection>,
+    // the first bytes of the rpc token which are already received
+    rpc_token: mgmt::RpcToken,
+    received: usize,
+    deadline: Instant,
+}
+
+impl Candidate {
+    fn new(stream: Stream, options: &ServerOptions, poll: &Poll, token: Token) -> io::Result<Self> {
+        #[cfg(feature = "tls")]
+        let handshake = match &options.tls {
+            Some(config) => Some(crate::tls::server_connection(config.clone())?),
+            None => None,
+        };
+        #[cfg(not(feature = "tls"))]
+        let _ = options;
+        stream.set_nonblocking(true)?;
+        let mut source = PolledStream::from_std(&stream)?;
+        // the handshake shares the token of the listener
+        poll.registry().register(
+            source.source(),
+            token,
+            Interest::READABLE | Interest::WRITABLE,
+        )?;
+        Ok(Candidate {
+            stream,
+            source,
+            #[cfg(feature = "tls")]
+            handshake,
+            rpc_token: mgmt::RpcToken([0u8; mgmt::RPC_TOKEN_SIZE]),
+            received: 0,
+            deadline: Instant::now() + ACCEPT_TIMEOUT,
+        })
+    }
+
+    // returns true when the connection can be served; the data after the rpc token is left for the connection
+    fn advance(&mut self, rpc_token: &mgmt::RpcToken) -> io::Result<bool> {
+        #[cfg(feature = "tls")]
+        if let Some(mut connection) = self.handshake.take() {
+            if !crate::tls::advance_handshake(&mut connection, &self.stream)? {
+                self.handshake = Some(connection);
+                return Ok(false);
+            }
+            self.stream = crate::tls::established(self.stream.try_clone()?, connection);
+        }
+        while self.received < mgmt::RPC_TOKEN_SIZE {
+            match (&self.stream).read(&mut self.rpc_token.0[self.received..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before the rpc token",
+                    ))
+                }
+                Ok(size) => self.received += size,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        if self.rpc_token != *rpc_token {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "invalid rpc token",
+            ));
+        }
+        Ok(true)
+    }
+}
+
+enum Polled<T: 'static + MessageProcessing> {
+    // waiting for the client to connect to the assigned port
+    Listening {
+        listener: PolledListener,
+        rpc_token: mgmt::RpcToken,
+        candidates: Vec<Candidate>,
+        state: Arc<ConnectionState>,
+        deadline: Instant,
+    },
+    Connected(Box<PolledConnection<T>>),
+}
+
+impl<T: 'static + MessageProcessing> Polled<T> {
+    fn deadline(&self) -> Option<Instant> {
+        match self {
+            Polled::Listening {
+                candidates,
+                deadline,
+                ..
+            } => candidates
+                .iter()
+                .map(|candidate| candidate.deadline)
+                .chain(Some(*deadline))
+                .max(),
+            Polled::Connected(connection) => connection
+                .state
+                .rpc_interval_timeout
+                .map(|timeout| connection.last_transmission + timeout),
+        }
+    }
+}
+
+struct PolledConnection<T: 'static + MessageProcessing> {
+    stream: Stream,
+    source: PolledStream,
+    session: Session<T>,
+    state: Arc<ConnectionState>,
+    // the received data which does not yet form a complete transmission
+    buffer: Vec<u8>,
+    last_transmission: Instant,
+}
+
+impl<Req, Resp, Error, T> PolledConnection<T>
+where
+    Req: DeserializeOwned + Send + 'static,
+    Resp: Serialize + 'static,
+    Error: Serialize + std::fmt::Debug + 'static,
+    T: 'static + MessageProcessing<Rq = Req, Rsp = Resp, E = Error>,
+{
+    // reads until the stream would block; the edge triggered readiness is not reported again before
+    fn receive(&mut self, chunk: &mut [u8], max_frame_size: u64) -> TransceiveLoopAction {
+        loop {
+            match self.stream.read(chunk) {
+                Ok(0) => {
+                    log::debug!(
+                        "connection {} closed by the client",
+                        self.state.connection_id
+                    );
+                    return TransceiveLoopAction::Stop;
+                }
+                Ok(size) => {
+                    self.buffer.extend_from_slice(&chunk[..size]);
+                    self.last_transmission = Instant::now();
+                    if self.dispatch(max_frame_size) == TransceiveLoopAction::Stop {
+                        return TransceiveLoopAction::Stop;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    return TransceiveLoopAction::Continue
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    log::error!("transmission error: {:?}", err);
+                    return TransceiveLoopAction::Stop;
+                }
+            }
+        }
+    }
+
+    fn dispatch(&mut self, max_frame_size: u64) -> TransceiveLoopAction {
+        while let Some(request) = self.next_transmission(max_frame_size) {
+            let request = match request {
+                Ok(request) => Ok(request),
+                Err(ReceiveError::Rejected(rejection)) => Err(rejection),
+                Err(ReceiveError::Io(err)) => {
+                    log::error!("transmission error: {:?}", err);
+                    return TransceiveLoopAction::Stop;
+                }
+            };
+            if self.session.process(request) == TransceiveLoopAction::Stop {
+                return TransceiveLoopAction::Stop;
+            }
+        }
+        TransceiveLoopAction::Continue
+    }
+
+    // the first complete transmission of the buffer
+    fn next_transmission(
+        &mut self,
+        max_frame_size: u64,
+    ) -> Option<Result<(u64, transport::Type<Req>), ReceiveError>> {
+        let length_prefix = u64::from_be_bytes(self.buffer.get(..8)?.try_into().ok()?);
+        let (payload_size, compressed) =
+            compression::payload_size(length_prefix, self.state.compression);
+        if payload_size > max_frame_size {
+            // like util::read_transmission, the head of the rejected transmission is read if there is one
+            let head = match self.buffer.get(8..16) {
+                _ if compressed.is_some() => Vec::new(),
+                Some(head) => head.to_vec(),
+                None if payload_size < 8 => Vec::new(),
+                None => return None,
+            };
+            let err = util::FrameSizeExceeded {
+                payload_size,
+                max_frame_size,
+                head,
+            };
+            log::error!("waiting for transmission: {}", err);
+            let err = io::Error::new(io::ErrorKind::InvalidData, err);
+            return Some(Err(ReceiveError::from_read(err, &self.state.format)));
+        }
+
+        let frame_size = 8 + payload_size as usize;
+        if self.buffer.len() < frame_size {
+            return None;
+        }
+        let payload = &self.buffer[8..frame_size];
+        let request = match compressed {
+            Some(compression) => compression::decompress(compression, payload, max_frame_size)
+                .map_err(ReceiveError::Io)
+                .and_then(|payload| decode_request(&self.state.format, &payload)),
+            None => decode_request(&self.state.format, payload),
+        };
+        self.buffer.drain(..frame_size);
+        Some(request)
+    }
+}
+
+struct Poller<T: 'static + MessageProcessing> {
+    poll: Poll,
+    receiver: mpsc::Receiver<Command>,
+    message_processing: Arc<T>,
+    options: ServerOptions,
+    pool: Arc<WorkerPool>,
+    connections: HashMap<Token, Polled<T>>,
+    next_token: usize,
+    chunk: Vec<u8>,
+}
+
+impl<Req, Resp, Error, T> Poller<T>
+where
+    Req: DeserializeOwned + Send + 'static,
+    Resp: Serialize + 'static,
+    Error: Serialize + std::fmt::Debug + 'static,
+    T: 'static + MessageProcessing<Rq = Req, Rsp = Resp, E = Error>,
+{
+    fn run(mut self) {
+        let mut events = Events::with_capacity(1024);
+        loop {
+            let timeout = self
+                .connections
+                .values()
+                .filter_map(Polled::deadline)
+                .min()
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if let Err(err) = self.poll.poll(&mut events, timeout) {
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                log::error!("polling connections: {:?}", err);
+                break;
+            }
+
+            for event in events.iter() {
+                if event.token() != WAKER {
+                    self.ready(event.token());
+                }
+            }
+            if !self.receive_commands() {
+                break;
+            }
+            self.expire();
+        }
+
+        for (_, polled) in std::mem::take(&mut self.connections) {
+            self.close(polled);
+        }
+        log::debug!("end multiplexer");
+    }
+
+    // returns false if the multiplexer is stopped
+    fn receive_commands(&mut self) -> bool {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(Command::Register(channel, state)) => {
+                    let token = Token(self.next_token);
+                    self.next_token += 1;
+                    match self.open(channel, &state, token) {
+                        Ok(polled) => {
+                            self.connections.insert(token, polled);
+                            // e.g. the requests which the tls handshake already received
+                            self.ready(token);
+                        }
+                        Err(err) => {
+                            log::error!("transceiver: {:?}", err);
+                            state.closed.store(true, Ordering::SeqCst);
+                        }
+                    }
+                }
+                Ok(Command::Stop) | Err(mpsc::TryRecvError::Disconnected) => return false,
+                Err(mpsc::TryRecvError::Empty) => return true,
+            }
+        }
+    }
+
+    fn open(
+        &mut self,
+        channel: RpcChannel,
+        state: &Arc<ConnectionState>,
+        token: Token,
+    ) -> io::Result<Polled<T>> {
+        match channel {
+            RpcChannel::Listener(listener, rpc_token) => {
+                let mut listener = PolledListener::from_std(listener)?;
+                self.poll
+                    .registry()
+                    .register(listener.source(), token, Interest::READABLE)?;
+                Ok(Polled::Listening {
+                    listener,
+                    rpc_token,
+                    candidates: Vec::new(),
+                    state: state.clone(),
+                    deadline: Instant::now() + ACCEPT_TIMEOUT,
+                })
+            }
+            RpcChannel::Management(stream) => self.serve(stream, state, token),
+        }
+    }
+
+    fn serve(
+        &mut self,
+        stream: Stream,
+        state: &Arc<ConnectionState>,
+        token: Token,
+    ) -> io::Result<Polled<T>> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        let peer = Peer::from_stream(&stream, state.principal.clone())?;
+
+        // setup runs on the polling thread, therefore it is finished before the first request is executed
+        self.message_processing
+            .setup_peer(&peer, state.connection_id);
+        let session = Session::new(
+            self.message_processing.clone(),
+            state,
+            &stream,
+            peer.address().to_string(),
+            &self.options,
+            Some(self.pool.clone()),
+        )?;
+
+        let mut source = PolledStream::from_std(&stream)?;
+        self.poll
+            .registry()
+            .register(source.source(), token, Interest::READABLE)?;
+        Ok(Polled::Connected(Box::new(PolledConnection {
+            stream,
+            source,
+            session,
+            state: state.clone(),
+            buffer: Vec::new(),
+            last_transmission: Instant::now(),
+        })))
+    }
+
+    fn ready(&mut self, token: Token) {
+        let polled = match self.connections.remove(&token) {
+            Some(polled) => polled,
+            None => return,
+        };
+        match polled {
+            Polled::Listening {
+                mut listener,
+                rpc_token,
+                mut candidates,
+                state,
+                deadline,
+            } => {
+                if let Err(err) = self.admit(&mut listener, &mut candidates, deadline, token) {
+                    log::error!("accepting connection: {:?}", err);
+                    self.close(Polled::Listening {
+                        listener,
+                        rpc_token,
+                        candidates,
+                        state,
+                        deadline,
+                    });
+                    return;
+                }
+
+                // the first connection which completes the handshake and sends the rpc token is served
+                let now = Instant::now();
+                let mut established = None;
+                for mut candidate in std::mem::take(&mut candidates) {
+                    if established.is_some() {
+                        candidates.push(candidate);
+                        continue;
+                    }
+                    match candidate.advance(&rpc_token) {
+                        Ok(true) => established = Some(candidate),
+                        Ok(false) if candidate.deadline > now => candidates.push(candidate),
+                        Ok(false) => {
+                            log::warn!(
+                                "rpc connection {} refused: timeout during the handshake",
+                                state.connection_id
+                            );
+                            self.discard(candidate);
+                        }
+                        Err(err) => {
+                            log::warn!("rpc connection {} refused: {:?}", state.connection_id, err);
+                            self.discard(candidate);
+                        }
+                    }
+                }
+
+                match established {
+                    Some(candidate) => {
+                        let _ = self.poll.registry().deregister(listener.source());
+                        for candidate in candidates {
+                            self.discard(candidate);
+                        }
+                        match self.accept(candidate, &state, token) {
+                            Ok(polled) => {
+                                self.connections.insert(token, polled);
+                                // the handshake may have received requests, their readiness was already consumed
+                                self.ready(token);
+                            }
+                            Err(err) => {
+                                log::error!("transceiver: {:?}", err);
+                                state.closed.store(true, Ordering::SeqCst);
+                            }
+                        }
+                    }
+                    None => {
+                        self.connections.insert(
+                            token,
+                            Polled::Listening {
+                                listener,
+                                rpc_token,
+                                candidates,
+                                state,
+                                deadline,
+                            },
+                        );
+                    }
+                }
+            }
+            Polled::Connected(mut connection) => {
+                let action = connection.receive(&mut self.chunk, self.options.max_frame_size);
+                match action {
+                    TransceiveLoopAction::Continue => {
+                        self.connections
+                            .insert(token, Polled::Connected(connection));
+                    }
+                    TransceiveLoopAction::Stop => self.close(Polled::Connected(connection)),
+                }
+            }
+        }
+    }
+
+    // accepts the pending connections until the accept timeout
+    fn admit(
+        &mut self,
+        listener: &mut PolledListener,
+        candidates: &mut Vec<Candidate>,
+        deadline: Instant,
+        token: Token,
+    ) -> io::Result<()> {
+        loop {
+            match listener.accept() {
+                Ok(_) if Instant::now() >= deadline => (),
+                Ok(stream) => match Candidate::new(stream, &self.options, &self.poll, token) {
+                    Ok(candidate) => candidates.push(candidate),
+                    Err(err) => log::error!("accepting connection: {:?}", err),
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn accept(
+        &mut self,
+        mut candidate: Candidate,
+        state: &Arc<ConnectionState>,
+        token: Token,
+    ) -> io::Result<Polled<T>> {
+        // the stream is registered again for reading only
+        let _ = self.poll.registry().deregister(candidate.source.source());
+        *state.endpoint.lock().expect("getting lock") =
+            Endpoint::Connected(candidate.stream.try_clone()?);
+        self.serve(candidate.stream, state, token)
+    }
+
+    // the registration outlives the socket as long as a duplicate of it is open, therefore it is removed explicitly
+    fn discard(&self, mut candidate: Candidate) {
+        let _ = self.poll.registry().deregister(candidate.source.source());
+    }
+
+    fn expire(&mut self) {
+        let now = Instant::now();
+        let expired = self
+            .connections
+            .iter()
+            .filter(|(_, polled)| polled.deadline().is_some_and(|deadline| deadline <= now))
+            .map(|(token, _)| *token)
+            .collect::<Vec<_>>();
+        for token in expired {
+            if let Some(polled) = self.connections.remove(&token) {
+                match &polled {
+                    Polled::Listening { .. } => {
+                        log::error!("timeout while waiting for connection")
+                    }
+                    Polled::Connected(connection) => log::warn!(
+                        "connection {} exceeded the rpc interval timeout of {:?}",
+                        connection.state.connection_id,
+                        connection.state.rpc_interval_timeout
+                    ),
+                }
+                self.close(polled);
+            }
+        }
+    }
+
+    // the cleanup of a connection runs after the outstanding executions of its requests
+    fn close(&mut self, polled: Polled<T>) {
+        match polled {
+            Polled::Listening {
+                mut listener,
+                candidates,
+                state,
+                ..
+            } => {
+                let _ = self.poll.registry().deregister(listener.source());
+                for candidate in candidates {
+                    self.discard(candidate);
+                }
+                state.closed.store(true, Ordering::SeqCst);
+            }
+            Polled::Connected(connection) => {
+                let PolledConnection {
+                    mut source,
+                    session,
+                    ..
+                } = *connection;
+                let _ = self.poll.registry().deregister(source.source());
+                session.finish();
+            }
+        }
+    }
+}
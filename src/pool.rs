@@ -0,0 +1,265 @@
+// a pool of client connections to one server, shared by the threads of e.g. a web server
+use crate::client::{Connection, ConnectionOptions};
+use crate::stream::Address;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use std::net::ToSocketAddrs;
+use std::ops::Deref;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+type Pooled<Req, Resp, Error> = Box<Connection<Req, Resp, Error>>;
+
+#[derive(Clone, Debug)]
+pub struct PoolOptions {
+    // the maximum number of open connections; further leases wait until a connection is returned
+    pub max_size: usize,
+    // the pool waits at most this long for a returned connection; None waits forever
+    pub lease_timeout: Option<Duration>,
+    // a connection which was idle for this duration is checked with CheckRunState before it is leased;
+    // None only evicts connections which were closed
+    pub health_check_interval: Option<Duration>,
+    // the options of the pooled connections, which request dynamic connection ids
+    pub connection: ConnectionOptions,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        PoolOptions {
+            max_size: 8,
+            lease_timeout: Some(Duration::from_secs(10)),
+            health_check_interval: Some(Duration::from_secs(30)),
+            connection: ConnectionOptions::default(),
+        }
+    }
+}
+
+// the connections are opened when they are leased for the first time and reused afterwards
+#[derive(Debug)]
+pub struct ConnectionPool<Req, Resp, Error>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    addresses: Vec<Address>,
+    options: PoolOptions,
+    state: Mutex<PoolState<Req, Resp, Error>>,
+    returned: Condvar,
+}
+
+#[derive(Debug)]
+struct PoolState<Req, Resp, Error>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    // the returned connections with the time they were returned, the most recently used last
+    idle: Vec<(Pooled<Req, Resp, Error>, Instant)>,
+    // the idle and leased connections, including the ones which are being opened
+    open: usize,
+}
+
+// a connection leased from the pool; it is returned to the pool on drop, or evicted if it was closed
+#[derive(Debug)]
+pub struct Lease<'a, Req, Resp, Error>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    pool: &'a ConnectionPool<Req, Resp, Error>,
+    connection: Option<Pooled<Req, Resp, Error>>,
+}
+
+impl<Req, Resp, Error> ConnectionPool<Req, Resp, Error>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    // the address of the service management port, see Connection::new
+    pub fn new(
+        address: impl ToSocketAddrs,
+        options: PoolOptions,
+    ) -> Result<Self, crate::Error<Error>> {
+        let addresses = address.to_socket_addrs()?.map(Address::Tcp).collect();
+        Ok(Self::with_addresses(addresses, options))
+    }
+
+    #[cfg(unix)]
+    pub fn unix(path: impl AsRef<Path>, options: PoolOptions) -> Self {
+        let address = Address::Unix(path.as_ref().to_path_buf());
+        Self::with_addresses(vec![address], options)
+    }
+
+    fn with_addresses(addresses: Vec<Address>, options: PoolOptions) -> Self {
+        ConnectionPool {
+            addresses,
+            options,
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                open: 0,
+            }),
+            returned: Condvar::new(),
+        }
+    }
+
+    // an idle connection, a new one if the pool is not full, or the next returned one
+    pub fn lease(&self) -> Result<Lease<'_, Req, Resp, Error>, crate::Error<Error>> {
+        let deadline = self
+            .options
+            .lease_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().expect("getting lock");
+        loop {
+            if let Some((connection, returned)) = state.idle.pop() {
+                // the health check doesn't block the other callers
+                drop(state);
+                if self.is_healthy(&connection, returned) {
+                    return Ok(self.leased(connection));
+                }
+                state = self.evict(connection);
+                continue;
+            }
+
+            if state.open < self.options.max_size {
+                state.open += 1;
+                drop(state);
+                let connection = Connection::connect(
+                    self.addresses.clone(),
+                    -1,
+                    self.options.connection.clone(),
+                );
+                return match connection {
+                    Ok(connection) => Ok(self.leased(connection)),
+                    Err(err) => {
+                        self.state.lock().expect("getting lock").open -= 1;
+                        self.returned.notify_one();
+                        Err(err)
+                    }
+                };
+            }
+
+            state = match deadline {
+                None => self.returned.wait(state).expect("getting lock"),
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        log::error!(
+                            "no connection returned to the pool within {:?}",
+                            self.options.lease_timeout
+                        );
+                        return Err(crate::Error::Timeout);
+                    }
+                    self.returned
+                        .wait_timeout(state, timeout)
+                        .expect("getting lock")
+                        .0
+                }
+            };
+        }
+    }
+
+    // the leased and idle connections
+    pub fn size(&self) -> usize {
+        self.state.lock().expect("getting lock").open
+    }
+
+    pub fn idle(&self) -> usize {
+        self.state.lock().expect("getting lock").idle.len()
+    }
+
+    fn leased(&self, connection: Pooled<Req, Resp, Error>) -> Lease<'_, Req, Resp, Error> {
+        Lease {
+            pool: self,
+            connection: Some(connection),
+        }
+    }
+
+    fn is_healthy(&self, connection: &Connection<Req, Resp, Error>, returned: Instant) -> bool {
+        if connection.is_closed() {
+            log::warn!("evicting closed connection {}", connection.id());
+            return false;
+        }
+        match self.options.health_check_interval {
+            Some(interval) if returned.elapsed() >= interval => {
+                match connection.check_run_state() {
+                    Ok(()) => true,
+                    Err(err) => {
+                        log::warn!("evicting connection {}: {}", connection.id(), err);
+                        false
+                    }
+                }
+            }
+            _ => true,
+        }
+    }
+
+    // the connection is closed outside of the lock
+    fn evict(
+        &self,
+        connection: Pooled<Req, Resp, Error>,
+    ) -> MutexGuard<'_, PoolState<Req, Resp, Error>> {
+        drop(connection);
+        let mut state = self.state.lock().expect("getting lock");
+        state.open -= 1;
+        state
+    }
+
+    fn give_back(&self, connection: Pooled<Req, Resp, Error>) {
+        if connection.is_closed() {
+            log::warn!("evicting closed connection {}", connection.id());
+            drop(self.evict(connection));
+        } else {
+            let mut state = self.state.lock().expect("getting lock");
+            state.idle.push((connection, Instant::now()));
+        }
+        self.returned.notify_one();
+    }
+}
+
+impl<'a, Req, Resp, Error> Lease<'a, Req, Resp, Error>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    // closes the connection instead of returning it to the pool, e.g. after an unexpected response
+    pub fn evict(mut self) {
+        if let Some(connection) = self.connection.take() {
+            drop(self.pool.evict(connection));
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+impl<'a, Req, Resp, Error> Deref for Lease<'a, Req, Resp, Error>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    type Target = Connection<Req, Resp, Error>;
+
+    fn deref(&self) -> &Self::Target {
+        self.connection.as_ref().expect("leased connection")
+    }
+}
+
+impl<'a, Req, Resp, Error> Drop for Lease<'a, Req, Resp, Error>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned + std::fmt::Debug,
+    Error: DeserializeOwned + std::fmt::Debug,
+{
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.give_back(connection);
+        }
+    }
+}
@@ -1,22 +1,45 @@
+use crate::auth::Authenticator;
+use crate::codec::{self, Codec, Format};
+use crate::compression::{Algorithm, Compression, DEFAULT_COMPRESSION_THRESHOLD};
 use crate::mgmt;
+use crate::multiplexer::{Multiplexer, WorkerPool, Writability};
+use crate::stream::{Address, Listener, Stream};
 use crate::transport;
 use crate::util;
 use crate::{ProtocolVersion, Service};
 
-use bincode::Options;
 use serde::{de::DeserializeOwned, Serialize};
 
+#[cfg(feature = "tls")]
+use rustls::pki_types::CertificateDer;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io;
-use std::net::{Ipv4Addr, TcpListener, TcpStream};
-use std::sync::Arc;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+
+pub type ResponseStream<Rsp, E> = Box<dyn Iterator<Item = Result<Rsp, E>>>;
+
+// the requests of a client stream or bidirectional stream; the iteration ends when the client sends the End transmission
+#[derive(Debug)]
+pub struct RequestStream<Rq> {
+    receiver: mpsc::Receiver<Rq>,
+}
 
-type BincodeSerde = bincode::config::WithOtherIntEncoding<
-    bincode::config::WithOtherEndian<bincode::DefaultOptions, bincode::config::BigEndian>,
-    bincode::config::FixintEncoding,
->;
+impl<Rq> Iterator for RequestStream<Rq> {
+    type Item = Rq;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
 
 pub trait MessageProcessing: Send + Sync {
     type Rq;
@@ -34,8 +57,108 @@ pub trait MessageProcessing: Send + Sync {
         );
     }
 
+    // like setup, but with the certificates of a tls client and the authenticated principal;
+    // the default implementation calls setup
+    fn setup_peer(&self, peer: &Peer, connection_id: u32) {
+        self.setup(peer.address().to_string(), connection_id)
+    }
+
     fn execute(&self, connection_id: u32, rpc: Self::Rq) -> Result<Self::Rsp, Self::E>;
 
+    // like execute, but with the context of the request, e.g. the authenticated principal;
+    // the default implementation calls execute
+    fn execute_with_context(&self, context: &Context, rpc: Self::Rq) -> Result<Self::Rsp, Self::E> {
+        self.execute(context.connection_id(), rpc)
+    }
+
+    // a transport::Type::Notification; the client doesn't wait for a response and none is sent.
+    // notifications are executed like requests, so consecutive ones may be processed concurrently;
+    // the context has no deadline and is never cancelled
+    fn on_notification(&self, context: &Context, rpc: Self::Rq) {
+        // default implementation drops the notification
+        log::trace!(
+            "default implementation for MessageProcessing::on_notification: {}",
+            context.connection_id()
+        );
+        drop(rpc);
+    }
+
+    // the items of the returned stream are sent as transport::Type::Stream transmissions, terminated by transport::Type::End;
+    // an error item is sent as transport::Type::Error and terminates the stream
+    fn execute_stream(
+        &self,
+        connection_id: u32,
+        rpc: Self::Rq,
+    ) -> Result<ResponseStream<Self::Rsp, Self::E>, Self::E>
+    where
+        Self::Rsp: 'static,
+        Self::E: 'static,
+    {
+        // default implementation responds with a stream of only the response of execute
+        log::trace!("default implementation for MessageProcessing::execute_stream");
+        Ok(Box::new(std::iter::once(self.execute(connection_id, rpc))))
+    }
+
+    // the client streamed the requests, starting with rpc; the response is sent after the client sent the End transmission
+    fn execute_client_stream(
+        &self,
+        connection_id: u32,
+        rpc: Self::Rq,
+        rpcs: RequestStream<Self::Rq>,
+    ) -> Result<Self::Rsp, Self::E> {
+        // default implementation executes each request and responds with the last response
+        log::trace!("default implementation for MessageProcessing::execute_client_stream");
+        let mut response = self.execute(connection_id, rpc)?;
+        for rpc in rpcs {
+            response = self.execute(connection_id, rpc)?;
+        }
+        Ok(response)
+    }
+
+    // like execute_client_stream, but with the context of the stream, e.g. to notice a Cancel of the client;
+    // the default implementation calls execute_client_stream
+    fn execute_client_stream_with_context(
+        &self,
+        context: &Context,
+        rpc: Self::Rq,
+        rpcs: RequestStream<Self::Rq>,
+    ) -> Result<Self::Rsp, Self::E> {
+        self.execute_client_stream(context.connection_id(), rpc, rpcs)
+    }
+
+    // rpc is the StreamRequest which opened the stream, rpcs are the requests the client streams afterwards;
+    // the returned stream is sent like the one from execute_stream
+    fn execute_bidi_stream(
+        &self,
+        connection_id: u32,
+        rpc: Self::Rq,
+        rpcs: RequestStream<Self::Rq>,
+    ) -> Result<ResponseStream<Self::Rsp, Self::E>, Self::E>
+    where
+        Self::Rsp: 'static,
+        Self::E: 'static,
+    {
+        // default implementation ignores the streamed requests; this also serves the plain server streams
+        log::trace!("default implementation for MessageProcessing::execute_bidi_stream");
+        drop(rpcs);
+        self.execute_stream(connection_id, rpc)
+    }
+
+    // like execute_bidi_stream, but with the context of the stream;
+    // the default implementation calls execute_bidi_stream
+    fn execute_bidi_stream_with_context(
+        &self,
+        context: &Context,
+        rpc: Self::Rq,
+        rpcs: RequestStream<Self::Rq>,
+    ) -> Result<ResponseStream<Self::Rsp, Self::E>, Self::E>
+    where
+        Self::Rsp: 'static,
+        Self::E: 'static,
+    {
+        self.execute_bidi_stream(context.connection_id(), rpc, rpcs)
+    }
+
     fn cleanup(&self, connection_info: String, connection_id: u32) {
         // default implementation does nothing
         log::trace!(
@@ -46,6 +169,7 @@ pub trait MessageProcessing: Send + Sync {
     }
 
     // this can be implemented to shutdow a server; to accomplish this, this function must return true when a mgmt::Request::CheckRunState is send
+    // Server::shutdown_handle can be used instead to shutdown the server from any thread without a mgmt request
     fn shutdown(&self) -> bool {
         // default implementation does nothing
         log::trace!("default implementation for MessageProcessing::shutdow");
@@ -63,6 +187,12 @@ trait Executor {
     type E;
 
     fn execute(&self, connection_id: u32, rpc: Self::Rq) -> Result<Self::Rsp, Self::E>;
+
+    // the encoded answer to a payload which doesn't decode as a transmission of Rq, e.g. a request of an
+    // older protocol version; None rejects it as undecodable
+    fn answer_undecodable(&self, _payload: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
 }
 
 impl<T: 'static + MessageProcessing> Executor for T {
@@ -75,120 +205,1001 @@ impl<T: 'static + MessageProcessing> Executor for T {
     }
 }
 
+// the client of a rpc connection
+#[derive(Clone, Debug)]
+pub struct Peer {
+    pub(crate) address: String,
+    pub(crate) principal: Option<Arc<str>>,
+    #[cfg(feature = "tls")]
+    pub(crate) certificates: Vec<CertificateDer<'static>>,
+}
+
+impl Peer {
+    #[cfg(test)]
+    pub(crate) fn new(address: String) -> Self {
+        Peer {
+            address,
+            principal: None,
+            #[cfg(feature = "tls")]
+            certificates: Vec::new(),
+        }
+    }
+
+    pub(crate) fn from_stream(stream: &Stream, principal: Option<Arc<str>>) -> io::Result<Self> {
+        Ok(Peer {
+            address: stream.peer_description()?,
+            principal,
+            #[cfg(feature = "tls")]
+            certificates: match stream {
+                Stream::Tls(stream) => stream.peer_certificates(),
+                _ => Vec::new(),
+            },
+        })
+    }
+
+    // the principal ServerOptions::authenticator authenticated; None without an authenticator
+    pub fn principal(&self) -> Option<&str> {
+        self.principal.as_deref()
+    }
+
+    // the socket address of a tcp client, the socket path for a unix domain socket
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    // the certificate chain the client authenticated with, starting with its own certificate;
+    // empty without tls or if ServerOptions::tls doesn't verify client certificates
+    #[cfg(feature = "tls")]
+    pub fn certificates(&self) -> &[CertificateDer<'static>] {
+        &self.certificates
+    }
+}
+
+// the context of a request for MessageProcessing::execute_with_context, also of the async server
+#[derive(Clone, Debug)]
+pub struct Context {
+    pub(crate) connection_id: u32,
+    pub(crate) principal: Option<Arc<str>>,
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) cancellation: CancellationToken,
+}
+
+impl Context {
+    pub fn connection_id(&self) -> u32 {
+        self.connection_id
+    }
+
+    // the principal ServerOptions::authenticator authenticated; None without an authenticator
+    pub fn principal(&self) -> Option<&str> {
+        self.principal.as_deref()
+    }
+
+    // the time at which the client gives up waiting for the response; None if the client waits forever
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    // the remaining time until the deadline, e.g. to skip work the client won't wait for
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+    }
+
+    // set when the client cancels the request; a long running execution should check it and return early
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+}
+
+// the cancellation of a request by a Cancel transmission of the client; clones share the state
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Default)]
+struct ShutdownState {
+    requested: AtomicBool,
+    // addresses of the management listeners; a connection to them unblocks the accept loops
+    addresses: Mutex<Vec<Address>>,
+}
+
+// can be used from any thread to stop a running server
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        log::debug!("shutdown requested");
+        self.state.requested.store(true, Ordering::SeqCst);
+        for address in self.state.addresses.lock().expect("getting lock").iter() {
+            wake_listener(address);
+        }
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+}
+
+// connects to a listener to unblock its accept call
+fn wake_listener(address: &Address) {
+    let mut address = address.clone();
+    if let Address::Tcp(address) = &mut address {
+        match address.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => address.set_ip(Ipv4Addr::LOCALHOST.into()),
+            IpAddr::V6(ip) if ip.is_unspecified() => address.set_ip(Ipv6Addr::LOCALHOST.into()),
+            _ => (),
+        }
+    }
+    let _ = address
+        .connect(Duration::from_secs(1))
+        .map_err(|err| log::warn!("waking listener {}: {:?}", address, err));
+}
+
+// the time a client gets to complete the tls handshake
+#[cfg(feature = "tls")]
+pub(crate) const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// performs the tls handshake on an accepted connection if the server is configured for tls
+#[cfg(feature = "tls")]
+pub(crate) fn secure(stream: Stream, options: &ServerOptions) -> io::Result<Stream> {
+    match &options.tls {
+        Some(config) => {
+            stream.set_nonblocking(false)?;
+            stream.set_read_timeout(Some(TLS_HANDSHAKE_TIMEOUT))?;
+            crate::tls::accept(stream, config.clone())
+        }
+        None => Ok(stream),
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+pub(crate) fn secure(stream: Stream, _options: &ServerOptions) -> io::Result<Stream> {
+    Ok(stream)
+}
+
+// the time a client gets to send a management request; the management requests are handled one after another,
+// so a silent client would otherwise hold up the other clients and the shutdown
+pub(crate) const MGMT_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+// the time the client has to send the token of the rpc connection
+const RPC_TOKEN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// the token which the client sends first on the rpc connection must be the one of the connection
+pub(crate) fn receive_rpc_token(mut stream: &Stream, rpc_token: &mgmt::RpcToken) -> io::Result<()> {
+    stream.set_read_timeout(Some(RPC_TOKEN_TIMEOUT))?;
+    let mut received = mgmt::RpcToken([0u8; mgmt::RPC_TOKEN_SIZE]);
+    stream.read_exact(&mut received.0)?;
+    if received != *rpc_token {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "invalid rpc token",
+        ));
+    }
+    Ok(())
+}
+
+// the clients of a unix domain socket are local; they count as the loopback address for the connection limits
+pub(crate) const UNIX_PEER_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+// the rpc socket of a connection is created next to the management socket
+pub(crate) fn rpc_socket_path(path: &Path, connection_id: u32) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{}", connection_id));
+    path.into()
+}
+
+// the rpc listener of a connection uses the address family of the management connection
+pub(crate) fn rpc_listener(
+    local_address: &Address,
+    connection_id: u32,
+) -> io::Result<(Listener, mgmt::RpcEndpoint)> {
+    let listener = match local_address {
+        Address::Tcp(address) => Listener::Tcp(TcpListener::bind((address.ip(), 0))?),
+        #[cfg(unix)]
+        Address::Unix(path) => Listener::bind_unix(&rpc_socket_path(path, connection_id))?,
+    };
+    let endpoint = match listener.local_address()? {
+        Address::Tcp(address) => mgmt::RpcEndpoint::Port(address.port()),
+        #[cfg(unix)]
+        Address::Unix(path) => mgmt::RpcEndpoint::Path(
+            path.to_str()
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "socket path is not valid unicode",
+                    )
+                })?
+                .to_string(),
+        ),
+    };
+    Ok((listener, endpoint))
+}
+
+#[derive(Debug)]
+pub(crate) enum Endpoint {
+    // the transceiver waits for the client to connect
+    Listening(Address),
+    Connected(Stream),
+}
+
+// the state of a connection, shared between the registry and the transceiver
+#[derive(Debug)]
+pub(crate) struct ConnectionState {
+    pub(crate) connection_id: u32,
+    // the ip address of the client which requested the connection
+    peer_ip: IpAddr,
+    port: u16,
+    start_time: SystemTime,
+    pub(crate) endpoint: Mutex<Endpoint>,
+    request_count: AtomicU64,
+    // the connection is closed if the client doesn't transmit anything within this interval
+    pub(crate) rpc_interval_timeout: Option<Duration>,
+    // set after cleanup; the connection id can be reused afterwards
+    pub(crate) closed: AtomicBool,
+    // authenticated on the management connection which requested the connection
+    pub(crate) principal: Option<Arc<str>>,
+    // the codec of the rpcs
+    pub(crate) format: Format,
+    pub(crate) compression: Option<Compression>,
+}
+
+#[derive(Debug)]
+struct Registration {
+    // None if the connection is served by the multiplexer
+    thread: Option<thread::JoinHandle<()>>,
+    state: Arc<ConnectionState>,
+}
+
+impl Registration {
+    fn is_finished(&self) -> bool {
+        match &self.thread {
+            Some(thread) => thread.is_finished(),
+            None => self.state.closed.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    next_key: u64,
+    connections: HashMap<u64, Registration>,
+}
+
+impl Registry {
+    fn insert(&mut self, registration: Registration) {
+        // finished connections are removed lazily
+        self.connections
+            .retain(|_, registration| !registration.is_finished());
+        self.connections.insert(self.next_key, registration);
+        self.next_key += 1;
+    }
+
+    fn in_use(&self, connection_id: u32) -> bool {
+        self.connections.values().any(|registration| {
+            registration.state.connection_id == connection_id
+                && !registration.state.closed.load(Ordering::SeqCst)
+        })
+    }
+
+    // the number of open connections, only from the peer ip address if given
+    fn open_connections(&self, peer_ip: Option<IpAddr>) -> usize {
+        self.connections
+            .values()
+            .filter(|registration| !registration.state.closed.load(Ordering::SeqCst))
+            .filter(|registration| peer_ip.is_none_or(|ip| registration.state.peer_ip == ip))
+            .count()
+    }
+
+    // the lowest connection id which is not in use
+    fn unused_connection_id(&self) -> Option<u32> {
+        (0..mgmt::DYNAMIC_CONNECTION_ID).find(|connection_id| !self.in_use(*connection_id))
+    }
+}
+
+// a snapshot of a connection of the server
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    id: u32,
+    peer_address: Option<SocketAddr>,
+    port: u16,
+    start_time: SystemTime,
+    request_count: u64,
+}
+
+impl ConnectionInfo {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // None if the client did not yet connect to the assigned port
+    pub fn peer_address(&self) -> Option<SocketAddr> {
+        self.peer_address
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    // the time of the Connect request
+    pub fn start_time(&self) -> SystemTime {
+        self.start_time
+    }
+
+    pub fn request_count(&self) -> u64 {
+        self.request_count
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ServerOptions {
+    // the management port is bound on each of these addresses, e.g. on the unspecified IPv4 and IPv6 addresses
+    pub addresses: Vec<IpAddr>,
+    // the management port is also bound to this unix domain socket; the rpc sockets are created next to it
+    pub unix_socket: Option<PathBuf>,
+    // the rpcs are transmitted on the management connection instead of a separate port per connection
+    pub single_port: bool,
+    // a connection which transmits a larger payload is closed
+    pub max_frame_size: u64,
+    // the handling of transmissions which violate the protocol
+    pub fault_policy: FaultPolicy,
+    // how the rpc connections are served
+    pub execution: Execution,
+    // Connect requests are answered with ServerBusy while this number of connections is open
+    pub max_connections: Option<usize>,
+    // like max_connections, but for the connections requested from the same ip address
+    pub max_connections_per_peer: Option<usize>,
+    // the management and rpc connections are encrypted with this configuration;
+    // a client certificate verifier makes it mutual tls
+    #[cfg(feature = "tls")]
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+    // the clients must authenticate before they can connect; Identify and CheckRunState don't require it
+    pub authenticator: Option<Arc<dyn Authenticator>>,
+    // the codecs for the rpcs in the order of preference; the first one the client supports is used
+    pub formats: Vec<Format>,
+    // the compression algorithms for the rpcs in the order of preference; empty disables compression
+    pub compression: Vec<Algorithm>,
+    // payloads of a compressed connection are compressed from this size on
+    pub compression_threshold: u64,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            addresses: vec![Ipv4Addr::UNSPECIFIED.into()],
+            unix_socket: None,
+            single_port: false,
+            max_frame_size: util::DEFAULT_MAX_FRAME_SIZE,
+            fault_policy: FaultPolicy::KeepConnection,
+            execution: Execution::ThreadPerConnection,
+            max_connections: None,
+            max_connections_per_peer: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            authenticator: None,
+            formats: vec![Format::Bincode],
+            compression: Vec::new(),
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Execution {
+    // each connection is read by its own thread and each request is executed on a new thread
+    ThreadPerConnection,
+    // the connections are multiplexed on a single thread and the requests are executed by a fixed number of workers;
+    // client streams and bidirectional streams occupy a worker until the client ends the stream
+    WorkerPool { workers: usize },
+}
+
+// the faulty transmission is always answered with an Error; a connection which can't be read any
+// further, e.g. after an oversize transmission, is closed regardless of the policy
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultPolicy {
+    KeepConnection,
+    CloseConnection,
+}
+
+// a transmission of the client which violates the protocol
+#[derive(Debug)]
+pub(crate) struct Rejection {
+    // the fault is only transmitted if the transmission id is known
+    pub(crate) transmission_id: Option<u64>,
+    pub(crate) fault: transport::Fault,
+    // the following transmissions can still be read
+    pub(crate) recoverable: bool,
+}
+
+#[derive(Debug)]
+pub(crate) enum ReceiveError {
+    Io(io::Error),
+    Rejected(Rejection),
+}
+
+impl ReceiveError {
+    // the error of reading the payload of a transmission; the codec finds the id of a rejected transmission
+    pub(crate) fn from_read(err: io::Error, codec: &impl Codec) -> Self {
+        match util::frame_size_exceeded(&err) {
+            Some(exceeded) => ReceiveError::Rejected(Rejection {
+                transmission_id: codec.transmission_id(&exceeded.head),
+                fault: transport::Fault::FrameSizeExceeded {
+                    payload_size: exceeded.payload_size,
+                    max_frame_size: exceeded.max_frame_size,
+                },
+                // the payload was not read, therefore the stream is out of sync
+                recoverable: false,
+            }),
+            None => ReceiveError::Io(err),
+        }
+    }
+}
+
+impl From<io::Error> for ReceiveError {
+    fn from(err: io::Error) -> Self {
+        ReceiveError::Io(err)
+    }
+}
+
+// a client of another protocol version might misinterpret the rpc transmissions
+pub(crate) fn compatible_protocol_version(
+    params: &mgmt::CommParams,
+) -> Result<(), transport::Error> {
+    let server_protocol_version = ProtocolVersion::entity().version();
+    if params.protocol_version != server_protocol_version {
+        log::warn!(
+            "server::connect -> incompatible protocol versions; server: {}, client: {}",
+            server_protocol_version,
+            params.protocol_version
+        );
+        return Err(format!(
+            "incompatible protocol version {}, the server uses {}",
+            params.protocol_version, server_protocol_version
+        ));
+    }
+    Ok(())
+}
+
+// the Identify response of protocol version 0 to the Identify of a client of that version, whose requests don't
+// decode as mgmt::Request; None for any other payload
+pub(crate) fn legacy_identity(payload: &[u8], service: &Service) -> Option<Vec<u8>> {
+    let transmission = codec::Bincode
+        .decode::<transport::Transmission<mgmt::LegacyRequest>>(payload)
+        .ok()?;
+    let protocol_version = match transmission.r#type {
+        transport::Type::Request(mgmt::LegacyRequest::Identify { protocol_version }) => {
+            protocol_version
+        }
+        _ => return None,
+    };
+    let server_protocol_version = ProtocolVersion::entity().version();
+    log::warn!(
+        "server::identify -> incompatible protocol versions; server: {}, client: {}",
+        server_protocol_version,
+        protocol_version
+    );
+    let response = transport::Transmission::<mgmt::LegacyResponse> {
+        id: transmission.id,
+        r#type: transport::Type::Response(mgmt::LegacyResponse::Identify(mgmt::LegacyIdentity {
+            protocol_version: server_protocol_version,
+            service: service.clone(),
+        })),
+    };
+    codec::Bincode.encode(&response).ok()
+}
+
+pub(crate) fn decode_request<Rq>(
+    codec: &impl Codec,
+    payload: &[u8],
+) -> Result<(u64, transport::Type<Rq>), ReceiveError>
+where
+    Rq: DeserializeOwned,
+{
+    let err = match codec.decode::<transport::Transmission<Rq>>(payload) {
+        Ok(transmission) => return Ok((transmission.id, transmission.r#type)),
+        Err(err) => err,
+    };
+
+    // a payload without transmission id can't be answered
+    let (transmission_id, reason) = match codec.transmission_id(payload) {
+        Some(transmission_id) => (Some(transmission_id), err.to_string()),
+        None => (None, "missing transmission id".to_string()),
+    };
+    Err(ReceiveError::Rejected(Rejection {
+        transmission_id,
+        fault: transport::Fault::UndecodableTransmission(reason),
+        recoverable: true,
+    }))
+}
+
+// the limit which refuses a further connection from the peer; open_connections counts the open connections,
+// only those from the given ip address if there is one
+pub(crate) fn connection_limit(
+    options: &ServerOptions,
+    peer_ip: IpAddr,
+    open_connections: impl Fn(Option<IpAddr>) -> usize,
+) -> Option<mgmt::ConnectionLimit> {
+    let exceeds = |max: Option<usize>, peer_ip| {
+        max.filter(|max| open_connections(peer_ip) >= *max)
+            .map(|max| u32::try_from(max).unwrap_or(u32::MAX))
+    };
+    exceeds(options.max_connections, None)
+        .map(mgmt::ConnectionLimit::Server)
+        .or_else(|| {
+            exceeds(options.max_connections_per_peer, Some(peer_ip))
+                .map(mgmt::ConnectionLimit::Peer)
+        })
+}
+
+// the codec requested with Connect is one of the server
+pub(crate) fn enables(options: &ServerOptions, format: Format) -> bool {
+    format.is_supported() && options.formats.contains(&format)
+}
+
+// the first compression algorithm of the server which the client offers with Connect
+pub(crate) fn compression(options: &ServerOptions, offered: &[Algorithm]) -> Option<Compression> {
+    Algorithm::negotiate(&options.compression, offered).map(|algorithm| Compression {
+        algorithm,
+        threshold: options.compression_threshold,
+    })
+}
+
+// the stream for the rpcs of a connection
+#[derive(Debug)]
+pub(crate) enum RpcChannel {
+    // the client connects to a separate port and authorizes the connection with the token
+    Listener(Listener, mgmt::RpcToken),
+    // the client keeps using the management connection
+    Management(Stream),
+}
+
 pub struct Server<T: 'static + MessageProcessing> {
     pub(crate) message_processing: Arc<T>,
     port: u16,
     service: Service,
-    //TODO store connection id in hash map with all associated thread join handles
+    options: ServerOptions,
+    shutdown: Arc<ShutdownState>,
+    registry: Mutex<Registry>,
+    // serves the connections with Execution::WorkerPool; started with the first connection
+    multiplexer: Mutex<Option<Multiplexer>>,
 }
 
 #[derive(PartialEq)]
-enum TransceiveLoopAction {
+pub(crate) enum TransceiveLoopAction {
     Stop,
     Continue,
 }
 
 impl<Req, Resp, Error, T> Server<T>
 where
-    Req: DeserializeOwned,
-    Resp: Serialize,
-    Error: Serialize + std::fmt::Debug,
+    Req: DeserializeOwned + Send + 'static,
+    Resp: Serialize + 'static,
+    Error: Serialize + std::fmt::Debug + 'static,
     T: 'static + MessageProcessing<Rq = Req, Rsp = Resp, E = Error>,
 {
     pub fn new(port: u16, service: Service) -> Self {
+        Self::with_options(port, service, ServerOptions::default())
+    }
+
+    pub fn with_options(port: u16, service: Service, options: ServerOptions) -> Self {
         Server {
             message_processing: T::new(),
             port,
             service,
+            options,
+            shutdown: Arc::new(ShutdownState::default()),
+            registry: Mutex::new(Registry::default()),
+            multiplexer: Mutex::new(None),
+        }
+    }
+
+    // a snapshot of all open connections, ordered by the time of the Connect request
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let registry = self.registry.lock().expect("getting lock");
+        let mut connections = registry
+            .connections
+            .iter()
+            .filter(|(_, registration)| !registration.is_finished())
+            .map(|(key, registration)| {
+                let state = &registration.state;
+                let peer_address = match &*state.endpoint.lock().expect("getting lock") {
+                    Endpoint::Listening(_) => None,
+                    Endpoint::Connected(stream) => stream.peer_addr(),
+                };
+                let info = ConnectionInfo {
+                    id: state.connection_id,
+                    peer_address,
+                    port: state.port,
+                    start_time: state.start_time,
+                    request_count: state.request_count.load(Ordering::Relaxed),
+                };
+                (*key, info)
+            })
+            .collect::<Vec<_>>();
+        connections.sort_by_key(|(key, _)| *key);
+        connections.into_iter().map(|(_, info)| info).collect()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            state: self.shutdown.clone(),
         }
     }
 
     pub fn run(&self) -> io::Result<()> {
         log::info!("run");
 
-        let ip = Ipv4Addr::UNSPECIFIED;
-
         // bind port
-        let listener = util::bind(ip, self.port)?;
+        let mut listeners = self
+            .options
+            .addresses
+            .iter()
+            .map(|ip| util::bind(*ip, self.port).map(Listener::Tcp))
+            .collect::<io::Result<Vec<_>>>()?;
+        if let Some(path) = &self.options.unix_socket {
+            listeners.push(Listener::bind_unix(path)?);
+        }
+        *self.shutdown.addresses.lock().expect("getting lock") = listeners
+            .iter()
+            .map(Listener::local_address)
+            .collect::<io::Result<_>>()?;
 
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
+        if !self.shutdown.requested.load(Ordering::SeqCst) {
+            // each listener is accepted on its own thread, the mgmt requests are handled one after another
+            thread::scope(|scope| {
+                let (sender, receiver) = mpsc::channel();
+                for listener in &listeners {
+                    let sender = sender.clone();
+                    scope.spawn(move || loop {
+                        let stream = listener.accept();
+                        if self.shutdown.requested.load(Ordering::SeqCst)
+                            || sender.send(stream).is_err()
+                        {
+                            break;
+                        }
+                    });
+                }
+                drop(sender);
 
-        for stream in listener.incoming() {
-            let _ = || -> io::Result<()> { self.handle_mgmt_request(stream?, &serde) }()
-                .map_err(|err| log::error!("mgmt request: {:?}", err));
-            if self.message_processing.shutdown() {
-                break;
-            }
+                for stream in receiver {
+                    if self.shutdown.requested.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let _ = || -> io::Result<()> { self.handle_mgmt_request(stream?) }()
+                        .map_err(|err| log::error!("mgmt request: {:?}", err));
+                    if self.message_processing.shutdown() {
+                        break;
+                    }
+                }
+
+                // unblocks the remaining accept loops
+                self.shutdown.requested.store(true, Ordering::SeqCst);
+                for listener in &listeners {
+                    if let Ok(address) = listener.local_address() {
+                        wake_listener(&address);
+                    }
+                }
+            });
         }
+        self.shutdown
+            .addresses
+            .lock()
+            .expect("getting lock")
+            .clear();
+
+        self.stop_transceivers();
         log::info!("run -> stop");
         Ok(())
     }
 
-    fn handle_mgmt_request(
-        &self,
-        mut stream: TcpStream,
-        serde: &BincodeSerde, // &dyn bincode::Options,
-    ) -> io::Result<()> {
-        util::adjust_stream(&stream, None)?;
+    // signals all transceivers to finish and waits for them
+    fn stop_transceivers(&self) {
+        self.shutdown.requested.store(true, Ordering::SeqCst);
+        let registry = std::mem::take(&mut *self.registry.lock().expect("getting lock"));
+        // the multiplexed connections are closed by the multiplexer
+        for registration in registry
+            .connections
+            .values()
+            .filter(|registration| registration.thread.is_some())
+        {
+            match &*registration.state.endpoint.lock().expect("getting lock") {
+                Endpoint::Listening(address) => wake_listener(address),
+                Endpoint::Connected(stream) => {
+                    // the outstanding responses can still be sent
+                    let _ = stream
+                        .shutdown(Shutdown::Read)
+                        .map_err(|err| log::warn!("shutdown rpc stream: {:?}", err));
+                }
+            }
+        }
+        for thread in registry
+            .connections
+            .into_values()
+            .filter_map(|registration| registration.thread)
+        {
+            let _ = thread.join();
+        }
+        if let Some(multiplexer) = self.multiplexer.lock().expect("getting lock").take() {
+            multiplexer.stop();
+        }
+    }
 
+    // the management requests are always bincode, the codec of the rpcs is negotiated with them
+    fn handle_mgmt_request(&self, stream: Stream) -> io::Result<()> {
+        let mut stream = secure(stream, &self.options)?;
+        util::adjust_stream(&stream, Some(MGMT_REQUEST_TIMEOUT))?;
+
+        let session = MgmtSession {
+            server: self,
+            local_address: stream.local_address()?,
+            peer: Peer::from_stream(&stream, None)?,
+            peer_ip: stream
+                .peer_addr()
+                .map_or(UNIX_PEER_IP, |address| address.ip()),
+            stream: if self.options.single_port {
+                Some(stream.try_clone()?)
+            } else {
+                None
+            },
+            authentication: RefCell::new(Authentication::None),
+        };
+
+        // the authentication continues on the same connection up to the Connect request
         const DUMMY_CONNECTION_ID: u32 = 0;
-        Self::handle_request(&mut stream, serde, self, DUMMY_CONNECTION_ID).and_then(|_| Ok(()))
+        loop {
+            let action = Self::handle_request(
+                &mut stream,
+                &codec::Bincode,
+                &session,
+                DUMMY_CONNECTION_ID,
+                self.options.max_frame_size,
+            )?;
+            if action == TransceiveLoopAction::Stop || !session.authenticating() {
+                return Ok(());
+            }
+        }
     }
 
-    fn connection_request(&self, connection_id: u32) -> io::Result<u16> {
-        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))?;
-        let local_port: u16 = listener.local_addr()?.port();
+    fn connection_request(
+        &self,
+        params: &mgmt::CommParams,
+        local_address: &Address,
+        peer_ip: IpAddr,
+        mgmt_stream: Option<Stream>,
+        principal: Option<String>,
+    ) -> io::Result<mgmt::Response> {
+        // the registry stays locked until the connection id is registered
+        let mut registry = self.registry.lock().expect("getting lock");
+        if let Some(limit) = connection_limit(&self.options, peer_ip, |peer_ip| {
+            registry.open_connections(peer_ip)
+        }) {
+            log::warn!("connection from {} refused: {}", peer_ip, limit);
+            return Ok(mgmt::Response::ServerBusy(limit));
+        }
+        let connection_id = params.connection_id;
+        let connection_id = if connection_id == mgmt::DYNAMIC_CONNECTION_ID {
+            registry.unused_connection_id().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::AddrInUse, "no unused connection id")
+            })?
+        } else if registry.in_use(connection_id) {
+            log::warn!("connection id {} already in use", connection_id);
+            return Ok(mgmt::Response::ConnectionIdInUse(connection_id));
+        } else {
+            connection_id
+        };
+
+        let rpc_token = mgmt::RpcToken::generate()?;
+        let (channel, endpoint, assigned_endpoint) = match mgmt_stream {
+            Some(stream) => {
+                let endpoint = Endpoint::Connected(stream.try_clone()?);
+                (
+                    RpcChannel::Management(stream),
+                    endpoint,
+                    mgmt::RpcEndpoint::Management,
+                )
+            }
+            None => {
+                let (listener, assigned_endpoint) = rpc_listener(local_address, connection_id)?;
+                let endpoint = Endpoint::Listening(listener.local_address()?);
+                (
+                    RpcChannel::Listener(listener, rpc_token),
+                    endpoint,
+                    assigned_endpoint,
+                )
+            }
+        };
+        // the tcp port of the rpcs; 0 for a unix domain socket
+        let port = match &assigned_endpoint {
+            mgmt::RpcEndpoint::Port(port) => *port,
+            _ => match local_address {
+                Address::Tcp(address) => address.port(),
+                #[cfg(unix)]
+                Address::Unix(_) => 0,
+            },
+        };
+
+        let state = Arc::new(ConnectionState {
+            connection_id,
+            peer_ip,
+            port,
+            start_time: SystemTime::now(),
+            endpoint: Mutex::new(endpoint),
+            request_count: AtomicU64::new(0),
+            rpc_interval_timeout: mgmt::rpc_interval_timeout(params.rpc_interval_timeout_ms),
+            closed: AtomicBool::new(false),
+            principal: principal.map(Arc::from),
+            format: params.format,
+            compression: compression(&self.options, &params.compression),
+        });
 
         // start the server transmission handler
-        let message_processing = self.message_processing.clone();
-        thread::spawn(move || {
-            Server::<T>::transceiver(message_processing, listener, connection_id)
-        }); // TODO: store threads in Vec and join them on drop
+        let thread = match self.options.execution {
+            Execution::ThreadPerConnection => {
+                let message_processing = self.message_processing.clone();
+                let shutdown = self.shutdown.clone();
+                let options = self.options.clone();
+                let state = state.clone();
+                Some(thread::spawn(move || {
+                    let _ = Server::<T>::transceiver(
+                        message_processing,
+                        channel,
+                        shutdown,
+                        &state,
+                        &options,
+                    )
+                    .map_err(|err| log::error!("transceiver: {:?}", err));
+                    state.closed.store(true, Ordering::SeqCst);
+                }))
+            }
+            Execution::WorkerPool { workers } => {
+                let mut multiplexer = self.multiplexer.lock().expect("getting lock");
+                let multiplexer = match &mut *multiplexer {
+                    Some(multiplexer) => multiplexer,
+                    none => none.insert(Multiplexer::start(
+                        self.message_processing.clone(),
+                        self.options.clone(),
+                        workers,
+                    )?),
+                };
+                multiplexer.register(channel, state.clone())?;
+                None
+            }
+        };
+
+        let compression = state.compression;
+        registry.insert(Registration { thread, state });
 
-        Ok(local_port)
+        Ok(mgmt::Response::Connect(mgmt::CommSettings {
+            connection_id,
+            endpoint: assigned_endpoint,
+            compression,
+            rpc_token,
+        }))
     }
 
     fn transceiver(
         message_processing: Arc<T>,
-        listener: TcpListener,
-        connection_id: u32,
+        channel: RpcChannel,
+        shutdown: Arc<ShutdownState>,
+        state: &Arc<ConnectionState>,
+        options: &ServerOptions,
     ) -> io::Result<()> {
-        let local_port: u16 = listener.local_addr()?.port();
-        let stream = &mut util::listener_accept_nonblocking(listener, Duration::from_secs(2))?;
-        util::adjust_stream(stream, None)?;
-
-        message_processing.setup(
-            "TODO: ip address:".to_string() + &local_port.to_string(),
-            connection_id,
-        );
+        let connection_id = state.connection_id;
+        let stream = &mut match channel {
+            RpcChannel::Listener(listener, rpc_token) => {
+                let address = listener.local_address()?;
+                let deadline = Instant::now() + Duration::from_secs(2);
+                loop {
+                    let stream = listener
+                        .accept_timeout(deadline.saturating_duration_since(Instant::now()))?;
+                    let mut endpoint = state.endpoint.lock().expect("getting lock");
+                    if shutdown.requested.load(Ordering::SeqCst) {
+                        log::debug!("server shutdown before the client connected");
+                        return Ok(());
+                    }
+                    *endpoint = Endpoint::Connected(stream.try_clone()?);
+                    drop(endpoint);
+                    match secure(stream, options)
+                        .and_then(|stream| receive_rpc_token(&stream, &rpc_token).map(|_| stream))
+                    {
+                        Ok(stream) => break stream,
+                        Err(err) => {
+                            log::warn!("rpc connection {} refused: {:?}", connection_id, err);
+                            let mut endpoint = state.endpoint.lock().expect("getting lock");
+                            if shutdown.requested.load(Ordering::SeqCst) {
+                                return Ok(());
+                            }
+                            *endpoint = Endpoint::Listening(address.clone());
+                        }
+                    }
+                }
+            }
+            RpcChannel::Management(stream) => stream,
+        };
+        let peer = Peer::from_stream(stream, state.principal.clone())?;
+        util::adjust_stream(stream, state.rpc_interval_timeout)?;
 
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
+        message_processing.setup_peer(&peer, connection_id);
+        let mut session = Session::new(
+            message_processing,
+            state,
+            stream,
+            peer.address,
+            options,
+            None,
+        )?;
 
-        let mut running = TransceiveLoopAction::Continue;
-        while running == TransceiveLoopAction::Continue {
-            running = Self::handle_request(stream, &serde, &*message_processing, connection_id)
-                .map_err(|err| log::error!("transmission error: {:?}", err))
-                .unwrap_or(TransceiveLoopAction::Stop);
+        loop {
+            let request = match Self::receive_request::<Req>(
+                stream,
+                &state.format,
+                state.compression,
+                options.max_frame_size,
+            ) {
+                Ok(request) => Ok(request),
+                Err(ReceiveError::Rejected(rejection)) => Err(rejection),
+                Err(_) if shutdown.requested.load(Ordering::SeqCst) => {
+                    log::debug!("server shutdown");
+                    break;
+                }
+                Err(ReceiveError::Io(err))
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    log::warn!(
+                        "connection {} exceeded the rpc interval timeout of {:?}",
+                        connection_id,
+                        state.rpc_interval_timeout
+                    );
+                    break;
+                }
+                Err(ReceiveError::Io(err)) => {
+                    log::error!("transmission error: {:?}", err);
+                    break;
+                }
+            };
+            if session.process(request) == TransceiveLoopAction::Stop {
+                break;
+            }
         }
 
-        message_processing.cleanup(
-            "TODO: ip address:".to_string() + &local_port.to_string(),
-            connection_id,
-        );
+        // outstanding requests are finished before the connection is cleaned up
+        session.finish();
 
         log::debug!("end message processing transceiver");
         Ok(())
     }
 
     fn handle_request<Rq, Rsp, E, U>(
-        stream: &mut TcpStream,
-        serde: &BincodeSerde, // &dyn bincode::config::Options,
+        stream: &mut Stream,
+        codec: &impl Codec,
         executor: &U,
         connection_id: u32,
+        max_frame_size: u64,
     ) -> io::Result<TransceiveLoopAction>
     where
         Rq: DeserializeOwned,
@@ -196,63 +1207,627 @@ where
         E: Serialize + std::fmt::Debug,
         U: Executor<Rq = Rq, Rsp = Rsp, E = E>,
     {
-        let payload_size = util::wait_for_transmission(stream)?;
-        let payload = util::read_transmission(stream, payload_size)?;
-
-        let (tid, r#type) = payload.split_at(8 as usize);
-        let transmission_id =
-            u64::from_be_bytes(<[u8; 8]>::try_from(tid).expect("transmission id"));
-
-        let request = serde
-            .deserialize::<transport::Type<Rq>>(r#type)
-            .expect("deserializing request"); //TODO error handling
+        let length_prefix = util::wait_for_transmission(stream)?;
+        let received = match util::read_transmission(stream, length_prefix, max_frame_size, None) {
+            Ok(payload) => match decode_request::<Rq>(codec, &payload) {
+                Err(rejection) => match executor.answer_undecodable(&payload) {
+                    Some(answer) => {
+                        util::write_transmission(stream, answer, None)?;
+                        return Ok(TransceiveLoopAction::Stop);
+                    }
+                    None => Err(rejection),
+                },
+                request => request,
+            },
+            Err(err) => Err(ReceiveError::from_read(err, codec)),
+        };
+        let (transmission_id, request) = match received {
+            Ok(request) => request,
+            Err(ReceiveError::Io(err)) => return Err(err),
+            Err(ReceiveError::Rejected(rejection)) => {
+                Self::reject(stream, codec, None, &rejection);
+                return Ok(if rejection.recoverable {
+                    TransceiveLoopAction::Continue
+                } else {
+                    TransceiveLoopAction::Stop
+                });
+            }
+        };
 
         match request {
             transport::Type::Request(cmd) => {
                 let response = executor.execute(connection_id, cmd);
-
-                match response {
-                    Ok(response) => {
-                        let response = transport::Transmission {
-                            id: transmission_id,
-                            r#type: transport::Type::Response(response),
-                        };
-                        let serialized = serde.serialize(&response).unwrap();
-                        util::write_transmission(stream, serialized)?;
-                    }
-                    Err(err) => {
-                        let response = transport::Transmission {
-                            id: transmission_id,
-                            r#type: transport::Type::Error(err),
-                        };
-                        let serialized = serde.serialize(&response).unwrap();
-                        util::write_transmission(stream, serialized)?;
-                    }
-                }
+                Self::send_response(stream, codec, None, transmission_id, response)?;
             }
             transport::Type::End => {
                 log::trace!("end request");
                 return Ok(TransceiveLoopAction::Stop);
             }
             _ => {
-                let response = transport::Transmission {
-                    id: transmission_id,
-                    r#type: transport::Type::Error("Not a request!".to_string()),
+                let rejection = Rejection {
+                    transmission_id: Some(transmission_id),
+                    fault: transport::Fault::UnexpectedType,
+                    recoverable: true,
                 };
-                let serialized = serde.serialize(&response).unwrap();
-                util::write_transmission(stream, serialized)?;
+                Self::reject(stream, codec, None, &rejection);
             }
         }
 
         Ok(TransceiveLoopAction::Continue)
     }
+
+    // answers a faulty transmission with a Fault transmission
+    fn reject<W: Write>(
+        stream: &mut W,
+        codec: &impl Codec,
+        compression: Option<Compression>,
+        rejection: &Rejection,
+    ) {
+        log::warn!("rejected transmission: {}", rejection.fault);
+        if let Some(transmission_id) = rejection.transmission_id {
+            let transmission = transport::Transmission::<()> {
+                id: transmission_id,
+                r#type: transport::Type::Fault(rejection.fault.clone()),
+            };
+            let _ = codec
+                .encode(&transmission)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+                .and_then(|serialized| util::write_transmission(stream, serialized, compression))
+                .map_err(|err| log::error!("transmission error: {:?}", err));
+        }
+    }
+
+    fn receive_request<Rq>(
+        stream: &mut Stream,
+        codec: &impl Codec,
+        compression: Option<Compression>,
+        max_frame_size: u64,
+    ) -> Result<(u64, transport::Type<Rq>), ReceiveError>
+    where
+        Rq: DeserializeOwned,
+    {
+        let length_prefix = util::wait_for_transmission(stream)?;
+        let payload = util::read_transmission(stream, length_prefix, max_frame_size, compression)
+            .map_err(|err| ReceiveError::from_read(err, codec))?;
+        decode_request(codec, &payload)
+    }
+
+    fn send_response<W, Rsp, E>(
+        stream: &mut W,
+        codec: &impl Codec,
+        compression: Option<Compression>,
+        transmission_id: u64,
+        response: Result<Rsp, E>,
+    ) -> io::Result<()>
+    where
+        W: Write,
+        Rsp: Serialize,
+        E: Serialize,
+    {
+        let response = transport::Transmission::<Rsp, E> {
+            id: transmission_id,
+            r#type: match response {
+                Ok(response) => transport::Type::Response(response),
+                Err(err) => transport::Type::Error(err),
+            },
+        };
+        let serialized = codec
+            .encode(&response)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        util::write_transmission(stream, serialized, compression).map(|_| ())
+    }
+
+    // the stream is stopped with a Cancelled fault when the client cancels it
+    fn send_stream<W, Rsp, E>(
+        writer: &Mutex<W>,
+        codec: &impl Codec,
+        compression: Option<Compression>,
+        transmission_id: u64,
+        response: Result<ResponseStream<Rsp, E>, E>,
+        cancellation: &CancellationToken,
+    ) -> io::Result<()>
+    where
+        W: Write,
+        Rsp: Serialize,
+        E: Serialize,
+    {
+        let send = |r#type: transport::Type<Rsp, E>| {
+            let transmission = transport::Transmission {
+                id: transmission_id,
+                r#type,
+            };
+            let serialized = codec
+                .encode(&transmission)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            util::write_transmission(
+                &mut *writer.lock().expect("getting lock"),
+                serialized,
+                compression,
+            )
+            .map(|_| ())
+        };
+
+        let items = match response {
+            Ok(items) => items,
+            Err(err) => return send(transport::Type::Error(err)),
+        };
+        for item in items {
+            if cancellation.is_cancelled() {
+                let rejection = Rejection {
+                    transmission_id: Some(transmission_id),
+                    fault: transport::Fault::Cancelled,
+                    recoverable: true,
+                };
+                Self::reject(
+                    &mut *writer.lock().expect("getting lock"),
+                    codec,
+                    compression,
+                    &rejection,
+                );
+                return Ok(());
+            }
+            match item {
+                Ok(item) => send(transport::Type::Stream(item))?,
+                Err(err) => return send(transport::Type::Error(err)),
+            }
+        }
+        send(transport::Type::End)
+    }
+}
+
+// the writing half of a connection; the stream is nonblocking if the connection is multiplexed
+#[derive(Debug)]
+struct Writer {
+    stream: Stream,
+    // registered on the first write to a full send buffer of a nonblocking stream
+    writability: Option<Writability>,
+}
+
+impl Writer {
+    fn wait_writable(&mut self) -> io::Result<()> {
+        if self.writability.is_none() {
+            self.writability = Some(Writability::new(&self.stream)?);
+        }
+        self.writability.as_mut().expect("writability").wait()
+    }
 }
 
-impl<Req, Resp, Error, T> Executor for Server<T>
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = loop {
+            match self.stream.write(buf) {
+                // the send buffer is full
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => self.wait_writable()?,
+                result => break result?,
+            }
+        };
+        // e.g. the tls records which would have blocked
+        self.flush()?;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        loop {
+            match self.stream.flush() {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => self.wait_writable()?,
+                result => return result,
+            }
+        }
+    }
+}
+
+// runs the cleanup of a connection when the session and all executions of its requests are finished
+struct Finalizer {
+    finalize: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+}
+
+impl Drop for Finalizer {
+    fn drop(&mut self) {
+        if let Some(finalize) = self.finalize.get_mut().expect("getting lock").take() {
+            finalize();
+        }
+    }
+}
+
+// dispatches the transmissions of a connection to the executions of the requests
+pub(crate) struct Session<T: 'static + MessageProcessing> {
+    message_processing: Arc<T>,
+    state: Arc<ConnectionState>,
+    writer: Arc<Mutex<Writer>>,
+    fault_policy: FaultPolicy,
+    // the requests are executed on the worker pool if there is one, otherwise on a thread per request
+    pool: Option<Arc<WorkerPool>>,
+    executions: Vec<thread::JoinHandle<()>>,
+    // streams for which the client has not yet sent the End transmission, by transmission id;
+    // the sender is taken by a Cancel, the further requests of the stream are discarded
+    open_streams: HashMap<u64, Option<mpsc::Sender<T::Rq>>>,
+    // the requests and stream requests which are not yet answered, by transmission id
+    cancellations: Cancellations,
+    finalizer: Arc<Finalizer>,
+}
+
+type Cancellations = Arc<Mutex<HashMap<u64, CancellationToken>>>;
+
+impl<Req, Resp, Error, T> Session<T>
+where
+    Req: DeserializeOwned + Send + 'static,
+    Resp: Serialize + 'static,
+    Error: Serialize + std::fmt::Debug + 'static,
+    T: 'static + MessageProcessing<Rq = Req, Rsp = Resp, E = Error>,
+{
+    // MessageProcessing::setup must be called before
+    pub(crate) fn new(
+        message_processing: Arc<T>,
+        state: &Arc<ConnectionState>,
+        stream: &Stream,
+        peer_address: String,
+        options: &ServerOptions,
+        pool: Option<Arc<WorkerPool>>,
+    ) -> io::Result<Self> {
+        let writer = Writer {
+            stream: stream.try_clone()?,
+            writability: None,
+        };
+        let finalize = {
+            let stream = stream.try_clone()?;
+            let message_processing = message_processing.clone();
+            let state = state.clone();
+            move || {
+                // the registry keeps a clone of the stream, therefore the client must be notified explicitly
+                let _ = stream.shutdown(Shutdown::Both);
+                message_processing.cleanup(peer_address, state.connection_id);
+                state.closed.store(true, Ordering::SeqCst);
+            }
+        };
+
+        Ok(Session {
+            message_processing,
+            state: state.clone(),
+            writer: Arc::new(Mutex::new(writer)),
+            fault_policy: options.fault_policy,
+            pool,
+            executions: Vec::new(),
+            open_streams: HashMap::new(),
+            cancellations: Arc::default(),
+            finalizer: Arc::new(Finalizer {
+                finalize: Mutex::new(Some(Box::new(finalize))),
+            }),
+        })
+    }
+
+    pub(crate) fn process(
+        &mut self,
+        request: Result<(u64, transport::Type<Req>), Rejection>,
+    ) -> TransceiveLoopAction {
+        let connection_id = self.state.connection_id;
+        let format = self.state.format;
+        let compression = self.state.compression;
+        match request {
+            Ok((transmission_id, transport::Type::Request(rpc))) => {
+                self.execute(transmission_id, rpc, None);
+            }
+            Ok((transmission_id, transport::Type::RequestWithDeadline(rpc, timeout_ms))) => {
+                // a deadline beyond the range of Instant is no deadline
+                let deadline = Instant::now().checked_add(Duration::from_millis(timeout_ms));
+                self.execute(transmission_id, rpc, deadline);
+            }
+            Ok((transmission_id, transport::Type::StreamRequest(rpc))) => {
+                self.state.request_count.fetch_add(1, Ordering::Relaxed);
+                let (sender, receiver) = mpsc::channel();
+                self.open_streams.insert(transmission_id, Some(sender));
+                let message_processing = self.message_processing.clone();
+                let writer = self.writer.clone();
+                let context = self.context(transmission_id, None);
+                let cancellations = self.cancellations.clone();
+                self.spawn(move || {
+                    let rpcs = RequestStream { receiver };
+                    let response =
+                        message_processing.execute_bidi_stream_with_context(&context, rpc, rpcs);
+                    let _ = Server::<T>::send_stream(
+                        &writer,
+                        &format,
+                        compression,
+                        transmission_id,
+                        response,
+                        &context.cancellation,
+                    )
+                    .map_err(|err| log::error!("transmission error: {:?}", err));
+                    cancellations
+                        .lock()
+                        .expect("getting lock")
+                        .remove(&transmission_id);
+                });
+            }
+            Ok((transmission_id, transport::Type::Stream(rpc))) => {
+                if let Some(sender) = self.open_streams.get(&transmission_id) {
+                    // the handler might not be interested in further requests
+                    if let Some(sender) = sender {
+                        let _ = sender.send(rpc);
+                    }
+                    return TransceiveLoopAction::Continue;
+                }
+                // the first Stream transmission with a new transmission id opens a client stream
+                self.state.request_count.fetch_add(1, Ordering::Relaxed);
+                let (sender, receiver) = mpsc::channel();
+                self.open_streams.insert(transmission_id, Some(sender));
+                let message_processing = self.message_processing.clone();
+                let writer = self.writer.clone();
+                let context = self.context(transmission_id, None);
+                let cancellations = self.cancellations.clone();
+                self.spawn(move || {
+                    let rpcs = RequestStream { receiver };
+                    // the client cancelled the stream while it waited for a worker
+                    let response = if context.is_cancelled() {
+                        Err(transport::Fault::Cancelled)
+                    } else {
+                        Ok(message_processing
+                            .execute_client_stream_with_context(&context, rpc, rpcs))
+                    };
+                    Self::respond(
+                        &writer,
+                        &format,
+                        compression,
+                        transmission_id,
+                        &context,
+                        &cancellations,
+                        response,
+                    );
+                });
+            }
+            Ok((transmission_id, transport::Type::End))
+                if self.open_streams.remove(&transmission_id).is_some() =>
+            {
+                log::trace!("end of stream {}", transmission_id);
+            }
+            Ok((_, transport::Type::End)) => {
+                log::trace!("end request");
+                return TransceiveLoopAction::Stop;
+            }
+            Ok((_, transport::Type::KeepAlive)) => {
+                log::trace!("keep alive");
+            }
+            Ok((_, transport::Type::Notification(rpc))) => {
+                self.state.request_count.fetch_add(1, Ordering::Relaxed);
+                let message_processing = self.message_processing.clone();
+                let context = Context {
+                    connection_id,
+                    principal: self.state.principal.clone(),
+                    deadline: None,
+                    cancellation: CancellationToken::default(),
+                };
+                self.spawn(move || message_processing.on_notification(&context, rpc));
+            }
+            Ok((transmission_id, transport::Type::Cancel)) => {
+                // the response might have been sent already
+                match self
+                    .cancellations
+                    .lock()
+                    .expect("getting lock")
+                    .get(&transmission_id)
+                {
+                    Some(cancellation) => cancellation.cancel(),
+                    None => log::trace!("cancel of finished request {}", transmission_id),
+                }
+                // the requests of the stream end for the handler, which finds its context cancelled already
+                if let Some(sender) = self.open_streams.get_mut(&transmission_id) {
+                    sender.take();
+                }
+            }
+            Ok((transmission_id, _)) => {
+                let rejection = Rejection {
+                    transmission_id: Some(transmission_id),
+                    fault: transport::Fault::UnexpectedType,
+                    recoverable: true,
+                };
+                return self.reject(&rejection);
+            }
+            Err(rejection) => return self.reject(&rejection),
+        }
+        TransceiveLoopAction::Continue
+    }
+
+    fn execute(&mut self, transmission_id: u64, rpc: Req, deadline: Option<Instant>) {
+        self.state.request_count.fetch_add(1, Ordering::Relaxed);
+        let format = self.state.format;
+        let compression = self.state.compression;
+        let message_processing = self.message_processing.clone();
+        let writer = self.writer.clone();
+        let context = self.context(transmission_id, deadline);
+        let cancellations = self.cancellations.clone();
+        self.spawn(move || {
+            // the client gave up while the request waited for its execution, e.g. for a worker
+            let response = if context.is_expired() {
+                Err(transport::Fault::DeadlineExceeded)
+            } else if context.is_cancelled() {
+                Err(transport::Fault::Cancelled)
+            } else {
+                Ok(message_processing.execute_with_context(&context, rpc))
+            };
+            Self::respond(
+                &writer,
+                &format,
+                compression,
+                transmission_id,
+                &context,
+                &cancellations,
+                response,
+            );
+        });
+    }
+
+    // sends the outcome of an execution, or Fault::Cancelled if the client cancelled the request meanwhile
+    fn respond(
+        writer: &Mutex<Writer>,
+        format: &Format,
+        compression: Option<Compression>,
+        transmission_id: u64,
+        context: &Context,
+        cancellations: &Cancellations,
+        response: Result<Result<Resp, Error>, transport::Fault>,
+    ) {
+        // a Cancel which arrives after the removal is ignored
+        cancellations
+            .lock()
+            .expect("getting lock")
+            .remove(&transmission_id);
+        let response = match response {
+            Ok(_) if context.is_cancelled() => Err(transport::Fault::Cancelled),
+            response => response,
+        };
+        let mut writer = writer.lock().expect("getting lock");
+        match response {
+            Ok(response) => {
+                let _ = Server::<T>::send_response(
+                    &mut *writer,
+                    format,
+                    compression,
+                    transmission_id,
+                    response,
+                )
+                .map_err(|err| log::error!("transmission error: {:?}", err));
+            }
+            Err(fault) => {
+                let rejection = Rejection {
+                    transmission_id: Some(transmission_id),
+                    fault,
+                    recoverable: true,
+                };
+                Server::<T>::reject(&mut *writer, format, compression, &rejection);
+            }
+        }
+    }
+
+    // the cancellation of the context is cancelled by a Cancel transmission until the execution removes it
+    fn context(&self, transmission_id: u64, deadline: Option<Instant>) -> Context {
+        let cancellation = CancellationToken::default();
+        self.cancellations
+            .lock()
+            .expect("getting lock")
+            .insert(transmission_id, cancellation.clone());
+        Context {
+            connection_id: self.state.connection_id,
+            principal: self.state.principal.clone(),
+            deadline,
+            cancellation,
+        }
+    }
+
+    fn reject(&self, rejection: &Rejection) -> TransceiveLoopAction {
+        Server::<T>::reject(
+            &mut *self.writer.lock().expect("getting lock"),
+            &self.state.format,
+            self.state.compression,
+            rejection,
+        );
+        if !rejection.recoverable || self.fault_policy == FaultPolicy::CloseConnection {
+            TransceiveLoopAction::Stop
+        } else {
+            TransceiveLoopAction::Continue
+        }
+    }
+
+    fn spawn<F: FnOnce() + Send + 'static>(&mut self, execution: F) {
+        let finalizer = self.finalizer.clone();
+        let execution = move || {
+            execution();
+            drop(finalizer);
+        };
+        match &self.pool {
+            Some(pool) => pool.execute(execution),
+            None => {
+                self.executions.retain(|execution| !execution.is_finished());
+                self.executions.push(thread::spawn(execution));
+            }
+        }
+    }
+
+    // streams which were not ended by the client are ended now; the cleanup runs after the last execution
+    pub(crate) fn finish(mut self) {
+        self.open_streams.clear();
+        for execution in self.executions.drain(..) {
+            let _ = execution.join();
+        }
+    }
+}
+
+// a management connection; in single port mode the stream is handed over to the transceiver on Connect
+struct MgmtSession<'a, T: 'static + MessageProcessing> {
+    server: &'a Server<T>,
+    // the rpc listener is bound to the address on which the management connection arrived
+    local_address: Address,
+    peer: Peer,
+    peer_ip: IpAddr,
+    stream: Option<Stream>,
+    authentication: RefCell<Authentication>,
+}
+
+// the progress of the authentication on a management connection, also of the async server
+#[derive(Debug)]
+pub(crate) enum Authentication {
+    None,
+    // the client has to answer the challenge with its credentials
+    Challenged(Vec<u8>),
+    Authenticated(Option<String>),
+}
+
+impl<'a, T: 'static + MessageProcessing> MgmtSession<'a, T> {
+    // further requests are expected on the management connection
+    fn authenticating(&self) -> bool {
+        !matches!(*self.authentication.borrow(), Authentication::None)
+    }
+
+    fn authenticate(&self, credentials: &[u8]) -> mgmt::Response {
+        let (authentication, response) = authenticate(
+            &self.server.options,
+            &self.peer,
+            self.authentication.replace(Authentication::None),
+            credentials,
+        );
+        *self.authentication.borrow_mut() = authentication;
+        response
+    }
+}
+
+// the credentials answer the challenge of the preceding Authenticate request
+pub(crate) fn authenticate(
+    options: &ServerOptions,
+    peer: &Peer,
+    authentication: Authentication,
+    credentials: &[u8],
+) -> (Authentication, mgmt::Response) {
+    let principal = match (authentication, &options.authenticator) {
+        (Authentication::Challenged(challenge), Some(authenticator)) => {
+            authenticator.authenticate(peer, &challenge, credentials)
+        }
+        // without an authenticator any credentials are accepted
+        (Authentication::Challenged(_), None) => {
+            return (
+                Authentication::Authenticated(None),
+                mgmt::Response::Authenticated,
+            );
+        }
+        _ => None,
+    };
+    match principal {
+        Some(principal) => {
+            log::debug!("{} authenticated as {}", peer.address(), principal);
+            (
+                Authentication::Authenticated(Some(principal)),
+                mgmt::Response::Authenticated,
+            )
+        }
+        None => {
+            log::warn!("authentication of {} failed", peer.address());
+            (Authentication::None, mgmt::Response::AuthenticationFailed)
+        }
+    }
+}
+
+impl<'a, Req, Resp, Error, T> Executor for MgmtSession<'a, T>
 where
-    Req: DeserializeOwned,
-    Resp: Serialize,
-    Error: Serialize + std::fmt::Debug,
+    Req: DeserializeOwned + Send + 'static,
+    Resp: Serialize + 'static,
+    Error: Serialize + std::fmt::Debug + 'static,
     T: 'static + MessageProcessing<Rq = Req, Rsp = Resp, E = Error>,
 {
     type Rq = mgmt::Request;
@@ -260,7 +1835,10 @@ where
     type E = transport::Error;
     fn execute(&self, _connection_id: u32, rpc: Self::Rq) -> Result<Self::Rsp, Self::E> {
         match rpc {
-            mgmt::Request::Identify { protocol_version } => {
+            mgmt::Request::Identify {
+                protocol_version,
+                formats,
+            } => {
                 log::debug!("server::Identify request");
                 let server_protocol_version = ProtocolVersion::entity().version();
                 if server_protocol_version != protocol_version {
@@ -268,23 +1846,74 @@ where
                 }
                 Ok(mgmt::Response::Identify(mgmt::Identity {
                     protocol_version: server_protocol_version,
-                    service: self.service.clone(),
+                    service: self.server.service.clone(),
+                    format: Format::negotiate(&self.server.options.formats, &formats),
                 }))
             }
             mgmt::Request::Connect(params) => {
                 log::debug!("server::Connect request");
-                let port = self.connection_request(params.connection_id).unwrap();
-                Ok(mgmt::Response::Connect(mgmt::CommSettings {
-                    connection_id: params.connection_id,
-                    port,
-                }))
+                compatible_protocol_version(&params)?;
+                let principal = match self.authentication.replace(Authentication::None) {
+                    Authentication::Authenticated(principal) => principal,
+                    _ if self.server.options.authenticator.is_some() => {
+                        log::warn!(
+                            "unauthenticated connection request of {}",
+                            self.peer.address()
+                        );
+                        return Ok(mgmt::Response::AuthenticationFailed);
+                    }
+                    _ => None,
+                };
+                if !enables(&self.server.options, params.format) {
+                    log::warn!(
+                        "connection request with unsupported codec {}",
+                        params.format
+                    );
+                    return Ok(mgmt::Response::UnsupportedFormat(params.format));
+                }
+                let mgmt_stream = self.stream.as_ref().map(Stream::try_clone).transpose();
+                mgmt_stream
+                    .and_then(|mgmt_stream| {
+                        self.server.connection_request(
+                            &params,
+                            &self.local_address,
+                            self.peer_ip,
+                            mgmt_stream,
+                            principal,
+                        )
+                    })
+                    .map_err(|err| {
+                        log::error!("connection request: {:?}", err);
+                        format!("connection request failed: {}", err)
+                    })
             }
             mgmt::Request::CheckRunState => {
                 log::debug!("server::CheckRunState request");
                 Ok(mgmt::Response::CheckRunState)
             }
+            mgmt::Request::Authenticate => {
+                log::debug!("server::Authenticate request");
+                let challenge = self
+                    .server
+                    .options
+                    .authenticator
+                    .as_ref()
+                    .map_or_else(Vec::new, |authenticator| {
+                        authenticator.challenge(&self.peer)
+                    });
+                *self.authentication.borrow_mut() = Authentication::Challenged(challenge.clone());
+                Ok(mgmt::Response::Challenge(challenge))
+            }
+            mgmt::Request::Credentials(credentials) => {
+                log::debug!("server::Credentials request");
+                Ok(self.authenticate(&credentials))
+            }
         }
     }
+
+    fn answer_undecodable(&self, payload: &[u8]) -> Option<Vec<u8>> {
+        legacy_identity(payload, &self.server.service)
+    }
 }
 
 #[cfg(test)]
@@ -330,48 +1959,49 @@ mod tests {
         let listener = util::bind(ip, port)?;
 
         let th = thread::spawn(move || {
-            let serde = bincode::DefaultOptions::new()
-                .with_big_endian()
-                .with_fixint_encoding();
-
-            const EXPECTED_ETM_PROTOCOL_VERSION: u32 = 0;
-            let identify = transport::Transmission {
+            const EXPECTED_ETM_PROTOCOL_VERSION: u32 = 4;
+            let identify = transport::Transmission::<mgmt::Request> {
                 id: 0,
                 r#type: transport::Type::Request(mgmt::Request::Identify {
                     protocol_version: EXPECTED_ETM_PROTOCOL_VERSION,
+                    formats: vec![Format::Json, Format::Bincode],
                 }),
             };
-            let identify = serde.serialize(&identify).unwrap();
+            let identify = codec::Bincode.encode(&identify).unwrap();
 
             let addr = SocketAddr::from((ip, port));
-            if let Ok(mut stream) = TcpStream::connect_timeout(&addr, Duration::from_millis(100)) {
-                util::adjust_stream(&mut stream, Some(Duration::from_millis(100)))?;
-                util::write_transmission(&mut stream, identify)?;
+            if let Ok(mut stream) = Address::Tcp(addr).connect(Duration::from_millis(100)) {
+                util::adjust_stream(&stream, Some(Duration::from_millis(100)))?;
+                util::write_transmission(&mut stream, identify, None)?;
                 let payload_length = util::wait_for_transmission(&mut stream)?;
-                let response = util::read_transmission(&mut stream, payload_length)?;
-                let identity = serde
-                    .deserialize::<transport::Transmission<mgmt::Response>>(&response)
+                let response = util::read_transmission(
+                    &mut stream,
+                    payload_length,
+                    util::DEFAULT_MAX_FRAME_SIZE,
+                    None,
+                )?;
+                let identity = codec::Bincode
+                    .decode::<transport::Transmission<mgmt::Response>>(&response)
                     .unwrap();
                 match identity.r#type {
                     transport::Type::Response(mgmt::Response::Identify(identity)) => {
-                        assert!(identity.protocol_version == 0)
+                        assert!(identity.protocol_version == EXPECTED_ETM_PROTOCOL_VERSION);
+                        // the server prefers bincode by default
+                        assert_eq!(identity.format, Some(Format::Bincode));
                     }
-                    _ => assert!(false),
+                    _ => unreachable!(),
                 }
             }
 
             Ok::<(), io::Error>(())
         });
 
-        let stream = util::listener_accept_nonblocking(listener, Duration::from_millis(100))?;
+        let stream = Listener::Tcp(listener).accept_timeout(Duration::from_millis(100))?;
 
         let service = Service::entity("TestService".to_string(), 1);
         let server = Server::<DummyServer>::new(port, service);
 
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
-        server.handle_mgmt_request(stream, &serde)?;
+        server.handle_mgmt_request(stream)?;
 
         assert!(th.join().is_ok());
         Ok(())
@@ -0,0 +1,317 @@
+// the byte streams which carry the management requests and the rpcs; tcp or, on unix, unix domain sockets
+#[cfg(feature = "tls")]
+use crate::tls::TlsStream;
+use crate::util;
+
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    // wraps a tcp or unix stream
+    #[cfg(feature = "tls")]
+    Tls(TlsStream),
+}
+
+impl Stream {
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.try_clone().map(Stream::Tls),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.socket().set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.socket().set_nonblocking(nonblocking),
+        }
+    }
+
+    // a unix domain socket has no Nagle algorithm
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nodelay(nodelay),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(()),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.socket().set_nodelay(nodelay),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(how),
+            // sends the close_notify alert
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.shutdown(how),
+        }
+    }
+
+    // the peer of a unix domain socket is usually unnamed, therefore only tcp peers have an address
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().ok(),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.socket().peer_addr(),
+        }
+    }
+
+    // the address on which the connection arrived; for a unix domain socket the path of the listener
+    pub fn local_address(&self) -> io::Result<Address> {
+        match self {
+            Stream::Tcp(stream) => stream.local_addr().map(Address::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => unix_path(&stream.local_addr()?).map(Address::Unix),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.socket().local_address(),
+        }
+    }
+
+    // describes the peer for MessageProcessing::setup and cleanup
+    pub fn peer_description(&self) -> io::Result<String> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().map(|address| address.to_string()),
+            #[cfg(unix)]
+            Stream::Unix(_) => self.local_address().map(|address| address.to_string()),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.socket().peer_description(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+// like TcpStream, a shared stream can be read and written
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => (&*stream).read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => (&*stream).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => (&*stream).flush(),
+        }
+    }
+}
+
+// the address of a listener
+#[derive(Clone, Debug, PartialEq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Address {
+    pub fn connect(&self, timeout: Duration) -> io::Result<Stream> {
+        match self {
+            Address::Tcp(address) => TcpStream::connect_timeout(address, timeout).map(Stream::Tcp),
+            // connecting to a local socket doesn't block
+            #[cfg(unix)]
+            Address::Unix(path) => UnixStream::connect(path).map(Stream::Unix),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => write!(f, "{}", address),
+            #[cfg(unix)]
+            Address::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, SocketFile),
+}
+
+impl Listener {
+    // a stale socket file, e.g. of a crashed server, is replaced
+    #[cfg(unix)]
+    pub fn bind_unix(path: &Path) -> io::Result<Listener> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        Ok(Listener::Unix(
+            listener,
+            SocketFile {
+                path: path.to_path_buf(),
+            },
+        ))
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind_unix(_path: &Path) -> io::Result<Listener> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unix domain sockets are not supported on this platform",
+        ))
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                listener.accept().map(|(stream, _)| Stream::Unix(stream))
+            }
+        }
+    }
+
+    // waits for a connection within the timeout, the listener stays open for further connections
+    pub fn accept_timeout(&self, timeout: Duration) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => {
+                util::listener_accept_nonblocking(listener.try_clone()?, timeout).map(Stream::Tcp)
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                util::unix_listener_accept_nonblocking(listener.try_clone()?, timeout)
+                    .map(Stream::Unix)
+            }
+        }
+    }
+
+    pub fn local_address(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(Address::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(_, socket_file) => Ok(Address::Unix(socket_file.path.clone())),
+        }
+    }
+}
+
+// removes the file of a unix domain socket listener when the listener is dropped
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct SocketFile {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path)
+            .map_err(|err| log::warn!("removing socket {}: {:?}", self.path.display(), err));
+    }
+}
+
+#[cfg(unix)]
+fn unix_path(address: &std::os::unix::net::SocketAddr) -> io::Result<PathBuf> {
+    address
+        .as_pathname()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::AddrNotAvailable, "unnamed unix socket"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn unix_socket_file_removed() -> io::Result<()> {
+        let path = std::env::temp_dir().join(format!("etm-stream-{}.sock", std::process::id()));
+        let listener = Listener::bind_unix(&path)?;
+        assert!(path.exists());
+
+        let address = listener.local_address()?;
+        let client = thread::spawn(move || -> io::Result<()> {
+            let mut stream = address.connect(Duration::from_secs(1))?;
+            stream.write_all(b"etm")
+        });
+        let mut stream = listener.accept_timeout(Duration::from_secs(1))?;
+        let mut buffer = [0u8; 3];
+        stream.read_exact(&mut buffer)?;
+        assert_eq!(&buffer, b"etm");
+        assert!(matches!(client.join(), Ok(Ok(()))));
+        drop(listener);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn unix_accept_timeout() -> io::Result<()> {
+        let path = std::env::temp_dir().join(format!("etm-timeout-{}.sock", std::process::id()));
+        let listener = Listener::bind_unix(&path)?;
+        let accepted = listener.accept_timeout(Duration::from_millis(10));
+        assert!(matches!(accepted, Err(err) if err.kind() == io::ErrorKind::TimedOut));
+        Ok(())
+    }
+}
@@ -1,29 +1,54 @@
 use super::*;
+use crate::codec::Codec;
+use crate::stream::{Address, Stream};
 use crate::test_common::TEST_PORT_BASE;
 
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "tls")]
+use std::convert::TryFrom;
 use std::io;
-use std::net::Ipv4Addr;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
 
 struct DummyServer {
     shutdown_request: Arc<AtomicBool>,
+    cleanups: AtomicU32,
+    peers: Mutex<Vec<server::Peer>>,
+    notified: AtomicU32,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 enum DummyRequest {
     Ping,
+    Fail,
+    Sleep(u64),
+    Count(u32),
+    CountAndFail(u32),
+    Add(u32),
+    Echo,
+    ConnectionId,
+    Principal,
+    Remaining,
+    WaitForCancel,
+    Notified,
+    Blob(Vec<u8>),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 enum DummyResponse {
     Pong,
+    Slept,
+    Number(u32),
+    Principal(Option<String>),
+    Blob(Vec<u8>),
 }
 
 impl server::MessageProcessing for DummyServer {
@@ -34,11 +59,113 @@ impl server::MessageProcessing for DummyServer {
     fn new() -> Arc<Self> {
         Arc::new(DummyServer {
             shutdown_request: Arc::new(AtomicBool::new(false)),
+            cleanups: AtomicU32::new(0),
+            peers: Mutex::new(Vec::new()),
+            notified: AtomicU32::new(0),
         })
     }
 
-    fn execute(&self, _connection_id: u32, _rpc: Self::Rq) -> Result<Self::Rsp, Self::E> {
-        Ok(DummyResponse::Pong)
+    fn setup_peer(&self, peer: &server::Peer, _connection_id: u32) {
+        self.peers.lock().expect("getting lock").push(peer.clone());
+    }
+
+    fn execute(&self, connection_id: u32, rpc: Self::Rq) -> Result<Self::Rsp, Self::E> {
+        match rpc {
+            DummyRequest::Ping => Ok(DummyResponse::Pong),
+            DummyRequest::ConnectionId => Ok(DummyResponse::Number(connection_id)),
+            DummyRequest::Notified => {
+                Ok(DummyResponse::Number(self.notified.load(Ordering::SeqCst)))
+            }
+            DummyRequest::Blob(blob) => Ok(DummyResponse::Blob(blob)),
+            DummyRequest::Fail => Err("failed".to_string()),
+            DummyRequest::Sleep(ms) => {
+                thread::sleep(Duration::from_millis(ms));
+                Ok(DummyResponse::Slept)
+            }
+            _ => Err("not a unary request".to_string()),
+        }
+    }
+
+    fn execute_with_context(
+        &self,
+        context: &server::Context,
+        rpc: Self::Rq,
+    ) -> Result<Self::Rsp, Self::E> {
+        match rpc {
+            DummyRequest::Principal => Ok(DummyResponse::Principal(
+                context.principal().map(str::to_string),
+            )),
+            DummyRequest::Remaining => match context.remaining() {
+                Some(remaining) => Ok(DummyResponse::Number(remaining.as_millis() as u32)),
+                None => Err("no deadline".to_string()),
+            },
+            DummyRequest::WaitForCancel => {
+                let start = Instant::now();
+                while !context.is_cancelled() && start.elapsed() < Duration::from_secs(5) {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Ok(DummyResponse::Pong)
+            }
+            rpc => self.execute(context.connection_id(), rpc),
+        }
+    }
+
+    fn on_notification(&self, _context: &server::Context, rpc: Self::Rq) {
+        if let DummyRequest::Add(n) = rpc {
+            self.notified.fetch_add(n, Ordering::SeqCst);
+        }
+    }
+
+    fn execute_stream(
+        &self,
+        connection_id: u32,
+        rpc: Self::Rq,
+    ) -> Result<server::ResponseStream<Self::Rsp, Self::E>, Self::E> {
+        match rpc {
+            DummyRequest::Count(count) => {
+                Ok(Box::new((0..count).map(|n| Ok(DummyResponse::Number(n)))))
+            }
+            DummyRequest::CountAndFail(count) => Ok(Box::new(
+                (0..count)
+                    .map(|n| Ok(DummyResponse::Number(n)))
+                    .chain(std::iter::once(Err("failed".to_string()))),
+            )),
+            rpc => Ok(Box::new(std::iter::once(self.execute(connection_id, rpc)))),
+        }
+    }
+
+    fn execute_client_stream(
+        &self,
+        _connection_id: u32,
+        rpc: Self::Rq,
+        rpcs: server::RequestStream<Self::Rq>,
+    ) -> Result<Self::Rsp, Self::E> {
+        std::iter::once(rpc)
+            .chain(rpcs)
+            .try_fold(0, |sum, rpc| match rpc {
+                DummyRequest::Add(n) => Ok(sum + n),
+                _ => Err("not an addition".to_string()),
+            })
+            .map(DummyResponse::Number)
+    }
+
+    fn execute_bidi_stream(
+        &self,
+        connection_id: u32,
+        rpc: Self::Rq,
+        rpcs: server::RequestStream<Self::Rq>,
+    ) -> Result<server::ResponseStream<Self::Rsp, Self::E>, Self::E> {
+        match rpc {
+            DummyRequest::Echo => Ok(Box::new(rpcs.map(|rpc| match rpc {
+                DummyRequest::Add(n) => Ok(DummyResponse::Number(n)),
+                _ => Err("not an addition".to_string()),
+            }))),
+            rpc => self.execute_stream(connection_id, rpc),
+        }
+    }
+
+    fn cleanup(&self, _connection_info: String, _connection_id: u32) {
+        self.cleanups.fetch_add(1, Ordering::Relaxed);
     }
 
     fn shutdown(&self) -> bool {
@@ -48,60 +175,2796 @@ impl server::MessageProcessing for DummyServer {
 
 type Connection = client::Connection<DummyRequest, DummyResponse, String>;
 
-#[test]
-fn simple_request() -> io::Result<()> {
+fn connect(ip: Ipv4Addr, port: u16) -> Box<Connection> {
+    connect_with_id(ip, port, -1)
+}
+
+fn connect_with_id(ip: Ipv4Addr, port: u16, connection_id: i32) -> Box<Connection> {
+    const EXIT_FAILURE: i32 = 1;
+
+    let mut retries = 100;
+    loop {
+        if let Ok(connection) = Connection::new((ip, port), connection_id) {
+            break connection;
+        } else if retries > 0 {
+            retries -= 1;
+            thread::sleep(Duration::from_millis(10));
+        } else {
+            std::process::exit({
+                eprintln!("could not connect to server");
+                EXIT_FAILURE
+            });
+        }
+    }
+}
+
+fn shutdown(ip: Ipv4Addr, port: u16, shutdown_request: Arc<AtomicBool>) {
+    const EXIT_FAILURE: i32 = 1;
+
+    shutdown_request.store(true, Ordering::Relaxed);
+
+    let addr = Address::Tcp(SocketAddr::from((ip, port)));
+    match Connection::mgmt_transceive(&addr, mgmt::Request::CheckRunState, &Default::default()) {
+        Ok(mgmt::Response::CheckRunState) => (),
+        // the server already saw the shutdown request after the previous mgmt request and closed the listener
+        Err(Error::Io(_)) => (),
+        _ => std::process::exit({
+            eprintln!("requesting to check server run state failed");
+            EXIT_FAILURE
+        }),
+    }
+}
+
+// runs the server on the current thread and the client function on a separate thread
+fn run_with_client<F>(client: F) -> io::Result<()>
+where
+    F: FnOnce(Ipv4Addr, u16) + Send + 'static,
+{
+    run_with_options(Default::default(), client)
+}
+
+fn run_with_options<F>(options: server::ServerOptions, client: F) -> io::Result<()>
+where
+    F: FnOnce(Ipv4Addr, u16) + Send + 'static,
+{
     let ip = Ipv4Addr::UNSPECIFIED;
     let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
 
     let service = Service::entity("TestService".to_string(), 1);
 
-    let server = server::Server::<DummyServer>::new(port, service);
+    let server = server::Server::<DummyServer>::with_options(port, service, options);
 
     let th = {
         let shutdown_request = server.message_processing.shutdown_request.clone();
         thread::spawn(move || {
-            const EXIT_FAILURE: i32 = 1;
+            client(ip, port);
+            shutdown(ip, port, shutdown_request);
+        })
+    };
+
+    server.run()?;
+
+    assert!(th.join().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn simple_request() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+#[test]
+fn remote_error() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        assert!(matches!(
+            connection.transceive(DummyRequest::Fail),
+            Err(Error::Remote(err)) if err == "failed"
+        ));
+
+        // the connection is still usable after a remote error
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+#[test]
+fn connect_to_missing_server() {
+    let ip = Ipv4Addr::LOCALHOST;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    assert!(matches!(
+        Connection::new((ip, port), 1),
+        Err(Error::Io(_)) | Err(Error::Timeout)
+    ));
+}
+
+#[test]
+fn pipelined_requests() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        let slow = connection.send(DummyRequest::Sleep(200)).unwrap();
+        let fast = connection.send(DummyRequest::Ping).unwrap();
+        assert_eq!(fast.transmission_id(), slow.transmission_id() + 1);
+
+        // the fast request is not blocked by the slow one
+        let start = Instant::now();
+        assert!(matches!(fast.wait(), Ok(DummyResponse::Pong)));
+        assert!(start.elapsed() < Duration::from_millis(150));
+
+        assert!(matches!(slow.wait(), Ok(DummyResponse::Slept)));
+    })
+}
+
+#[test]
+fn concurrent_callers() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+        let connection = &*connection;
+
+        let start = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(move || {
+                    assert!(matches!(
+                        connection.transceive(DummyRequest::Sleep(100)),
+                        Ok(DummyResponse::Slept)
+                    ));
+                });
+            }
+        });
+        // the requests were executed concurrently by the server
+        assert!(start.elapsed() < Duration::from_millis(300));
+    })
+}
+
+#[test]
+fn server_stream() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        let numbers = connection
+            .transceive_stream(DummyRequest::Count(5))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>();
+        assert!(
+            matches!(numbers, Ok(numbers) if numbers == (0..5).map(DummyResponse::Number).collect::<Vec<_>>())
+        );
+
+        // an empty stream only consists of the End transmission
+        assert_eq!(
+            connection
+                .transceive_stream(DummyRequest::Count(0))
+                .unwrap()
+                .count(),
+            0
+        );
+
+        // the connection is still usable for regular requests
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+#[test]
+fn server_stream_error() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        let mut items = connection
+            .transceive_stream(DummyRequest::CountAndFail(2))
+            .unwrap();
+        assert!(matches!(items.next(), Some(Ok(DummyResponse::Number(0)))));
+        assert!(matches!(items.next(), Some(Ok(DummyResponse::Number(1)))));
+        assert!(matches!(items.next(), Some(Err(Error::Remote(err))) if err == "failed"));
+        assert!(items.next().is_none());
+    })
+}
+
+#[test]
+fn server_stream_of_unary_request() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        let mut items = connection.transceive_stream(DummyRequest::Ping).unwrap();
+        assert!(matches!(items.next(), Some(Ok(DummyResponse::Pong))));
+        assert!(items.next().is_none());
+    })
+}
+
+#[test]
+fn client_stream() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        assert!(matches!(
+            connection.transceive_client_stream((1..=4).map(DummyRequest::Add)),
+            Ok(DummyResponse::Number(10))
+        ));
+
+        let mut stream = connection.open_client_stream().unwrap();
+        assert!(stream.send(DummyRequest::Add(1)).is_ok());
+        assert!(stream.send(DummyRequest::Ping).is_ok());
+        assert!(matches!(stream.finish(), Err(Error::Remote(err)) if err == "not an addition"));
+
+        // a client stream must consist of at least one request
+        assert!(matches!(
+            connection.transceive_client_stream(Vec::new()),
+            Err(Error::Protocol(_))
+        ));
+
+        // the connection is still usable for regular requests
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+#[test]
+fn bidi_stream() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        let mut stream = connection.open_bidi_stream(DummyRequest::Echo).unwrap();
+        for n in 0..3 {
+            assert!(stream.send(DummyRequest::Add(n)).is_ok());
+            assert!(matches!(stream.next(), Some(Ok(DummyResponse::Number(m))) if m == n));
+        }
+        assert!(stream.close().is_ok());
+        assert!(stream.send(DummyRequest::Add(3)).is_err());
+        assert!(stream.next().is_none());
+    })
+}
+
+#[test]
+fn bidi_stream_split() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        let stream = connection.open_bidi_stream(DummyRequest::Echo).unwrap();
+        let (mut sender, responses) = stream.split();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for n in 0..100 {
+                    assert!(sender.send(DummyRequest::Add(n)).is_ok());
+                }
+                // dropping the sender ends the stream
+            });
+            let numbers = responses.collect::<Result<Vec<_>, _>>();
+            assert!(
+                matches!(numbers, Ok(numbers) if numbers == (0..100).map(DummyResponse::Number).collect::<Vec<_>>())
+            );
+        });
+    })
+}
+
+#[test]
+fn shutdown_handle() -> io::Result<()> {
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<DummyServer>::new(port, service);
+    let shutdown_handle = server.shutdown_handle();
+
+    let th = thread::spawn(move || {
+        let connection = connect(ip, port);
+        let pending = connection.send(DummyRequest::Sleep(200)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        assert!(!shutdown_handle.is_shutdown());
+        shutdown_handle.shutdown();
+        assert!(shutdown_handle.is_shutdown());
+
+        // the request in flight is finished before the connection is closed
+        assert!(matches!(pending.wait(), Ok(DummyResponse::Slept)));
+        assert!(connection.transceive(DummyRequest::Ping).is_err());
+    });
+
+    server.run()?;
+    assert_eq!(
+        server.message_processing.cleanups.load(Ordering::Relaxed),
+        1
+    );
+
+    assert!(th.join().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn shutdown_handle_with_pending_connect() -> io::Result<()> {
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<DummyServer>::new(port, service);
+    let shutdown_handle = server.shutdown_handle();
 
-            let mut retries = 100;
-            let mut connection = loop {
-                if let Some(connection) = Connection::new(ip, port, 1) {
-                    break connection;
-                } else if retries > 0 {
+    let th = thread::spawn(move || {
+        // the client never connects to the assigned port
+        let addr = Address::Tcp(SocketAddr::from((ip, port)));
+        let connect = || {
+            mgmt::Request::Connect(mgmt::CommParams {
+                protocol_version: ProtocolVersion::entity().version(),
+                connection_id: u32::MAX,
+                rpc_interval_timeout_ms: u32::MAX,
+                format: codec::Format::Bincode,
+                compression: Vec::new(),
+            })
+        };
+        let mut retries = 100;
+        while Connection::mgmt_transceive(&addr, connect(), &Default::default()).is_err()
+            && retries > 0
+        {
+            retries -= 1;
+            thread::sleep(Duration::from_millis(10));
+        }
+        shutdown_handle.shutdown();
+    });
+
+    let start = Instant::now();
+    server.run()?;
+    // the transceiver waiting for the client is stopped before the accept timeout
+    assert!(start.elapsed() < Duration::from_millis(1500));
+    assert_eq!(
+        server.message_processing.cleanups.load(Ordering::Relaxed),
+        0
+    );
+
+    assert!(th.join().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn shutdown_with_silent_mgmt_client() -> io::Result<()> {
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<DummyServer>::new(port, service);
+    let shutdown_handle = server.shutdown_handle();
+
+    let th = thread::spawn(move || {
+        // the client connects to the management port but never sends a request
+        let silent = mgmt_stream(ip, port);
+        thread::sleep(Duration::from_millis(100));
+        shutdown_handle.shutdown();
+        silent
+    });
+
+    let start = Instant::now();
+    server.run()?;
+    // the server stops waiting for the request after the management request timeout
+    assert!(start.elapsed() < Duration::from_secs(4));
+
+    assert!(th.join().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn connection_registry() -> io::Result<()> {
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<DummyServer>::new(port, service);
+    let shutdown_handle = server.shutdown_handle();
+
+    thread::scope(|scope| {
+        let runner = scope.spawn(|| server.run());
+
+        let start = SystemTime::now();
+        let connection = connect(ip, port);
+        assert!(connection.transceive(DummyRequest::Ping).is_ok());
+        assert!(connection.transceive(DummyRequest::Ping).is_ok());
+
+        let connections = server.connections();
+        assert_eq!(connections.len(), 1);
+        let info = &connections[0];
+        assert_eq!(info.id(), connection.id());
+        assert_eq!(info.port(), connection.port());
+        assert!(info.peer_address().is_some());
+        assert!(info.start_time() >= start && info.start_time() <= SystemTime::now());
+        assert_eq!(info.request_count(), 2);
+
+        // the connection is removed from the registry once it is closed
+        drop(connection);
+        let mut retries = 100;
+        while !server.connections().is_empty() && retries > 0 {
+            retries -= 1;
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(server.connections().is_empty());
+
+        shutdown_handle.shutdown();
+        assert!(matches!(runner.join(), Ok(Ok(()))));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn dynamic_connection_ids() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let first = connect(ip, port);
+        let second = connect(ip, port);
+        assert_ne!(first.id(), second.id());
+
+        assert!(matches!(
+            first.transceive(DummyRequest::ConnectionId),
+            Ok(DummyResponse::Number(id)) if id == first.id()
+        ));
+        assert!(matches!(
+            second.transceive(DummyRequest::ConnectionId),
+            Ok(DummyResponse::Number(id)) if id == second.id()
+        ));
+    })
+}
+
+#[test]
+fn requested_connection_id() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect_with_id(ip, port, 7);
+        assert_eq!(connection.id(), 7);
+        assert!(matches!(
+            connection.transceive(DummyRequest::ConnectionId),
+            Ok(DummyResponse::Number(7))
+        ));
+
+        assert!(matches!(
+            Connection::new((ip, port), 7),
+            Err(Error::ConnectionIdInUse(7))
+        ));
+
+        // the connection id can be reused after the connection is closed
+        drop(connection);
+        let mut retries = 100;
+        let connection = loop {
+            match Connection::new((ip, port), 7) {
+                Err(Error::ConnectionIdInUse(_)) if retries > 0 => {
                     retries -= 1;
                     thread::sleep(Duration::from_millis(10));
-                } else {
-                    std::process::exit({
-                        eprintln!("could not connect to server");
-                        EXIT_FAILURE
-                    });
                 }
-            };
+                connection => break connection,
+            }
+        };
+        assert!(matches!(connection, Ok(connection) if connection.id() == 7));
+    })
+}
 
-            assert_eq!(
-                connection.transceive(DummyRequest::Ping),
-                Some(DummyResponse::Pong)
-            );
+#[test]
+fn rpc_interval_timeout() -> io::Result<()> {
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
 
-            // shutdown server
-            shutdown_request.store(true, Ordering::Relaxed);
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<DummyServer>::new(port, service);
+    let shutdown_handle = server.shutdown_handle();
 
-            let addr = SocketAddr::from((ip, port));
-            if Connection::mgmt_transceive(&addr, mgmt::Request::CheckRunState)
-                != Some(mgmt::Response::CheckRunState)
-            {
-                std::process::exit({
-                    eprintln!("requesting to check server run state failed");
-                    EXIT_FAILURE
-                });
+    thread::scope(|scope| {
+        let runner = scope.spawn(|| server.run());
+
+        let options = client::ConnectionOptions {
+            rpc_interval_timeout: Some(Duration::from_millis(100)),
+            keep_alive: false,
+            ..Default::default()
+        };
+        let connection = loop {
+            if let Ok(connection) = Connection::with_options((ip, port), -1, options.clone()) {
+                break connection;
             }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(connection.transceive(DummyRequest::Ping).is_ok());
 
-            Ok::<(), io::Error>(())
-        })
+        // the server closes the idle connection
+        let mut retries = 100;
+        while !server.connections().is_empty() && retries > 0 {
+            retries -= 1;
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(server.connections().is_empty());
+        assert_eq!(server.message_processing.cleanups.load(Ordering::SeqCst), 1);
+        assert!(connection.transceive(DummyRequest::Ping).is_err());
+
+        shutdown_handle.shutdown();
+        assert!(matches!(runner.join(), Ok(Ok(()))));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn rpc_interval_timeout_with_keep_alive() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let options = client::ConnectionOptions {
+            rpc_interval_timeout: Some(Duration::from_millis(100)),
+            keep_alive: true,
+            ..Default::default()
+        };
+        let connection = loop {
+            if let Ok(connection) = Connection::with_options((ip, port), -1, options.clone()) {
+                break connection;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        thread::sleep(Duration::from_millis(400));
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+fn reconnecting(max_attempts: u32) -> client::ConnectionOptions {
+    client::ConnectionOptions {
+        reconnect: Some(client::ReconnectPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_millis(100),
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn reconnect_after_rpc_interval_timeout() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let options = client::ConnectionOptions {
+            rpc_interval_timeout: Some(Duration::from_millis(100)),
+            keep_alive: false,
+            ..reconnecting(10)
+        };
+        let connection = connect_with_id_and_options(ip, port, 7, options);
+        assert!(connection.transceive(DummyRequest::Ping).is_ok());
+
+        // the server closes the idle connection, the request is sent on the reestablished connection
+        thread::sleep(Duration::from_millis(300));
+        assert!(matches!(
+            connection.transceive(DummyRequest::ConnectionId),
+            Ok(DummyResponse::Number(7))
+        ));
+        assert_eq!(connection.id(), 7);
+    })
+}
+
+// forwards the connections to the server until they are cut, e.g. to lose a connection with a request in flight
+struct Proxy {
+    port: u16,
+    streams: Arc<Mutex<Vec<std::net::TcpStream>>>,
+}
+
+impl Proxy {
+    fn new(ip: Ipv4Addr, server_port: u16) -> Proxy {
+        let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+        let listener = std::net::TcpListener::bind((ip, port)).unwrap();
+        let streams = Arc::new(Mutex::new(Vec::new()));
+        {
+            let streams = streams.clone();
+            thread::spawn(move || {
+                for client in listener.incoming().flatten() {
+                    let server = match std::net::TcpStream::connect((ip, server_port)) {
+                        Ok(server) => server,
+                        Err(_) => continue,
+                    };
+                    for (mut from, mut to) in [
+                        (client.try_clone().unwrap(), server.try_clone().unwrap()),
+                        (server.try_clone().unwrap(), client.try_clone().unwrap()),
+                    ] {
+                        thread::spawn(move || {
+                            let _ = io::copy(&mut from, &mut to);
+                            let _ = to.shutdown(std::net::Shutdown::Write);
+                        });
+                    }
+                    streams.lock().unwrap().extend([client, server]);
+                }
+            });
+        }
+        Proxy { port, streams }
+    }
+
+    fn cut(&self) {
+        for stream in self.streams.lock().unwrap().drain(..) {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+}
+
+#[test]
+fn reconnect_with_request_in_flight() -> io::Result<()> {
+    // the rpcs use the proxied management connection
+    let options = server::ServerOptions {
+        single_port: true,
+        ..Default::default()
     };
+    run_with_options(options, |ip, port| {
+        let proxy = Proxy::new(ip, port);
+        let connection = connect_with_id_and_options(ip, proxy.port, 7, reconnecting(20));
+        assert!(connection.transceive(DummyRequest::Ping).is_ok());
 
-    server.run()?;
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                proxy.cut();
+            });
+            assert!(matches!(
+                connection.transceive(DummyRequest::Sleep(300)),
+                Err(Error::ConnectionLost {
+                    maybe_executed: true,
+                    reconnected: true
+                })
+            ));
+        });
+        assert!(matches!(
+            connection.transceive(DummyRequest::ConnectionId),
+            Ok(DummyResponse::Number(7))
+        ));
+    })
+}
 
-    assert!(th.join().is_ok());
+#[test]
+fn reconnect_after_server_restart() -> io::Result<()> {
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<DummyServer>::new(port, service.clone());
+    let shutdown_handle = server.shutdown_handle();
+    let runner = thread::spawn(move || server.run());
+
+    let connection = connect_with_id_and_options(ip, port, 7, reconnecting(10));
+    assert!(connection.transceive(DummyRequest::Ping).is_ok());
+
+    shutdown_handle.shutdown();
+    assert!(matches!(runner.join(), Ok(Ok(()))));
+    // the client notices the closed connection before the next request
+    thread::sleep(Duration::from_millis(50));
+
+    // the request is not sent while the server is down
+    assert!(matches!(
+        connection.transceive(DummyRequest::Ping),
+        Err(Error::ConnectionLost {
+            maybe_executed: false,
+            reconnected: false
+        })
+    ));
+
+    let server = server::Server::<DummyServer>::new(port, service);
+    let shutdown_handle = server.shutdown_handle();
+    let runner = thread::spawn(move || server.run());
+
+    assert!(matches!(
+        connection.transceive(DummyRequest::ConnectionId),
+        Ok(DummyResponse::Number(7))
+    ));
+    assert!(connection.compatibility_check(Service::entity("TestService".to_string(), 1)));
+
+    shutdown_handle.shutdown();
+    assert!(matches!(runner.join(), Ok(Ok(()))));
+
+    Ok(())
+}
+
+#[test]
+fn reconnect_to_incompatible_service() -> io::Result<()> {
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<DummyServer>::new(port, service);
+    let shutdown_handle = server.shutdown_handle();
+    let runner = thread::spawn(move || server.run());
+
+    let connection = connect_with_options(ip, port, reconnecting(10));
+    assert!(connection.transceive(DummyRequest::Ping).is_ok());
+
+    shutdown_handle.shutdown();
+    assert!(matches!(runner.join(), Ok(Ok(()))));
+    thread::sleep(Duration::from_millis(50));
+
+    // the connection is not resumed with a service of another protocol version
+    let service = Service::entity("TestService".to_string(), 2);
+    let server = server::Server::<DummyServer>::new(port, service);
+    let shutdown_handle = server.shutdown_handle();
+    let runner = thread::spawn(move || server.run());
+
+    assert!(matches!(
+        connection.transceive(DummyRequest::Ping),
+        Err(Error::ConnectionLost {
+            maybe_executed: false,
+            reconnected: false
+        })
+    ));
+
+    shutdown_handle.shutdown();
+    assert!(matches!(runner.join(), Ok(Ok(()))));
+
+    Ok(())
+}
+
+type ConnectionPool = pool::ConnectionPool<DummyRequest, DummyResponse, String>;
+
+#[test]
+fn connection_pool() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let options = pool::PoolOptions {
+            max_size: 2,
+            lease_timeout: Some(Duration::from_millis(100)),
+            // every idle connection is checked before it is leased again
+            health_check_interval: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let pool = ConnectionPool::new((ip, port), options).unwrap();
+        // the connections are opened lazily
+        assert_eq!(pool.size(), 0);
+
+        let first = pool.lease().unwrap();
+        let second = pool.lease().unwrap();
+        assert_ne!(first.id(), second.id());
+        assert_eq!(pool.size(), 2);
+        assert!(matches!(pool.lease(), Err(Error::Timeout)));
+
+        // a returned connection is reused
+        let id = first.id();
+        drop(first);
+        assert_eq!(pool.idle(), 1);
+        let first = pool.lease().unwrap();
+        assert_eq!(first.id(), id);
+        assert!(first.check_run_state().is_ok());
+        drop((first, second));
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..10 {
+                        let connection = pool.lease().unwrap();
+                        assert!(matches!(
+                            connection.transceive(DummyRequest::Ping),
+                            Ok(DummyResponse::Pong)
+                        ));
+                    }
+                });
+            }
+        });
+        assert_eq!(pool.size(), 2);
+        assert_eq!(pool.idle(), 2);
+
+        // an evicted connection is replaced by a new one
+        pool.lease().unwrap().evict();
+        assert_eq!(pool.size(), 1);
+    })
+}
+
+#[test]
+fn connection_pool_evicts_closed_connections() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let options = pool::PoolOptions {
+            max_size: 1,
+            connection: client::ConnectionOptions {
+                rpc_interval_timeout: Some(Duration::from_millis(100)),
+                keep_alive: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let pool = ConnectionPool::new((ip, port), options).unwrap();
+        {
+            let connection = pool.lease().unwrap();
+            assert!(connection.transceive(DummyRequest::Ping).is_ok());
+        }
+
+        // the server closes the idle connection
+        thread::sleep(Duration::from_millis(300));
+        let connection = pool.lease().unwrap();
+        assert!(!connection.is_closed());
+        assert!(matches!(
+            connection.transceive(DummyRequest::ConnectionId),
+            Ok(DummyResponse::Number(new_id)) if new_id == connection.id()
+        ));
+        assert_eq!(pool.size(), 1);
+    })
+}
+
+#[test]
+fn deadlines() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert!(matches!(
+            connection.transceive_with_deadline(DummyRequest::Remaining, deadline),
+            Ok(DummyResponse::Number(remaining)) if remaining > 0 && remaining <= 1000
+        ));
+        assert!(matches!(
+            connection.transceive(DummyRequest::Remaining),
+            Err(Error::Remote(err)) if err == "no deadline"
+        ));
+
+        // the client doesn't wait for the response beyond the deadline
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(100);
+        assert!(matches!(
+            connection.transceive_with_deadline(DummyRequest::Sleep(300), deadline),
+            Err(Error::Timeout)
+        ));
+        assert!(start.elapsed() < Duration::from_millis(300));
+
+        // an expired deadline isn't sent, and the late response is discarded
+        assert!(matches!(
+            connection.transceive_with_deadline(DummyRequest::Ping, Instant::now()),
+            Err(Error::Timeout)
+        ));
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+#[test]
+fn deadline_exceeded_before_execution() -> io::Result<()> {
+    run_with_options(worker_pool(1), |ip, port| {
+        let connection = connect(ip, port);
+
+        // the request waits for the only worker beyond its deadline
+        let slow = connection.send(DummyRequest::Sleep(200)).unwrap();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let expired = connection
+            .send_with_deadline(DummyRequest::Ping, deadline)
+            .unwrap();
+
+        assert!(matches!(
+            expired.wait(),
+            Err(Error::Rejected(transport::Fault::DeadlineExceeded))
+        ));
+        assert!(matches!(slow.wait(), Ok(DummyResponse::Slept)));
+    })
+}
+
+#[test]
+fn cancel_request() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        let start = Instant::now();
+        let pending = connection.send(DummyRequest::WaitForCancel).unwrap();
+        assert!(pending.cancel().is_ok());
+        assert!(matches!(pending.wait(), Err(Error::Cancelled)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // the waiting caller is blocked, another thread cancels the request
+        let pending = connection.send(DummyRequest::WaitForCancel).unwrap();
+        let canceller = pending.canceller();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                assert!(canceller.cancel().is_ok());
+            });
+            assert!(matches!(pending.wait(), Err(Error::Cancelled)));
+        });
+
+        // the cancel of an answered request is ignored
+        let pending = connection.send(DummyRequest::Ping).unwrap();
+        let canceller = pending.canceller();
+        assert!(matches!(pending.wait(), Ok(DummyResponse::Pong)));
+        assert!(canceller.cancel().is_ok());
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+#[test]
+fn cancel_stream() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        let count = 1_000_000;
+        let mut responses = connection
+            .transceive_stream(DummyRequest::Count(count))
+            .unwrap();
+        assert!(matches!(
+            responses.next(),
+            Some(Ok(DummyResponse::Number(0)))
+        ));
+        assert!(responses.cancel().is_ok());
+
+        // the items sent before the cancel arrived are received
+        let mut received = 1;
+        let last = loop {
+            match responses.next() {
+                Some(Ok(_)) => received += 1,
+                last => break last,
+            }
+        };
+        assert!(matches!(last, Some(Err(Error::Cancelled))));
+        assert!(received < count);
+        assert!(responses.next().is_none());
+    })
+}
+
+#[test]
+fn cancel_client_stream() -> io::Result<()> {
+    run_with_options(worker_pool(1), |ip, port| {
+        let connection = connect(ip, port);
+
+        // the handler waits for further requests until the cancel ends them
+        let start = Instant::now();
+        let mut stream = connection.open_client_stream().unwrap();
+        assert!(stream.send(DummyRequest::Add(1)).is_ok());
+        assert!(stream.send(DummyRequest::Add(2)).is_ok());
+        assert!(stream.cancel().is_ok());
+        // requests after the cancel are discarded
+        assert!(stream.send(DummyRequest::Add(3)).is_ok());
+        assert!(matches!(stream.finish(), Err(Error::Cancelled)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // the end of the cancelled stream doesn't close the connection, and the worker is free again
+        assert!(matches!(
+            connection.transceive_client_stream((1..=4).map(DummyRequest::Add)),
+            Ok(DummyResponse::Number(10))
+        ));
+    })
+}
+
+#[test]
+fn notifications() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+
+        for n in 1..=100 {
+            assert!(connection.notify(DummyRequest::Add(n)).is_ok());
+        }
+        // the server doesn't respond, not even to a failing notification
+        assert!(connection.notify(DummyRequest::Fail).is_ok());
+
+        // the notifications are executed concurrently with the requests
+        let start = Instant::now();
+        loop {
+            match connection.transceive(DummyRequest::Notified) {
+                Ok(DummyResponse::Number(5050)) => break,
+                Ok(DummyResponse::Number(_)) if start.elapsed() < Duration::from_secs(1) => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                unexpected => panic!("unexpected response: {:?}", unexpected),
+            }
+        }
+    })
+}
+
+// a service with an error type which the encoding of a fault could be mistaken for
+struct CodedServer;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum ErrorCode {
+    NotFound,
+    Denied,
+    Busy,
+    Unavailable,
+    Aborted,
+}
+
+impl server::MessageProcessing for CodedServer {
+    type Rq = DummyRequest;
+    type Rsp = DummyResponse;
+    type E = ErrorCode;
+
+    fn new() -> Arc<Self> {
+        Arc::new(CodedServer)
+    }
+
+    fn execute(&self, _connection_id: u32, rpc: Self::Rq) -> Result<Self::Rsp, Self::E> {
+        match rpc {
+            DummyRequest::Ping => Ok(DummyResponse::Pong),
+            DummyRequest::Sleep(ms) => {
+                thread::sleep(Duration::from_millis(ms));
+                Ok(DummyResponse::Slept)
+            }
+            _ => Err(ErrorCode::Aborted),
+        }
+    }
+
+    fn execute_with_context(
+        &self,
+        context: &server::Context,
+        rpc: Self::Rq,
+    ) -> Result<Self::Rsp, Self::E> {
+        match rpc {
+            DummyRequest::WaitForCancel => {
+                let start = Instant::now();
+                while !context.is_cancelled() && start.elapsed() < Duration::from_secs(5) {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(ErrorCode::Busy)
+            }
+            rpc => self.execute(context.connection_id(), rpc),
+        }
+    }
+}
+
+fn run_coded_server<F>(options: server::ServerOptions, client: F) -> io::Result<()>
+where
+    F: FnOnce(Ipv4Addr, u16) + Send + 'static,
+{
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<CodedServer>::with_options(port, service, options);
+    let shutdown_handle = server.shutdown_handle();
+
+    let th = thread::spawn(move || {
+        client(ip, port);
+        shutdown_handle.shutdown();
+    });
+
+    server.run()?;
+
+    assert!(th.join().is_ok());
+
+    Ok(())
+}
+
+type CodedConnection = client::Connection<DummyRequest, DummyResponse, ErrorCode>;
+
+fn connect_coded(ip: Ipv4Addr, port: u16) -> Box<CodedConnection> {
+    let mut retries = 100;
+    loop {
+        match CodedConnection::new((ip, port), -1) {
+            Ok(connection) => break connection,
+            Err(_) if retries > 0 => {
+                retries -= 1;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(err) => panic!("could not connect to server: {:?}", err),
+        }
+    }
+}
+
+#[test]
+fn faults_with_enum_error() -> io::Result<()> {
+    run_coded_server(worker_pool(1), |ip, port| {
+        let connection = connect_coded(ip, port);
+
+        // the request waits for the only worker beyond its deadline
+        let slow = connection.send(DummyRequest::Sleep(200)).unwrap();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let expired = connection
+            .send_with_deadline(DummyRequest::Ping, deadline)
+            .unwrap();
+        assert!(matches!(
+            expired.wait(),
+            Err(Error::Rejected(transport::Fault::DeadlineExceeded))
+        ));
+        assert!(matches!(slow.wait(), Ok(DummyResponse::Slept)));
+
+        // a fault isn't decoded as an error code
+        let pending = connection.send(DummyRequest::WaitForCancel).unwrap();
+        assert!(pending.cancel().is_ok());
+        assert!(matches!(pending.wait(), Err(Error::Cancelled)));
+
+        assert!(matches!(
+            connection.transceive(DummyRequest::Fail),
+            Err(Error::Remote(ErrorCode::Aborted))
+        ));
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn faults_with_json() -> io::Result<()> {
+    let options = server::ServerOptions {
+        formats: vec![codec::Format::Json],
+        ..Default::default()
+    };
+    run_with_options(options, |ip, port| {
+        let connection = connect(ip, port);
+        assert_eq!(connection.format(), codec::Format::Json);
+
+        // a fault isn't decoded as the string error of the service
+        let pending = connection.send(DummyRequest::WaitForCancel).unwrap();
+        assert!(pending.cancel().is_ok());
+        assert!(matches!(pending.wait(), Err(Error::Cancelled)));
+
+        assert!(matches!(
+            connection.transceive(DummyRequest::Fail),
+            Err(Error::Remote(err)) if err == "failed"
+        ));
+    })
+}
+
+#[test]
+fn single_port() -> io::Result<()> {
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let options = server::ServerOptions {
+        single_port: true,
+        ..Default::default()
+    };
+    let server = server::Server::<DummyServer>::with_options(port, service, options);
+    let shutdown_handle = server.shutdown_handle();
+
+    thread::scope(|scope| {
+        let runner = scope.spawn(|| server.run());
+
+        let first = connect(ip, port);
+        let second = connect(ip, port);
+        assert_eq!(first.port(), port);
+        assert_eq!(second.port(), port);
+        assert_ne!(first.id(), second.id());
+
+        assert!(matches!(
+            first.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+        assert!(matches!(
+            second.transceive(DummyRequest::ConnectionId),
+            Ok(DummyResponse::Number(id)) if id == second.id()
+        ));
+        let numbers = first
+            .transceive_stream(DummyRequest::Count(3))
+            .map(|stream| stream.collect::<Vec<_>>());
+        assert!(matches!(numbers, Ok(numbers) if numbers.len() == 3));
+
+        let connections = server.connections();
+        assert_eq!(connections.len(), 2);
+        assert!(connections.iter().all(|info| info.port() == port));
+
+        shutdown_handle.shutdown();
+        assert!(matches!(runner.join(), Ok(Ok(()))));
+    });
+
+    Ok(())
+}
+
+// a raw connection to the management port; the server may not be listening yet
+fn mgmt_stream(ip: Ipv4Addr, port: u16) -> Stream {
+    let addr = Address::Tcp(SocketAddr::from((ip, port)));
+    let mut retries = 100;
+    loop {
+        match addr.connect(Duration::from_secs(2)) {
+            Ok(stream) => break stream,
+            Err(_) if retries > 0 => {
+                retries -= 1;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(err) => panic!("could not connect to server: {:?}", err),
+        }
+    }
+}
+
+// a raw rpc connection to the server
+fn rpc_stream(ip: Ipv4Addr, port: u16) -> Stream {
+    compressed_rpc_stream(ip, port, Vec::new()).0
+}
+
+// like rpc_stream, offering the compression algorithms; returns the compression chosen by the server
+fn compressed_rpc_stream(
+    ip: Ipv4Addr,
+    port: u16,
+    offered: Vec<compression::Algorithm>,
+) -> (Stream, Option<compression::Compression>) {
+    negotiated_rpc_stream(ip, port, codec::Format::Bincode, offered)
+}
+
+fn negotiated_rpc_stream(
+    ip: Ipv4Addr,
+    port: u16,
+    format: codec::Format,
+    offered: Vec<compression::Algorithm>,
+) -> (Stream, Option<compression::Compression>) {
+    let (port, compression, rpc_token) = negotiate_rpc_endpoint(ip, port, format, offered);
+    let addr = Address::Tcp(SocketAddr::from((ip, port)));
+    let mut stream = addr.connect(Duration::from_secs(2)).unwrap();
+    stream.write_all(&rpc_token.0).unwrap();
+    util::adjust_stream(&stream, Some(Duration::from_secs(2))).unwrap();
+    (stream, compression)
+}
+
+// sends Connect on the management port; returns the rpc port, the chosen compression and the rpc token
+fn negotiate_rpc_endpoint(
+    ip: Ipv4Addr,
+    port: u16,
+    format: codec::Format,
+    offered: Vec<compression::Algorithm>,
+) -> (u16, Option<compression::Compression>, mgmt::RpcToken) {
+    let addr = Address::Tcp(SocketAddr::from((ip, port)));
+    let connect = || {
+        mgmt::Request::Connect(mgmt::CommParams {
+            protocol_version: ProtocolVersion::entity().version(),
+            connection_id: mgmt::DYNAMIC_CONNECTION_ID,
+            rpc_interval_timeout_ms: mgmt::INFINITE_RPC_INTERVAL_TIMEOUT,
+            format,
+            compression: offered.clone(),
+        })
+    };
+    // the server may not be listening yet
+    let mut retries = 100;
+    loop {
+        match Connection::mgmt_transceive(&addr, connect(), &Default::default()) {
+            Ok(mgmt::Response::Connect(mgmt::CommSettings {
+                endpoint: mgmt::RpcEndpoint::Port(port),
+                compression,
+                rpc_token,
+                ..
+            })) => break (port, compression, rpc_token),
+            Err(_) if retries > 0 => {
+                retries -= 1;
+                thread::sleep(Duration::from_millis(10));
+            }
+            response => panic!("unexpected response to Connect: {:?}", response),
+        }
+    }
+}
+
+// a client which connects to the rpc port of another client first is refused, the other client is served;
+// the servers answer Ping with Pong
+pub(crate) fn rpc_impostor_refused(ip: Ipv4Addr, port: u16) {
+    let (rpc_port, _, rpc_token) =
+        negotiate_rpc_endpoint(ip, port, codec::Format::Bincode, Vec::new());
+    let addr = Address::Tcp(SocketAddr::from((ip, rpc_port)));
+
+    let mut impostor = addr.connect(Duration::from_secs(2)).unwrap();
+    impostor.write_all(&[0u8; mgmt::RPC_TOKEN_SIZE]).unwrap();
+    impostor
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let refused = impostor.read(&mut [0u8; 1]);
+    assert!(
+        matches!(&refused, Ok(0))
+            || matches!(&refused, Err(err) if err.kind() == io::ErrorKind::ConnectionReset),
+        "impostor not refused: {:?}",
+        refused
+    );
+
+    let mut stream = addr.connect(Duration::from_secs(2)).unwrap();
+    stream.write_all(&rpc_token.0).unwrap();
+    util::adjust_stream(&stream, Some(Duration::from_secs(2))).unwrap();
+    let request = codec::Bincode
+        .encode(&transport::Transmission::<DummyRequest> {
+            id: 1,
+            r#type: transport::Type::Request(DummyRequest::Ping),
+        })
+        .unwrap();
+    util::write_transmission(&mut stream, request, None).unwrap();
+    let payload_size = util::wait_for_transmission(&mut stream).unwrap();
+    let response =
+        util::read_transmission(&mut stream, payload_size, DEFAULT_MAX_FRAME_SIZE, None).unwrap();
+    assert!(matches!(
+        codec::Bincode.decode::<transport::Transmission<DummyResponse>>(&response),
+        Ok(transport::Transmission {
+            id: 1,
+            r#type: transport::Type::Response(DummyResponse::Pong),
+        })
+    ));
+}
+
+#[test]
+fn rpc_impostor() -> io::Result<()> {
+    run_with_client(rpc_impostor_refused)
+}
+
+// transmits a raw length prefix followed by a transmission id and returns the response of the server
+fn transmit_length_prefix(stream: &mut Stream, length: u64) -> io::Result<Vec<u8>> {
+    const TRANSMISSION_ID: u64 = 42;
+    let mut frame = length.to_be_bytes().to_vec();
+    frame.extend(TRANSMISSION_ID.to_be_bytes());
+    stream.write_all(&frame)?;
+
+    let payload_size = util::wait_for_transmission(stream)?;
+    util::read_transmission(stream, payload_size, DEFAULT_MAX_FRAME_SIZE, None)
+}
+
+fn is_frame_size_error(response: &[u8]) -> bool {
+    matches!(
+        codec::Bincode.decode::<transport::Transmission<()>>(response),
+        Ok(transport::Transmission {
+            id: 42,
+            r#type: transport::Type::Fault(transport::Fault::FrameSizeExceeded { .. })
+        })
+    )
+}
+
+#[test]
+fn malicious_length_prefix_on_rpc_connection() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let mut stream = rpc_stream(ip, port);
+        let response = transmit_length_prefix(&mut stream, u64::MAX);
+        assert!(matches!(response, Ok(response) if is_frame_size_error(&response)));
+
+        // the server closes the offending connection
+        assert!(util::wait_for_transmission(&mut stream).is_err());
+    })
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn malicious_length_prefix_with_json() -> io::Result<()> {
+    for options in [Default::default(), worker_pool(1)] {
+        let options = server::ServerOptions {
+            formats: vec![codec::Format::Json],
+            ..options
+        };
+        run_with_options(options, |ip, port| {
+            // the id of a json transmission isn't readable without its payload, the connection is closed unanswered
+            let (mut stream, _) = negotiated_rpc_stream(ip, port, codec::Format::Json, Vec::new());
+            assert!(transmit_length_prefix(&mut stream, u64::MAX).is_err());
+        })?;
+    }
+    Ok(())
+}
+
+#[test]
+fn malicious_length_prefix_on_mgmt_connection() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let mut stream = mgmt_stream(ip, port);
+        util::adjust_stream(&stream, Some(Duration::from_secs(2))).unwrap();
+        let response = transmit_length_prefix(&mut stream, DEFAULT_MAX_FRAME_SIZE + 1);
+        assert!(matches!(response, Ok(response) if is_frame_size_error(&response)));
+        assert!(util::wait_for_transmission(&mut stream).is_err());
+
+        // the server is still available
+        let connection = connect(ip, port);
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+// deterministic pseudo random bytes for the fuzz tests (xorshift)
+struct Fuzzer(u64);
+
+impl Fuzzer {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, max_len: u64) -> Vec<u8> {
+        let len = self.next() % (max_len + 1);
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+fn decode_fault(response: &[u8]) -> Option<(u64, transport::Fault)> {
+    match codec::Bincode.decode::<transport::Transmission<()>>(response) {
+        Ok(transport::Transmission {
+            id,
+            r#type: transport::Type::Fault(fault),
+        }) => Some((id, fault)),
+        _ => None,
+    }
+}
+
+#[test]
+fn fuzz_rpc_connection() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let mut stream = rpc_stream(ip, port);
+        let mut fuzzer = Fuzzer(0x2545_F491_4F6C_DD1D);
+
+        for _ in 0..200 {
+            let transmission_id = fuzzer.next();
+            let mut payload = transmission_id.to_be_bytes().to_vec();
+            payload.extend(fuzzer.bytes(64));
+            assert!(util::write_transmission(&mut stream, payload, None).is_ok());
+
+            // every faulty transmission is answered and the connection is kept
+            let response = util::wait_for_transmission(&mut stream).and_then(|payload_size| {
+                util::read_transmission(&mut stream, payload_size, DEFAULT_MAX_FRAME_SIZE, None)
+            });
+            assert!(matches!(
+                response.ok().as_deref().and_then(decode_fault),
+                Some((id, _)) if id == transmission_id
+            ));
+        }
+
+        // payloads without transmission id are not answered
+        for len in 0..8 {
+            assert!(util::write_transmission(&mut stream, fuzzer.bytes(len), None).is_ok());
+        }
+
+        let connection = connect(ip, port);
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+#[test]
+fn fuzz_mgmt_connection() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let mut fuzzer = Fuzzer(0x9E37_79B9_7F4A_7C15);
+
+        for _ in 0..50 {
+            let mut stream = mgmt_stream(ip, port);
+            // random length prefixes are rejected by the maximum frame size
+            let mut frame = fuzzer.next().to_be_bytes().to_vec();
+            frame.extend(fuzzer.bytes(64));
+            assert!(io::Write::write_all(&mut stream, &frame).is_ok());
+        }
+
+        let connection = connect(ip, port);
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+#[test]
+fn legacy_identify() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        // a client of protocol version 0 sends the Identify without formats
+        let identify = transport::Transmission::<mgmt::LegacyRequest> {
+            id: 7,
+            r#type: transport::Type::Request(mgmt::LegacyRequest::Identify {
+                protocol_version: 0,
+            }),
+        };
+        let mut stream = mgmt_stream(ip, port);
+        util::adjust_stream(&stream, Some(Duration::from_secs(2))).unwrap();
+        let identify = codec::Bincode.encode(&identify).unwrap();
+        assert!(util::write_transmission(&mut stream, identify, None).is_ok());
+
+        // and decodes the answer in the layout of its version
+        let response = util::wait_for_transmission(&mut stream).and_then(|length_prefix| {
+            util::read_transmission(&mut stream, length_prefix, DEFAULT_MAX_FRAME_SIZE, None)
+        });
+        assert!(matches!(
+            response.map(|response| codec::Bincode.decode::<transport::Transmission<mgmt::LegacyResponse>>(&response)),
+            Ok(Ok(transport::Transmission {
+                id: 7,
+                r#type: transport::Type::Response(mgmt::LegacyResponse::Identify(identity)),
+            })) if identity.protocol_version == ProtocolVersion::entity().version()
+        ));
+    })
+}
+
+#[test]
+fn close_connection_on_fault() -> io::Result<()> {
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let options = server::ServerOptions {
+        fault_policy: server::FaultPolicy::CloseConnection,
+        ..Default::default()
+    };
+    let server = server::Server::<DummyServer>::with_options(port, service, options);
+    let shutdown_handle = server.shutdown_handle();
+
+    thread::scope(|scope| {
+        let runner = scope.spawn(|| server.run());
+
+        // wait for the server
+        drop(connect(ip, port));
+
+        let mut stream = rpc_stream(ip, port);
+        const TRANSMISSION_ID: u64 = 7;
+        let mut payload = TRANSMISSION_ID.to_be_bytes().to_vec();
+        payload.extend([0xFF; 4]);
+        assert!(util::write_transmission(&mut stream, payload, None).is_ok());
+
+        let response = util::wait_for_transmission(&mut stream).and_then(|payload_size| {
+            util::read_transmission(&mut stream, payload_size, DEFAULT_MAX_FRAME_SIZE, None)
+        });
+        assert!(matches!(
+            response.ok().as_deref().and_then(decode_fault),
+            Some((
+                TRANSMISSION_ID,
+                transport::Fault::UndecodableTransmission(_)
+            ))
+        ));
+        assert!(util::wait_for_transmission(&mut stream).is_err());
+
+        shutdown_handle.shutdown();
+        assert!(matches!(runner.join(), Ok(Ok(()))));
+    });
+
+    Ok(())
+}
+
+fn worker_pool(workers: usize) -> server::ServerOptions {
+    server::ServerOptions {
+        execution: server::Execution::WorkerPool { workers },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn worker_pool_requests() -> io::Result<()> {
+    run_with_options(worker_pool(2), |ip, port| {
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(move || {
+                    let connection = connect(ip, port);
+                    assert!(matches!(
+                        connection.transceive(DummyRequest::Ping),
+                        Ok(DummyResponse::Pong)
+                    ));
+                    assert!(matches!(
+                        connection.transceive(DummyRequest::Fail),
+                        Err(Error::Remote(err)) if err == "failed"
+                    ));
+                    let numbers = connection
+                        .transceive_stream(DummyRequest::Count(3))
+                        .map(|stream| stream.collect::<Result<Vec<_>, _>>());
+                    assert!(
+                        matches!(numbers, Ok(Ok(numbers)) if numbers == (0..3).map(DummyResponse::Number).collect::<Vec<_>>())
+                    );
+                    let sum = connection
+                        .transceive_client_stream((1..=4).map(DummyRequest::Add));
+                    assert!(matches!(sum, Ok(DummyResponse::Number(10))));
+                });
+            }
+        });
+    })
+}
+
+#[test]
+fn worker_pool_pipelined_requests() -> io::Result<()> {
+    run_with_options(worker_pool(2), |ip, port| {
+        let connection = connect(ip, port);
+
+        let slow = connection.send(DummyRequest::Sleep(200)).unwrap();
+        let fast = connection.send(DummyRequest::Ping).unwrap();
+
+        // the fast request is executed by the second worker
+        let start = Instant::now();
+        assert!(matches!(fast.wait(), Ok(DummyResponse::Pong)));
+        assert!(start.elapsed() < Duration::from_millis(150));
+
+        assert!(matches!(slow.wait(), Ok(DummyResponse::Slept)));
+    })
+}
+
+// the client reads the responses only after all requests are sent, so that the responses exceed the
+// send buffer of the socket and the writer has to wait until it's writable again
+#[test]
+fn worker_pool_large_responses() -> io::Result<()> {
+    run_with_options(worker_pool(2), |ip, port| {
+        let mut stream = rpc_stream(ip, port);
+        let blob = (0..1024 * 1024)
+            .map(|n| (n % 251) as u8)
+            .collect::<Vec<_>>();
+        for id in 0..8 {
+            let request = codec::Bincode
+                .encode(&transport::Transmission::<DummyRequest> {
+                    id,
+                    r#type: transport::Type::Request(DummyRequest::Blob(blob.clone())),
+                })
+                .unwrap();
+            util::write_transmission(&mut stream, request, None).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+
+        let mut ids = (0..8)
+            .map(|_| {
+                let payload_size = util::wait_for_transmission(&mut stream).unwrap();
+                let response = util::read_transmission(
+                    &mut stream,
+                    payload_size,
+                    DEFAULT_MAX_FRAME_SIZE,
+                    None,
+                )
+                .unwrap();
+                match codec::Bincode.decode::<transport::Transmission<DummyResponse>>(&response) {
+                    Ok(transport::Transmission {
+                        id,
+                        r#type: transport::Type::Response(DummyResponse::Blob(echo)),
+                    }) if echo == blob => id,
+                    response => panic!("unexpected response: {:?}", response.map(|r| r.id)),
+                }
+            })
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..8).collect::<Vec<_>>());
+    })
+}
+
+#[test]
+fn worker_pool_rpc_impostor() -> io::Result<()> {
+    run_with_options(worker_pool(1), rpc_impostor_refused)
+}
+
+#[test]
+fn worker_pool_connection_lifecycle() -> io::Result<()> {
+    let ip = Ipv4Addr::UNSPECIFIED;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<DummyServer>::with_options(port, service, worker_pool(1));
+    let shutdown_handle = server.shutdown_handle();
+
+    thread::scope(|scope| {
+        let runner = scope.spawn(|| server.run());
+
+        let first = connect(ip, port);
+        let options = client::ConnectionOptions {
+            rpc_interval_timeout: Some(Duration::from_millis(100)),
+            keep_alive: false,
+            ..Default::default()
+        };
+        let second = Connection::with_options((ip, port), -1, options).unwrap();
+        assert!(first.transceive(DummyRequest::Ping).is_ok());
+        assert!(second.transceive(DummyRequest::Ping).is_ok());
+        assert_eq!(server.connections().len(), 2);
+
+        // the server closes the idle connection and the dropped connection
+        drop(first);
+        let mut retries = 100;
+        while !server.connections().is_empty() && retries > 0 {
+            retries -= 1;
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(server.connections().is_empty());
+        assert_eq!(server.message_processing.cleanups.load(Ordering::SeqCst), 2);
+        assert!(second.transceive(DummyRequest::Ping).is_err());
+
+        // the request in flight is finished before the server stops
+        let third = connect(ip, port);
+        let pending = third.send(DummyRequest::Sleep(100)).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        shutdown_handle.shutdown();
+        assert!(matches!(pending.wait(), Ok(DummyResponse::Slept)));
+        assert!(matches!(runner.join(), Ok(Ok(()))));
+        assert_eq!(server.message_processing.cleanups.load(Ordering::SeqCst), 3);
+    });
+
+    Ok(())
+}
+
+#[test]
+fn worker_pool_single_port() -> io::Result<()> {
+    let options = server::ServerOptions {
+        single_port: true,
+        ..worker_pool(1)
+    };
+    run_with_options(options, |ip, port| {
+        let connection = connect(ip, port);
+        assert_eq!(connection.port(), port);
+
+        let mut bidi = connection.open_bidi_stream(DummyRequest::Echo).unwrap();
+        assert!(bidi.send(DummyRequest::Add(3)).is_ok());
+        assert!(matches!(bidi.next(), Some(Ok(DummyResponse::Number(3)))));
+        assert!(bidi.close().is_ok());
+    })
+}
+
+#[test]
+fn worker_pool_malicious_length_prefix() -> io::Result<()> {
+    run_with_options(worker_pool(1), |ip, port| {
+        let mut stream = rpc_stream(ip, port);
+        let response = transmit_length_prefix(&mut stream, u64::MAX);
+        assert!(matches!(response, Ok(response) if is_frame_size_error(&response)));
+    })
+}
+
+#[test]
+fn max_connections() -> io::Result<()> {
+    let options = server::ServerOptions {
+        max_connections: Some(1),
+        ..worker_pool(1)
+    };
+    run_with_options(options, |ip, port| {
+        let connection = connect(ip, port);
+        assert!(matches!(
+            Connection::new((ip, port), -1),
+            Err(Error::ServerBusy(ConnectionLimit::Server(1)))
+        ));
+
+        // the connection can be opened after the other connection is closed
+        drop(connection);
+        let mut retries = 100;
+        let connection = loop {
+            match Connection::new((ip, port), -1) {
+                Err(_) if retries > 0 => {
+                    retries -= 1;
+                    thread::sleep(Duration::from_millis(10));
+                }
+                connection => break connection,
+            }
+        };
+        assert!(matches!(
+            connection.map(|connection| connection.transceive(DummyRequest::Ping)),
+            Ok(Ok(DummyResponse::Pong))
+        ));
+    })
+}
+
+#[test]
+fn max_connections_per_peer() -> io::Result<()> {
+    let options = server::ServerOptions {
+        max_connections: Some(3),
+        max_connections_per_peer: Some(2),
+        ..Default::default()
+    };
+    run_with_options(options, |ip, port| {
+        let first = connect(ip, port);
+        let second = connect(ip, port);
+        assert!(matches!(
+            Connection::new((ip, port), -1),
+            Err(Error::ServerBusy(ConnectionLimit::Peer(2)))
+        ));
+        assert!(first.transceive(DummyRequest::Ping).is_ok());
+        assert!(second.transceive(DummyRequest::Ping).is_ok());
+    })
+}
+
+#[test]
+fn ipv4_and_ipv6() -> io::Result<()> {
+    let options = server::ServerOptions {
+        addresses: vec![Ipv4Addr::UNSPECIFIED.into(), Ipv6Addr::UNSPECIFIED.into()],
+        ..Default::default()
+    };
+    run_with_options(options, |ip, port| {
+        drop(connect(ip, port));
+
+        let connection = Connection::new((Ipv6Addr::LOCALHOST, port), -1).unwrap();
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+        let connection = Connection::new(format!("[::1]:{}", port), -1).unwrap();
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+#[test]
+fn host_name() -> io::Result<()> {
+    // localhost may resolve to ::1 first, which is not bound
+    let options = server::ServerOptions {
+        addresses: vec![Ipv4Addr::LOCALHOST.into()],
+        ..Default::default()
+    };
+    run_with_options(options, |ip, port| {
+        drop(connect(ip, port));
+
+        let connection = Connection::new(("localhost", port), -1).unwrap();
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+        assert!(matches!(
+            Connection::new(("localhost", 0), -1),
+            Err(Error::Io(_))
+        ));
+    })
+}
+
+#[cfg(unix)]
+fn unix_socket_path(port: u16) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("etm-test-{}-{}.sock", std::process::id(), port))
+}
+
+#[cfg(unix)]
+fn run_with_unix_socket<F>(options: server::ServerOptions, client: F) -> io::Result<()>
+where
+    F: FnOnce(&std::path::Path) + Send + 'static,
+{
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+    let path = unix_socket_path(port);
+    let options = server::ServerOptions {
+        addresses: Vec::new(),
+        unix_socket: Some(path.clone()),
+        ..options
+    };
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<DummyServer>::with_options(port, service, options);
+    let shutdown_handle = server.shutdown_handle();
+
+    let th = {
+        let path = path.clone();
+        thread::spawn(move || {
+            client(&path);
+            shutdown_handle.shutdown();
+        })
+    };
+
+    server.run()?;
+    assert!(th.join().is_ok());
+    // the socket file is removed with the listener
+    assert!(!path.exists());
+    Ok(())
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &std::path::Path) -> Box<Connection> {
+    let mut retries = 100;
+    loop {
+        match Connection::unix(path, -1) {
+            Ok(connection) => break connection,
+            Err(_) if retries > 0 => {
+                retries -= 1;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(err) => panic!("could not connect to server: {:?}", err),
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket() -> io::Result<()> {
+    run_with_unix_socket(Default::default(), |path| {
+        let connection = connect_unix(path);
+        assert_eq!(connection.port(), 0);
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+        let numbers = connection
+            .transceive_stream(DummyRequest::Count(3))
+            .map(|stream| stream.count());
+        assert!(matches!(numbers, Ok(3)));
+
+        // the rpc socket of the connection is removed after the client connected
+        let rpc_socket = format!("{}.{}", path.display(), connection.id());
+        assert!(!std::path::Path::new(&rpc_socket).exists());
+    })
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_single_port() -> io::Result<()> {
+    let options = server::ServerOptions {
+        single_port: true,
+        ..Default::default()
+    };
+    run_with_unix_socket(options, |path| {
+        let connection = connect_unix(path);
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_worker_pool() -> io::Result<()> {
+    run_with_unix_socket(worker_pool(2), |path| {
+        let first = connect_unix(path);
+        let second = connect_unix(path);
+        assert!(matches!(
+            first.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+        assert!(matches!(
+            second.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+    })
+}
+
+fn credentials_options(credentials: impl auth::Credentials + 'static) -> client::ConnectionOptions {
+    client::ConnectionOptions {
+        credentials: Some(Arc::new(credentials)),
+        ..Default::default()
+    }
+}
+
+fn connect_with_options(
+    ip: Ipv4Addr,
+    port: u16,
+    options: client::ConnectionOptions,
+) -> Box<Connection> {
+    connect_with_id_and_options(ip, port, -1, options)
+}
+
+fn connect_with_id_and_options(
+    ip: Ipv4Addr,
+    port: u16,
+    connection_id: i32,
+    options: client::ConnectionOptions,
+) -> Box<Connection> {
+    let mut retries = 100;
+    loop {
+        match Connection::with_options((ip, port), connection_id, options.clone()) {
+            Ok(connection) => break connection,
+            Err(_) if retries > 0 => {
+                retries -= 1;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(err) => panic!("could not connect to server: {:?}", err),
+        }
+    }
+}
+
+fn authenticator(
+    authenticator: impl auth::Authenticator + 'static,
+    options: server::ServerOptions,
+) -> server::ServerOptions {
+    server::ServerOptions {
+        authenticator: Some(Arc::new(authenticator)),
+        ..options
+    }
+}
+
+fn assert_principal(connection: &Connection, principal: &str) {
+    assert!(matches!(
+        connection.transceive(DummyRequest::Principal),
+        Ok(DummyResponse::Principal(Some(p))) if p == principal
+    ));
+}
+
+#[test]
+fn token_authentication() -> io::Result<()> {
+    let tokens = auth::TokenAuthenticator::new()
+        .with_token("alice's token", "alice")
+        .with_token("bob's token", "bob");
+    run_with_options(authenticator(tokens, Default::default()), |ip, port| {
+        let alice = connect_with_options(
+            ip,
+            port,
+            credentials_options(auth::Token::new("alice's token")),
+        );
+        assert_principal(&alice, "alice");
+        let bob = connect_with_options(
+            ip,
+            port,
+            credentials_options(auth::Token::new("bob's token")),
+        );
+        assert_principal(&bob, "bob");
+        assert!(matches!(
+            bob.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+
+        let mallory = Connection::with_options(
+            (ip, port),
+            -1,
+            credentials_options(auth::Token::new("guessed")),
+        );
+        assert!(matches!(mallory, Err(Error::AuthenticationFailed)));
+        // the authentication is required for Connect, not for Identify
+        assert!(matches!(
+            Connection::new((ip, port), -1),
+            Err(Error::AuthenticationFailed)
+        ));
+    })
+}
+
+// answers the challenge with the challenge xored with the shared key
+#[derive(Debug)]
+struct XorKey(u8);
+
+impl auth::Credentials for XorKey {
+    fn respond(&self, challenge: &[u8]) -> Vec<u8> {
+        challenge.iter().map(|byte| byte ^ self.0).collect()
+    }
+}
+
+// issues a new challenge per authentication
+#[derive(Debug, Default)]
+struct XorAuthenticator {
+    challenges: AtomicU32,
+}
+
+impl auth::Authenticator for XorAuthenticator {
+    fn challenge(&self, _peer: &server::Peer) -> Vec<u8> {
+        self.challenges
+            .fetch_add(1, Ordering::Relaxed)
+            .to_be_bytes()
+            .to_vec()
+    }
+
+    fn authenticate(
+        &self,
+        peer: &server::Peer,
+        challenge: &[u8],
+        credentials: &[u8],
+    ) -> Option<String> {
+        (auth::Credentials::respond(&XorKey(0x5a), challenge) == credentials)
+            .then(|| format!("client at {}", peer.address()))
+    }
+}
+
+#[test]
+fn challenge_response_authentication() -> io::Result<()> {
+    for options in [
+        Default::default(),
+        server::ServerOptions {
+            single_port: true,
+            ..Default::default()
+        },
+        worker_pool(2),
+    ] {
+        run_with_options(
+            authenticator(XorAuthenticator::default(), options),
+            |ip, port| {
+                let connection = connect_with_options(ip, port, credentials_options(XorKey(0x5a)));
+                assert!(matches!(
+                    connection.transceive(DummyRequest::Principal),
+                    Ok(DummyResponse::Principal(Some(principal))) if principal.starts_with("client at ")
+                ));
+                let wrong_key =
+                    Connection::with_options((ip, port), -1, credentials_options(XorKey(0)));
+                assert!(matches!(wrong_key, Err(Error::AuthenticationFailed)));
+            },
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn authenticated_peer() -> io::Result<()> {
+    let tokens = auth::TokenAuthenticator::new().with_token("token", "alice");
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<DummyServer>::with_options(
+        port,
+        service,
+        authenticator(tokens, Default::default()),
+    );
+    let shutdown_handle = server.shutdown_handle();
+    let th = thread::spawn(move || {
+        let connection = connect_with_options(
+            Ipv4Addr::LOCALHOST,
+            port,
+            credentials_options(auth::Token::new("token")),
+        );
+        assert_principal(&connection, "alice");
+        shutdown_handle.shutdown();
+    });
+    server.run()?;
+    assert!(th.join().is_ok());
+
+    let peers = server
+        .message_processing
+        .peers
+        .lock()
+        .expect("getting lock");
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].principal(), Some("alice"));
+    Ok(())
+}
+
+#[test]
+fn credentials_without_authenticator() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        let connection =
+            connect_with_options(ip, port, credentials_options(auth::Token::new("token")));
+        assert!(matches!(
+            connection.transceive(DummyRequest::Principal),
+            Ok(DummyResponse::Principal(None))
+        ));
+    })
+}
+
+fn codec_requests(connection: &Connection) {
+    assert!(matches!(
+        connection.transceive(DummyRequest::Ping),
+        Ok(DummyResponse::Pong)
+    ));
+    assert!(matches!(
+        connection.transceive(DummyRequest::Fail),
+        Err(Error::Remote(err)) if err == "failed"
+    ));
+    let numbers = connection
+        .transceive_stream(DummyRequest::Count(3))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>();
+    assert!(
+        matches!(numbers, Ok(numbers) if numbers == (0..3).map(DummyResponse::Number).collect::<Vec<_>>())
+    );
+    assert!(matches!(
+        connection.transceive_client_stream((1..=4).map(DummyRequest::Add)),
+        Ok(DummyResponse::Number(10))
+    ));
+    let mut stream = connection.open_bidi_stream(DummyRequest::Echo).unwrap();
+    assert!(stream.send(DummyRequest::Add(7)).is_ok());
+    assert!(matches!(stream.next(), Some(Ok(DummyResponse::Number(7)))));
+    assert!(stream.close().is_ok());
+    assert!(stream.next().is_none());
+}
+
+#[test]
+fn codecs() -> io::Result<()> {
+    for format in codec::Format::supported() {
+        for options in [
+            Default::default(),
+            server::ServerOptions {
+                single_port: true,
+                ..Default::default()
+            },
+            worker_pool(2),
+        ] {
+            let options = server::ServerOptions {
+                formats: vec![format],
+                ..options
+            };
+            run_with_options(options, move |ip, port| {
+                let connection = connect(ip, port);
+                assert_eq!(connection.format(), format);
+                codec_requests(&connection);
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn codec_negotiation() -> io::Result<()> {
+    let options = server::ServerOptions {
+        formats: vec![
+            codec::Format::MessagePack,
+            codec::Format::Json,
+            codec::Format::Bincode,
+        ],
+        ..Default::default()
+    };
+    run_with_options(options, |ip, port| {
+        // the server prefers the first of its formats which is enabled and offered by the client
+        let connection = connect(ip, port);
+        let preferred = [codec::Format::MessagePack, codec::Format::Json]
+            .iter()
+            .copied()
+            .find(|format| format.is_supported())
+            .unwrap_or(codec::Format::Bincode);
+        assert_eq!(connection.format(), preferred);
+
+        let options = client::ConnectionOptions {
+            formats: vec![codec::Format::Cbor, codec::Format::Bincode],
+            ..Default::default()
+        };
+        let connection = connect_with_options(ip, port, options);
+        assert_eq!(connection.format(), codec::Format::Bincode);
+        codec_requests(&connection);
+
+        let options = client::ConnectionOptions {
+            formats: vec![codec::Format::Cbor],
+            ..Default::default()
+        };
+        assert!(matches!(
+            Connection::with_options((ip, port), -1, options),
+            Err(Error::Protocol(_))
+        ));
+    })
+}
+
+#[test]
+fn unsupported_codec_on_connect() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        // a client which skipped the negotiation
+        let addr = Address::Tcp(SocketAddr::from((ip, port)));
+        let connect = || {
+            mgmt::Request::Connect(mgmt::CommParams {
+                protocol_version: ProtocolVersion::entity().version(),
+                connection_id: mgmt::DYNAMIC_CONNECTION_ID,
+                rpc_interval_timeout_ms: mgmt::INFINITE_RPC_INTERVAL_TIMEOUT,
+                format: codec::Format::Json,
+                compression: Vec::new(),
+            })
+        };
+        let mut retries = 100;
+        let response = loop {
+            match Connection::mgmt_transceive(&addr, connect(), &Default::default()) {
+                Err(Error::Io(_)) if retries > 0 => {
+                    retries -= 1;
+                    thread::sleep(Duration::from_millis(10));
+                }
+                response => break response,
+            }
+        };
+        assert!(matches!(
+            response,
+            Ok(mgmt::Response::UnsupportedFormat(codec::Format::Json))
+        ));
+    })
+}
+
+#[test]
+fn incompatible_protocol_version_on_connect() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        // a client which ignored the protocol version of the Identify response
+        let addr = Address::Tcp(SocketAddr::from((ip, port)));
+        let connect = || {
+            mgmt::Request::Connect(mgmt::CommParams {
+                protocol_version: ProtocolVersion::entity().version() + 1,
+                connection_id: mgmt::DYNAMIC_CONNECTION_ID,
+                rpc_interval_timeout_ms: mgmt::INFINITE_RPC_INTERVAL_TIMEOUT,
+                format: codec::Format::Bincode,
+                compression: Vec::new(),
+            })
+        };
+        let mut retries = 100;
+        let response = loop {
+            match Connection::mgmt_transceive(&addr, connect(), &Default::default()) {
+                Err(Error::Io(_)) if retries > 0 => {
+                    retries -= 1;
+                    thread::sleep(Duration::from_millis(10));
+                }
+                response => break response,
+            }
+        };
+        assert!(matches!(response, Err(Error::Remote(_))));
+    })
+}
+
+#[test]
+fn incompatible_protocol_version_on_identify() -> io::Result<()> {
+    let ip = Ipv4Addr::LOCALHOST;
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+    let listener = util::bind(ip, port)?;
+
+    // a server of a newer protocol version
+    let server = thread::spawn(move || -> io::Result<()> {
+        let mut stream = Stream::Tcp(listener.accept()?.0);
+        let length_prefix = util::wait_for_transmission(&mut stream)?;
+        let identify =
+            util::read_transmission(&mut stream, length_prefix, DEFAULT_MAX_FRAME_SIZE, None)?;
+        let identify = codec::Bincode
+            .decode::<transport::Transmission<mgmt::Request>>(&identify)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let identity = transport::Transmission::<mgmt::Response> {
+            id: identify.id,
+            r#type: transport::Type::Response(mgmt::Response::Identify(mgmt::Identity {
+                protocol_version: ProtocolVersion::entity().version() + 1,
+                service: Service::entity("TestService".to_string(), 1),
+                format: Some(codec::Format::Bincode),
+            })),
+        };
+        let identity = codec::Bincode
+            .encode(&identity)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        util::write_transmission(&mut stream, identity, None).map(|_| ())
+    });
+
+    // the client doesn't continue with Connect
+    assert!(matches!(
+        Connection::new((ip, port), -1),
+        Err(Error::Protocol(_))
+    ));
+    assert!(matches!(server.join(), Ok(Ok(()))));
+    Ok(())
+}
+
+// the responses echo a compressible blob in both directions
+fn blob_requests(connection: &Connection) {
+    for len in [0, 100, 64 * 1024] {
+        let blob = (0..len).map(|n| (n % 7) as u8).collect::<Vec<_>>();
+        assert!(matches!(
+            connection.transceive(DummyRequest::Blob(blob.clone())),
+            Ok(DummyResponse::Blob(echo)) if echo == blob
+        ));
+    }
+    codec_requests(connection);
+}
+
+#[test]
+fn compression() -> io::Result<()> {
+    for algorithm in compression::Algorithm::supported() {
+        for options in [
+            Default::default(),
+            server::ServerOptions {
+                single_port: true,
+                ..Default::default()
+            },
+            worker_pool(2),
+        ] {
+            let options = server::ServerOptions {
+                compression: vec![algorithm],
+                compression_threshold: 0,
+                ..options
+            };
+            run_with_options(options, move |ip, port| {
+                let connection = connect(ip, port);
+                assert_eq!(
+                    connection.compression(),
+                    Some(compression::Compression {
+                        algorithm,
+                        threshold: 0
+                    })
+                );
+                blob_requests(&connection);
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn compression_negotiation() -> io::Result<()> {
+    // the server doesn't compress unless configured
+    run_with_client(|ip, port| {
+        let connection = connect(ip, port);
+        assert_eq!(connection.compression(), None);
+        blob_requests(&connection);
+    })?;
+
+    let options = server::ServerOptions {
+        compression: vec![compression::Algorithm::Zstd, compression::Algorithm::Lz4],
+        ..Default::default()
+    };
+    run_with_options(options, |ip, port| {
+        let connection = connect(ip, port);
+        assert_eq!(
+            connection
+                .compression()
+                .map(|compression| compression.algorithm),
+            compression::Algorithm::supported().first().copied()
+        );
+        assert!(connection.compression().is_none_or(
+            |compression| compression.threshold == compression::DEFAULT_COMPRESSION_THRESHOLD
+        ));
+
+        // a client which offers no compression
+        let options = client::ConnectionOptions {
+            compression: Vec::new(),
+            ..Default::default()
+        };
+        let connection = connect_with_options(ip, port, options);
+        assert_eq!(connection.compression(), None);
+        blob_requests(&connection);
+    })
+}
+
+#[test]
+fn compressed_transmissions() -> io::Result<()> {
+    use std::io::Read;
+
+    let algorithm = match compression::Algorithm::supported().first() {
+        Some(algorithm) => *algorithm,
+        None => return Ok(()),
+    };
+    let options = server::ServerOptions {
+        compression: vec![algorithm],
+        ..Default::default()
+    };
+    run_with_options(options, move |ip, port| {
+        let (mut stream, compression) = compressed_rpc_stream(ip, port, vec![algorithm]);
+        assert!(compression.is_some());
+
+        const TRANSMISSION_ID: u64 = 3;
+        let blob = vec![0x2A; 64 * 1024];
+        let request = codec::Bincode
+            .encode(&transport::Transmission::<DummyRequest> {
+                id: TRANSMISSION_ID,
+                r#type: transport::Type::Request(DummyRequest::Blob(blob.clone())),
+            })
+            .unwrap();
+        let sent = util::write_transmission(&mut stream, request, compression).unwrap();
+        assert!(sent < blob.len());
+
+        // the response is flagged as compressed in the length prefix
+        let length_prefix = util::wait_for_transmission(&mut stream).unwrap();
+        let mut payload = vec![0u8; (length_prefix & !compression::COMPRESSED) as usize];
+        assert!(length_prefix & compression::COMPRESSED != 0);
+        assert!(payload.len() < blob.len());
+        stream.read_exact(&mut payload).unwrap();
+        let response =
+            compression::decompress(compression.unwrap(), &payload, DEFAULT_MAX_FRAME_SIZE)
+                .and_then(|response| {
+                    codec::Bincode
+                        .decode::<transport::Transmission<DummyResponse>>(&response)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+                });
+        assert!(matches!(
+            response,
+            Ok(transport::Transmission {
+                id: TRANSMISSION_ID,
+                r#type: transport::Type::Response(DummyResponse::Blob(echo)),
+            }) if echo == blob
+        ));
+    })
+}
+
+#[test]
+fn compressed_transmission_without_compression() -> io::Result<()> {
+    run_with_client(|ip, port| {
+        // like an old peer, a connection without compression rejects a compressed transmission
+        let mut stream = rpc_stream(ip, port);
+        let response = transmit_length_prefix(&mut stream, 8 | compression::COMPRESSED);
+        assert!(matches!(response, Ok(response) if is_frame_size_error(&response)));
+        assert!(util::wait_for_transmission(&mut stream).is_err());
+    })
+}
+
+// a certificate authority which issues the certificates of the server and the clients
+#[cfg(feature = "tls")]
+pub(crate) struct TestCa {
+    certificate: rcgen::Certificate,
+    key: rcgen::KeyPair,
+}
+
+#[cfg(feature = "tls")]
+impl TestCa {
+    pub(crate) fn new() -> Self {
+        let mut params = rcgen::CertificateParams::new(Vec::new()).expect("ca parameters");
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "etm test ca");
+        let key = rcgen::KeyPair::generate().expect("generating key");
+        let certificate = params.self_signed(&key).expect("signing ca certificate");
+        TestCa { certificate, key }
+    }
+
+    fn issue(
+        &self,
+        name: &str,
+    ) -> (
+        Vec<rustls::pki_types::CertificateDer<'static>>,
+        rustls::pki_types::PrivateKeyDer<'static>,
+    ) {
+        let params =
+            rcgen::CertificateParams::new(vec![name.to_string()]).expect("certificate parameters");
+        let key = rcgen::KeyPair::generate().expect("generating key");
+        let certificate = params
+            .signed_by(&key, &self.certificate, &self.key)
+            .expect("signing certificate");
+        (
+            vec![certificate.der().clone()],
+            rustls::pki_types::PrivatePkcs8KeyDer::from(key.serialize_der()).into(),
+        )
+    }
+
+    fn roots(&self) -> Arc<rustls::RootCertStore> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(self.certificate.der().clone())
+            .expect("adding root");
+        Arc::new(roots)
+    }
+
+    // verify_clients requires a client certificate issued by this ca
+    pub(crate) fn server_config(&self, verify_clients: bool) -> Arc<rustls::ServerConfig> {
+        let builder = rustls::ServerConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()
+            .expect("protocol versions");
+        let builder = if verify_clients {
+            builder.with_client_cert_verifier(
+                rustls::server::WebPkiClientVerifier::builder_with_provider(
+                    self.roots(),
+                    crypto_provider(),
+                )
+                .build()
+                .expect("client verifier"),
+            )
+        } else {
+            builder.with_no_client_auth()
+        };
+        let (chain, key) = self.issue("localhost");
+        Arc::new(
+            builder
+                .with_single_cert(chain, key)
+                .expect("server certificate"),
+        )
+    }
+
+    pub(crate) fn client_options(&self, certificate: bool) -> client::TlsOptions {
+        let builder = rustls::ClientConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()
+            .expect("protocol versions")
+            .with_root_certificates(self.roots());
+        let config = if certificate {
+            let (chain, key) = self.issue("client.etm");
+            builder
+                .with_client_auth_cert(chain, key)
+                .expect("client certificate")
+        } else {
+            builder.with_no_client_auth()
+        };
+        client::TlsOptions {
+            config: Arc::new(config),
+            server_name: rustls::pki_types::ServerName::try_from("localhost").expect("server name"),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+fn crypto_provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+#[cfg(feature = "tls")]
+pub(crate) fn tls_options(tls: client::TlsOptions) -> client::ConnectionOptions {
+    client::ConnectionOptions {
+        tls: Some(tls),
+        ..Default::default()
+    }
+}
+
+// returns the message processing of the server to inspect the peers
+#[cfg(feature = "tls")]
+fn run_with_tls<F>(
+    config: Arc<rustls::ServerConfig>,
+    options: server::ServerOptions,
+    client: F,
+) -> io::Result<Arc<DummyServer>>
+where
+    F: FnOnce(u16) + Send + 'static,
+{
+    let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+    let options = server::ServerOptions {
+        tls: Some(config),
+        ..options
+    };
+
+    let service = Service::entity("TestService".to_string(), 1);
+    let server = server::Server::<DummyServer>::with_options(port, service, options);
+    let shutdown_handle = server.shutdown_handle();
+
+    let th = thread::spawn(move || {
+        client(port);
+        shutdown_handle.shutdown();
+    });
+
+    server.run()?;
+    assert!(th.join().is_ok());
+    Ok(server.message_processing.clone())
+}
+
+#[cfg(feature = "tls")]
+fn connect_tls(port: u16, tls: client::TlsOptions) -> Box<Connection> {
+    let mut retries = 100;
+    loop {
+        match Connection::with_options((Ipv4Addr::LOCALHOST, port), -1, tls_options(tls.clone())) {
+            Ok(connection) => break connection,
+            Err(_) if retries > 0 => {
+                retries -= 1;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(err) => panic!("could not connect to server: {:?}", err),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+fn tls_requests(connection: &Connection) {
+    assert!(matches!(
+        connection.transceive(DummyRequest::Ping),
+        Ok(DummyResponse::Pong)
+    ));
+    let pending = (0..10)
+        .map(|_| connection.send(DummyRequest::Sleep(10)))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("sending requests");
+    for pending in pending {
+        assert!(matches!(pending.wait(), Ok(DummyResponse::Slept)));
+    }
+    assert!(matches!(
+        connection.transceive(DummyRequest::Fail),
+        Err(Error::Remote(err)) if err == "failed"
+    ));
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls() -> io::Result<()> {
+    let ca = TestCa::new();
+    let tls = ca.client_options(false);
+    let server = run_with_tls(ca.server_config(false), Default::default(), move |port| {
+        let connection = connect_tls(port, tls);
+        assert_ne!(connection.port(), port);
+        tls_requests(&connection);
+    })?;
+    let peers = server.peers.lock().expect("getting lock");
+    assert_eq!(peers.len(), 1);
+    assert!(peers[0].certificates().is_empty());
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls_single_port() -> io::Result<()> {
+    let ca = TestCa::new();
+    let tls = ca.client_options(false);
+    let options = server::ServerOptions {
+        single_port: true,
+        ..Default::default()
+    };
+    run_with_tls(ca.server_config(false), options, move |port| {
+        let connection = connect_tls(port, tls);
+        assert_eq!(connection.port(), port);
+        tls_requests(&connection);
+    })?;
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls_worker_pool() -> io::Result<()> {
+    let ca = TestCa::new();
+    let tls = ca.client_options(false);
+    run_with_tls(ca.server_config(false), worker_pool(2), move |port| {
+        let connections = (0..3)
+            .map(|_| connect_tls(port, tls.clone()))
+            .collect::<Vec<_>>();
+        for connection in &connections {
+            tls_requests(connection);
+        }
+    })?;
+    Ok(())
+}
+
+// negotiates a connection on the management port, the rpc port is left to the test
+#[cfg(feature = "tls")]
+fn tls_rpc_port(port: u16, tls: client::TlsOptions) -> (u16, mgmt::RpcToken) {
+    let addr = Address::Tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+    let connect = mgmt::Request::Connect(mgmt::CommParams {
+        protocol_version: ProtocolVersion::entity().version(),
+        connection_id: mgmt::DYNAMIC_CONNECTION_ID,
+        rpc_interval_timeout_ms: mgmt::INFINITE_RPC_INTERVAL_TIMEOUT,
+        format: codec::Format::Bincode,
+        compression: Vec::new(),
+    });
+    match Connection::mgmt_transceive(&addr, connect, &tls_options(tls)) {
+        Ok(mgmt::Response::Connect(mgmt::CommSettings {
+            endpoint: mgmt::RpcEndpoint::Port(rpc_port),
+            rpc_token,
+            ..
+        })) => (rpc_port, rpc_token),
+        response => panic!("unexpected response to Connect: {:?}", response),
+    }
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls_handshake_in_worker_pool() -> io::Result<()> {
+    let ca = TestCa::new();
+    let tls = ca.client_options(false);
+    run_with_tls(ca.server_config(false), worker_pool(1), move |port| {
+        let connection = connect_tls(port, tls.clone());
+
+        // a client which connects to its rpc port but never starts the tls handshake
+        let (rpc_port, _) = tls_rpc_port(port, tls);
+        let _stalled = Address::Tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, rpc_port)))
+            .connect(Duration::from_secs(2))
+            .expect("connecting to the rpc port");
+        thread::sleep(Duration::from_millis(100));
+
+        // the other connections are served during the handshake
+        let start = Instant::now();
+        assert!(matches!(
+            connection.transceive(DummyRequest::Ping),
+            Ok(DummyResponse::Pong)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    })?;
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls_request_with_handshake_in_worker_pool() -> io::Result<()> {
+    let ca = TestCa::new();
+    let tls = ca.client_options(false);
+    run_with_tls(ca.server_config(false), worker_pool(1), move |port| {
+        // waits until the server is running
+        let _connection = connect_tls(port, tls.clone());
+        let (rpc_port, rpc_token) = tls_rpc_port(port, tls.clone());
+        let mut socket = std::net::TcpStream::connect((Ipv4Addr::LOCALHOST, rpc_port)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut client = rustls::ClientConnection::new(tls.config, tls.server_name).unwrap();
+        while client.is_handshaking() {
+            if client.wants_write() {
+                client.write_tls(&mut socket).unwrap();
+            } else {
+                client.read_tls(&mut socket).unwrap();
+                client.process_new_packets().unwrap();
+            }
+        }
+
+        // the rpc token and the request are sent together with the last records of the handshake
+        client.writer().write_all(&rpc_token.0).unwrap();
+        let request = codec::Bincode
+            .encode(&transport::Transmission::<DummyRequest> {
+                id: 1,
+                r#type: transport::Type::Request(DummyRequest::Ping),
+            })
+            .unwrap();
+        util::write_transmission(&mut client.writer(), request, None).unwrap();
+        while client.wants_write() {
+            client.write_tls(&mut socket).unwrap();
+        }
+
+        let mut stream = rustls::Stream::new(&mut client, &mut socket);
+        let payload_size = util::wait_for_transmission(&mut stream).unwrap();
+        let response =
+            util::read_transmission(&mut stream, payload_size, DEFAULT_MAX_FRAME_SIZE, None)
+                .unwrap();
+        assert!(matches!(
+            codec::Bincode.decode::<transport::Transmission<DummyResponse>>(&response),
+            Ok(transport::Transmission {
+                id: 1,
+                r#type: transport::Type::Response(DummyResponse::Pong),
+            })
+        ));
+    })?;
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls_worker_pool_large_responses() -> io::Result<()> {
+    let ca = TestCa::new();
+    let tls = ca.client_options(false);
+    run_with_tls(ca.server_config(false), worker_pool(1), move |port| {
+        let connection = connect_tls(port, tls);
+        // the responses exceed the send buffer of the socket
+        let blob = (0..4 * 1024 * 1024)
+            .map(|n| (n % 251) as u8)
+            .collect::<Vec<_>>();
+        for _ in 0..2 {
+            match connection.transceive(DummyRequest::Blob(blob.clone())) {
+                Ok(DummyResponse::Blob(echo)) => assert!(echo == blob),
+                response => panic!("unexpected response: {:?}", response.map(|_| ())),
+            }
+        }
+    })?;
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn mutual_tls() -> io::Result<()> {
+    let ca = TestCa::new();
+    let tls = ca.client_options(true);
+    let server = run_with_tls(ca.server_config(true), Default::default(), move |port| {
+        let connection = connect_tls(port, tls);
+        tls_requests(&connection);
+    })?;
+    let peers = server.peers.lock().expect("getting lock");
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].certificates().len(), 1);
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls_rejected() -> io::Result<()> {
+    let ca = TestCa::new();
+    let tls = ca.client_options(true);
+    let without_certificate = ca.client_options(false);
+    let other_ca = TestCa::new().client_options(true);
+    let server = run_with_tls(ca.server_config(true), Default::default(), move |port| {
+        // the server is running once a trusted client is connected
+        let connection = connect_tls(port, tls);
 
+        let address = (Ipv4Addr::LOCALHOST, port);
+        assert!(Connection::new(address, -1).is_err());
+        assert!(Connection::with_options(address, -1, tls_options(without_certificate)).is_err());
+        assert!(Connection::with_options(address, -1, tls_options(other_ca)).is_err());
+        tls_requests(&connection);
+    })?;
+    assert_eq!(server.peers.lock().expect("getting lock").len(), 1);
     Ok(())
 }
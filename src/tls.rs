@@ -0,0 +1,172 @@
+// tls on top of a stream::Stream; the rustls connection is shared by the clones of a stream,
+// so that one thread can wait for data while another one writes
+use crate::stream::Stream;
+
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, Connection, ServerConfig, ServerConnection};
+
+use std::io;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub struct TlsStream {
+    socket: Box<Stream>,
+    connection: Arc<Mutex<Connection>>,
+}
+
+// the handshake is finished before the stream is returned
+pub fn accept(socket: Stream, config: Arc<ServerConfig>) -> io::Result<Stream> {
+    let connection = ServerConnection::new(config).map_err(io::Error::other)?;
+    handshake(socket, connection.into())
+}
+
+pub fn connect(
+    socket: Stream,
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+) -> io::Result<Stream> {
+    let connection = ClientConnection::new(config, server_name).map_err(io::Error::other)?;
+    handshake(socket, connection.into())
+}
+
+fn handshake(mut socket: Stream, mut connection: Connection) -> io::Result<Stream> {
+    while connection.is_handshaking() {
+        connection.complete_io(&mut socket).map_err(|err| {
+            log::error!("tls handshake: {:?}", err);
+            err
+        })?;
+    }
+    Ok(established(socket, connection))
+}
+
+// the server side of a handshake which is driven by advance_handshake
+pub(crate) fn server_connection(config: Arc<ServerConfig>) -> io::Result<Connection> {
+    Ok(ServerConnection::new(config)
+        .map_err(io::Error::other)?
+        .into())
+}
+
+// continues the handshake on a nonblocking socket; returns true when it is finished
+pub(crate) fn advance_handshake(
+    connection: &mut Connection,
+    mut socket: &Stream,
+) -> io::Result<bool> {
+    // the records of the handshake are written completely, e.g. the session tickets of the server
+    while connection.is_handshaking() || connection.wants_write() {
+        match connection.complete_io(&mut socket) {
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
+
+pub(crate) fn established(socket: Stream, connection: Connection) -> Stream {
+    Stream::Tls(TlsStream {
+        socket: Box::new(socket),
+        connection: Arc::new(Mutex::new(connection)),
+    })
+}
+
+impl TlsStream {
+    pub fn socket(&self) -> &Stream {
+        &self.socket
+    }
+
+    pub fn try_clone(&self) -> io::Result<TlsStream> {
+        Ok(TlsStream {
+            socket: Box::new(self.socket.try_clone()?),
+            connection: self.connection.clone(),
+        })
+    }
+
+    // the certificate chain of the peer, starting with its own certificate; empty if the peer didn't send one
+    pub fn peer_certificates(&self) -> Vec<CertificateDer<'static>> {
+        self.connection
+            .lock()
+            .expect("getting lock")
+            .peer_certificates()
+            .map(<[_]>::to_vec)
+            .unwrap_or_default()
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        if how != Shutdown::Read {
+            let mut connection = self.connection.lock().expect("getting lock");
+            connection.send_close_notify();
+            let _ = flush(&mut connection, &self.socket);
+        }
+        self.socket.shutdown(how)
+    }
+}
+
+// writes the pending tls records; on a nonblocking socket the records which would block stay pending for the next flush
+fn flush(connection: &mut Connection, mut socket: &Stream) -> io::Result<()> {
+    while connection.wants_write() {
+        connection.write_tls(&mut socket)?;
+    }
+    Ok(())
+}
+
+impl Read for &TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self
+                .connection
+                .lock()
+                .expect("getting lock")
+                .reader()
+                .read(buf)
+            {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                result => return result,
+            }
+
+            // the socket is read without the lock, a nonblocking socket returns WouldBlock here
+            let mut records = [0u8; 16 * 1024];
+            let size = (&*self.socket).read(&mut records)?;
+            if size == 0 {
+                return Ok(0);
+            }
+
+            let mut connection = self.connection.lock().expect("getting lock");
+            let mut records = &records[..size];
+            while !records.is_empty() {
+                connection.read_tls(&mut records)?;
+                let processed = connection.process_new_packets();
+                // e.g. an alert for the error or a key update
+                match flush(&mut connection, &self.socket) {
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                    result => result?,
+                }
+                processed.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            }
+        }
+    }
+}
+
+impl Write for &TlsStream {
+    // the written data is encrypted, therefore it counts as written even if the records would block
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut connection = self.connection.lock().expect("getting lock");
+        loop {
+            let size = connection.writer().write(buf)?;
+            match flush(&mut connection, &self.socket) {
+                // the buffer of the connection was full
+                Ok(()) if size == 0 && !buf.is_empty() => (),
+                Ok(()) => return Ok(size),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock && size > 0 => return Ok(size),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut connection = self.connection.lock().expect("getting lock");
+        connection.writer().flush()?;
+        flush(&mut connection, &self.socket)
+    }
+}
@@ -3,25 +3,75 @@ use serde::{Deserialize, Serialize};
 pub type Error = String;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
-pub enum Type<T> {
-    Error(T),
+pub enum Type<T, E = T> {
+    Error(E),
     End,
     Request(T),
     Response(T),
     Stream(T),
+    StreamRequest(T),
+    KeepAlive,
+    // a request with the time in milliseconds the caller waits for the response; relative, because the clocks
+    // of client and server differ
+    RequestWithDeadline(T, u64),
+    // transmitted by the client with the id of an outstanding request or stream request which it doesn't wait for anymore
+    Cancel,
+    // a request of type T which the server doesn't answer, e.g. an event report
+    Notification(T),
+    // the server rejected the transmission with the same id; separate from Error, because E might decode a fault
+    Fault(Fault),
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
-pub struct Transmission<T> {
+pub struct Transmission<T, E = T> {
     pub id: u64, // maybe tag instead of id?
-    pub r#type: Type<T>,
+    pub r#type: Type<T, E>,
+}
+
+// the reason why the server rejected a transmission; transmitted as payload of a Fault
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum Fault {
+    // the transmission type or the request could not be deserialized
+    UndecodableTransmission(String),
+    // the transmission type is not expected from a client, e.g. a Response
+    UnexpectedType,
+    // the payload exceeds the maximum frame size of the server
+    FrameSizeExceeded {
+        payload_size: u64,
+        max_frame_size: u64,
+    },
+    // the deadline of a RequestWithDeadline passed before the request was executed
+    DeadlineExceeded,
+    // the client cancelled the request before its response was sent
+    Cancelled,
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::UndecodableTransmission(reason) => {
+                write!(f, "undecodable transmission: {}", reason)
+            }
+            Fault::UnexpectedType => write!(f, "unexpected transmission type"),
+            Fault::FrameSizeExceeded {
+                payload_size,
+                max_frame_size,
+            } => write!(
+                f,
+                "frame size {} exceeds the maximum frame size {}",
+                payload_size, max_frame_size
+            ),
+            Fault::DeadlineExceeded => write!(f, "deadline exceeded"),
+            Fault::Cancelled => write!(f, "cancelled"),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use bincode::Options;
+    use crate::codec::{Bincode, Codec};
     use serde::{Deserialize, Serialize};
 
     #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -33,16 +83,14 @@ mod tests {
 
     #[test]
     fn error_transmission() {
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
+        let codec = Bincode;
 
         let transmission = Transmission::<TestType> {
             id: 0x42,
             r#type: Type::Error(TestType::U8(0x13)),
         };
 
-        let transmission = serde.serialize(&transmission);
+        let transmission = codec.encode(&transmission);
         assert!(transmission.is_ok());
 
         // 8 byte transmission id, 4 byte transmission type tag, 4 byte test type tag, 1 byte test type value
@@ -56,18 +104,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn error_transmission_with_distinct_error_type() {
+        let codec = Bincode;
+
+        let transmission = Transmission::<TestType, Error> {
+            id: 0x42,
+            r#type: Type::Error("A".to_string()),
+        };
+
+        let transmission = codec.encode(&transmission);
+        assert!(transmission.is_ok());
+
+        // 8 byte transmission id, 4 byte transmission type tag, 9 byte error value (8 bytes string length, 1 byte string)
+        const EXPECTED: [u8; 21] = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x41,
+        ];
+
+        if let Ok(result) = transmission {
+            assert_eq!(result, EXPECTED);
+            let transmission = codec.decode::<Transmission<TestType, Error>>(&result);
+            assert!(
+                matches!(transmission, Ok(Transmission { id: 0x42, r#type: Type::Error(err) }) if err == "A")
+            );
+        }
+    }
+
     #[test]
     fn end_transmission() {
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
+        let codec = Bincode;
 
         let transmission = Transmission::<TestType> {
             id: 0x42,
             r#type: Type::End,
         };
 
-        let transmission = serde.serialize(&transmission);
+        let transmission = codec.encode(&transmission);
         assert!(transmission.is_ok());
 
         // 8 byte transmission id, 4 byte transmission type tag, 4 byte test type tag
@@ -82,16 +155,14 @@ mod tests {
 
     #[test]
     fn request_transmission() {
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
+        let codec = Bincode;
 
         let transmission = Transmission::<TestType> {
             id: 0x42,
             r#type: Type::Request(TestType::U8(0x13)),
         };
 
-        let transmission = serde.serialize(&transmission);
+        let transmission = codec.encode(&transmission);
         assert!(transmission.is_ok());
 
         // 8 byte transmission id, 4 byte transmission type tag, 4 byte test type tag, 1 byte test type value
@@ -107,16 +178,14 @@ mod tests {
 
     #[test]
     fn response_transmission() {
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
+        let codec = Bincode;
 
         let transmission = Transmission::<TestType> {
             id: 0x42,
             r#type: Type::Response(TestType::U8(0x13)),
         };
 
-        let transmission = serde.serialize(&transmission);
+        let transmission = codec.encode(&transmission);
         assert!(transmission.is_ok());
 
         // 8 byte transmission id, 4 byte transmission type tag, 4 byte test type tag, 1 byte test type value
@@ -132,16 +201,14 @@ mod tests {
 
     #[test]
     fn stream_transmission() {
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
+        let codec = Bincode;
 
         let transmission = Transmission::<TestType> {
             id: 0x42,
             r#type: Type::Stream(TestType::U8(0x13)),
         };
 
-        let transmission = serde.serialize(&transmission);
+        let transmission = codec.encode(&transmission);
         assert!(transmission.is_ok());
 
         // 8 byte transmission id, 4 byte transmission type tag, 4 byte test type tag, 1 byte test type value
@@ -155,18 +222,157 @@ mod tests {
         }
     }
 
+    #[test]
+    fn stream_request_transmission() {
+        let codec = Bincode;
+
+        let transmission = Transmission::<TestType> {
+            id: 0x42,
+            r#type: Type::StreamRequest(TestType::U8(0x13)),
+        };
+
+        let transmission = codec.encode(&transmission);
+        assert!(transmission.is_ok());
+
+        // 8 byte transmission id, 4 byte transmission type tag, 4 byte test type tag, 1 byte test type value
+        const EXPECTED: [u8; 17] = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00,
+            0x00, 0x00, 0x13,
+        ];
+
+        if let Ok(result) = transmission {
+            assert_eq!(result, EXPECTED);
+        }
+    }
+
+    #[test]
+    fn keep_alive_transmission() {
+        let codec = Bincode;
+
+        let transmission = Transmission::<TestType> {
+            id: 0x42,
+            r#type: Type::KeepAlive,
+        };
+
+        let transmission = codec.encode(&transmission);
+        assert!(transmission.is_ok());
+
+        // 8 byte transmission id, 4 byte transmission type tag, no data
+        const EXPECTED: [u8; 12] = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x00, 0x00, 0x00, 0x06,
+        ];
+
+        if let Ok(result) = transmission {
+            assert_eq!(result, EXPECTED);
+        }
+    }
+
+    #[test]
+    fn request_with_deadline_transmission() {
+        let codec = Bincode;
+
+        let transmission = Transmission::<TestType> {
+            id: 0x42,
+            r#type: Type::RequestWithDeadline(TestType::U8(0x13), 0x1F4),
+        };
+
+        let transmission = codec.encode(&transmission);
+        assert!(transmission.is_ok());
+
+        // 8 byte transmission id, 4 byte transmission type tag, 4 byte test type tag, 1 byte test type value,
+        // 8 byte milliseconds
+        const EXPECTED: [u8; 25] = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00,
+            0x00, 0x00, 0x13, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xF4,
+        ];
+
+        if let Ok(result) = transmission {
+            assert_eq!(result, EXPECTED);
+        }
+    }
+
+    #[test]
+    fn cancel_transmission() {
+        let codec = Bincode;
+
+        let transmission = Transmission::<TestType> {
+            id: 0x42,
+            r#type: Type::Cancel,
+        };
+
+        let transmission = codec.encode(&transmission);
+        assert!(transmission.is_ok());
+
+        // 8 byte transmission id of the cancelled request, 4 byte transmission type tag
+        const EXPECTED: [u8; 12] = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x00, 0x00, 0x00, 0x08,
+        ];
+
+        if let Ok(result) = transmission {
+            assert_eq!(result, EXPECTED);
+        }
+    }
+
+    #[test]
+    fn notification_transmission() {
+        let codec = Bincode;
+
+        let transmission = Transmission::<TestType> {
+            id: 0x42,
+            r#type: Type::Notification(TestType::U8(0x13)),
+        };
+
+        let transmission = codec.encode(&transmission);
+        assert!(transmission.is_ok());
+
+        // 8 byte transmission id, 4 byte transmission type tag, 4 byte test type tag, 1 byte test type value
+        const EXPECTED: [u8; 17] = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00,
+            0x00, 0x00, 0x13,
+        ];
+
+        if let Ok(result) = transmission {
+            assert_eq!(result, EXPECTED);
+        }
+    }
+
+    #[test]
+    fn fault_transmission() {
+        let codec = Bincode;
+
+        let transmission = Transmission::<()> {
+            id: 0x42,
+            r#type: Type::Fault(Fault::FrameSizeExceeded {
+                payload_size: 0x13,
+                max_frame_size: 0x10,
+            }),
+        };
+
+        let transmission = codec.encode(&transmission);
+        assert!(transmission.is_ok());
+
+        // 8 byte transmission id, 4 byte transmission type tag, 4 byte fault tag, 8 byte payload size, 8 byte max frame size
+        const EXPECTED: [u8; 32] = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00,
+            0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x13, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x10,
+        ];
+
+        if let Ok(result) = transmission {
+            assert_eq!(result, EXPECTED);
+        }
+    }
+
     #[test]
     fn transmission_type_string() {
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
+        let codec = Bincode;
 
         let transmission = Transmission::<TestType> {
             id: 0x42,
             r#type: Type::Request(TestType::String("A".to_string())),
         };
 
-        let transmission = serde.serialize(&transmission);
+        let transmission = codec.encode(&transmission);
         assert!(transmission.is_ok());
 
         // 8 byte transmission id, 4 byte transmission type tag, 4 byte test type tag, 9 byte test type value (8 bytes string length, 1 byte string)
@@ -182,16 +388,14 @@ mod tests {
 
     #[test]
     fn transmission_type_vec() {
-        let serde = bincode::DefaultOptions::new()
-            .with_big_endian()
-            .with_fixint_encoding();
+        let codec = Bincode;
 
         let transmission = Transmission::<TestType> {
             id: 0x42,
             r#type: Type::Request(TestType::Vec(vec![0x37, 0x73])),
         };
 
-        let transmission = serde.serialize(&transmission);
+        let transmission = codec.encode(&transmission);
         assert!(transmission.is_ok());
 
         // 8 byte transmission id, 4 byte transmission type tag, 4 byte test type tag, 9 byte test type value (8 bytes string length, 1 byte string)
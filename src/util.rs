@@ -1,7 +1,14 @@
+use crate::compression::{self, Compression};
+use crate::stream::Stream;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
 use std::io;
 use std::io::prelude::*;
 use std::io::{Error, ErrorKind};
-use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::sync::{Arc, Condvar, Mutex};
 use std::time::Duration;
 use std::{thread, time};
@@ -19,6 +26,7 @@ struct Interrupter {
 }
 
 impl InterruptableSleep {
+    #[allow(clippy::new_ret_no_self)]
     fn new() -> (Sleeper, Interrupter) {
         let resource = Arc::new(InterruptableSleep {
             predicate: Mutex::new(false),
@@ -37,7 +45,7 @@ impl Sleeper {
     // returns true if sleep was finished, false if interrupted
     fn sleep(self, timeout: Duration) -> bool {
         let mut predicate = self.resource.predicate.lock().expect("getting lock");
-        while *predicate == false {
+        while !*predicate {
             let (pred, result) = self
                 .resource
                 .cond_var
@@ -68,9 +76,11 @@ impl Interrupter {
     }
 }
 
-pub fn bind(ip: Ipv4Addr, port: u16) -> Result<TcpListener, io::Error> {
+// an IPv6 listener only accepts IPv6 connections, therefore it can be combined with an IPv4 listener on the same port
+pub fn bind(ip: impl Into<IpAddr>, port: u16) -> Result<TcpListener, io::Error> {
+    let ip = ip.into();
     let port_in_use_sleep_milliseconds = time::Duration::from_millis(100);
-    match TcpListener::bind((ip, port)) {
+    match bind_listener(SocketAddr::new(ip, port)) {
         Ok(listener) => Ok(listener),
         Err(ref e) if e.raw_os_error() == Some(0x62) => {
             // port already in use
@@ -82,6 +92,23 @@ pub fn bind(ip: Ipv4Addr, port: u16) -> Result<TcpListener, io::Error> {
     }
 }
 
+fn bind_listener(address: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // like TcpListener::bind
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
 pub fn listener_accept_nonblocking(
     listener: TcpListener,
     timeout: Duration,
@@ -118,7 +145,46 @@ pub fn listener_accept_nonblocking(
     }
 }
 
-pub fn adjust_stream(stream: &TcpStream, read_timeout: Option<time::Duration>) -> io::Result<()> {
+// like listener_accept_nonblocking; the peer of a unix domain socket is unnamed, therefore a connection
+// which arrives after the timeout is not accepted
+#[cfg(unix)]
+pub(crate) fn unix_listener_accept_nonblocking(
+    listener: UnixListener,
+    timeout: Duration,
+) -> io::Result<UnixStream> {
+    listener.set_nonblocking(false)?;
+    let path = listener
+        .local_addr()?
+        .as_pathname()
+        .map(|path| path.to_path_buf())
+        .ok_or_else(|| Error::new(ErrorKind::AddrNotAvailable, "unnamed unix socket"))?;
+    let (sleeper, interrupter) = InterruptableSleep::new();
+    // the thread returns true if the sleeper was not interrupted
+    let th = thread::spawn(move || {
+        let timed_out = sleeper.sleep(timeout);
+        if timed_out {
+            let _ = UnixStream::connect(path);
+        }
+        timed_out
+    });
+
+    let (stream, _) = listener.accept()?;
+    interrupter.interrupt();
+
+    match th.join() {
+        Ok(false) => Ok(stream),
+        _ => {
+            let err = Err(Error::new(
+                ErrorKind::TimedOut,
+                "timeout while waiting for connection",
+            ));
+            log::error!("{:?}", err);
+            err
+        }
+    }
+}
+
+pub fn adjust_stream(stream: &Stream, read_timeout: Option<time::Duration>) -> io::Result<()> {
     stream.set_read_timeout(read_timeout).map_err(|err| {
         log::error!("failed to set read timeout on tcp stream: {:?}", err);
         err
@@ -134,7 +200,7 @@ pub fn adjust_stream(stream: &TcpStream, read_timeout: Option<time::Duration>) -
     Ok(())
 }
 
-pub fn wait_for_transmission(stream: &mut TcpStream) -> io::Result<u64> {
+pub fn wait_for_transmission<R: Read>(stream: &mut R) -> io::Result<u64> {
     let mut datalengthbuffer = [0u8; 8];
     stream
         .read_exact(&mut datalengthbuffer[..])
@@ -145,23 +211,90 @@ pub fn wait_for_transmission(stream: &mut TcpStream) -> io::Result<u64> {
     Ok(u64::from_be_bytes(datalengthbuffer))
 }
 
-pub fn read_transmission(stream: &mut TcpStream, payload_size: u64) -> io::Result<Vec<u8>> {
+// upper bound of the payload size of a transmission unless configured otherwise
+pub const DEFAULT_MAX_FRAME_SIZE: u64 = 16 * 1024 * 1024;
+
+// the length prefix of a transmission exceeds the maximum frame size; the payload is not read
+#[derive(Debug)]
+pub struct FrameSizeExceeded {
+    pub payload_size: u64,
+    pub max_frame_size: u64,
+    // the first 8 bytes of an uncompressed payload; they contain the transmission id if the codec encodes it
+    // first, e.g. bincode, see codec::Codec::transmission_id
+    pub head: Vec<u8>,
+}
+
+impl std::fmt::Display for FrameSizeExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "frame size {} exceeds the maximum frame size {}",
+            self.payload_size, self.max_frame_size
+        )
+    }
+}
+
+impl std::error::Error for FrameSizeExceeded {}
+
+pub fn frame_size_exceeded(err: &io::Error) -> Option<&FrameSizeExceeded> {
+    err.get_ref()
+        .and_then(|err| err.downcast_ref::<FrameSizeExceeded>())
+}
+
+// the length prefix is the one returned by wait_for_transmission; a compressed payload is decompressed
+pub fn read_transmission<R: Read>(
+    stream: &mut R,
+    length_prefix: u64,
+    max_frame_size: u64,
+    compression: Option<Compression>,
+) -> io::Result<Vec<u8>> {
+    let (payload_size, compressed) = compression::payload_size(length_prefix, compression);
+    if payload_size > max_frame_size {
+        // the transmission id is only readable from an uncompressed payload
+        let mut head = vec![0u8; 8];
+        if payload_size < head.len() as u64
+            || compressed.is_some()
+            || stream.read_exact(&mut head).is_err()
+        {
+            head.clear();
+        }
+        let err = FrameSizeExceeded {
+            payload_size,
+            max_frame_size,
+            head,
+        };
+        log::error!("waiting for transmission: {}", err);
+        return Err(Error::new(ErrorKind::InvalidData, err));
+    }
+
     let mut databuffer = vec![0u8; payload_size as usize];
     stream.read_exact(&mut databuffer[..]).map_err(|err| {
         log::error!("waiting for transmission: {:?}", err);
         err
     })?;
-    Ok(databuffer)
+    match compressed {
+        Some(compression) => compression::decompress(compression, &databuffer, max_frame_size),
+        None => Ok(databuffer),
+    }
 }
 
-pub fn write_transmission(stream: &mut TcpStream, serialized: Vec<u8>) -> io::Result<usize> {
-    let mut senddata = (serialized.len() as u64).to_be_bytes().to_vec();
-    senddata.extend(serialized);
+// the payload is compressed if the compression is given and the payload reaches its threshold
+pub fn write_transmission<W: Write>(
+    stream: &mut W,
+    serialized: Vec<u8>,
+    compression: Option<Compression>,
+) -> io::Result<usize> {
+    let (length_prefix, payload) = compression::compress(compression, serialized)?;
+    let mut senddata = length_prefix.to_be_bytes().to_vec();
+    senddata.extend(payload);
 
-    stream.write(&senddata).map_err(|err| {
-        log::error!("writing transmission: {:?}", err);
-        err
-    })
+    stream
+        .write_all(&senddata)
+        .map(|_| senddata.len())
+        .map_err(|err| {
+            log::error!("writing transmission: {:?}", err);
+            err
+        })
 }
 
 #[cfg(test)]
@@ -169,12 +302,13 @@ mod tests {
     use super::*;
     use crate::test_common::TEST_PORT_BASE;
 
-    use std::net::SocketAddr;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::thread;
     use std::time::{Duration, SystemTime};
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn interruptable_sleep_finish() {
         let (sleeper, _) = InterruptableSleep::new();
         let start = SystemTime::now();
@@ -189,6 +323,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn interruptable_sleep_interrupted() {
         let (sleeper, interrupter) = InterruptableSleep::new();
         let start = SystemTime::now();
@@ -212,10 +347,19 @@ mod tests {
         assert!(bind(ip, port).is_ok());
         match start.elapsed() {
             Ok(elapsed) => assert!(elapsed < Duration::from_millis(20)),
-            _ => assert!(false),
+            _ => unreachable!(),
         }
     }
 
+    #[test]
+    fn bind_ipv4_and_ipv6_on_the_same_port() {
+        let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+        let v4 = bind(Ipv4Addr::UNSPECIFIED, port);
+        assert!(v4.is_ok());
+        let v6 = bind(Ipv6Addr::UNSPECIFIED, port);
+        assert!(matches!(v6, Ok(listener) if listener.local_addr().unwrap().is_ipv6()));
+    }
+
     #[test]
     fn bind_port_in_use() {
         let ip = Ipv4Addr::UNSPECIFIED;
@@ -232,7 +376,7 @@ mod tests {
         assert!(bind(ip, port).is_ok());
         match start.elapsed() {
             Ok(elapsed) => assert!(elapsed > Duration::from_millis(90)),
-            _ => assert!(false),
+            _ => unreachable!(),
         }
 
         assert!(th.join().is_ok());
@@ -295,10 +439,13 @@ mod tests {
             assert!(tcp_stream.is_ok());
 
             if let Ok(mut tcp_stream) = tcp_stream {
-                assert!(adjust_stream(&tcp_stream, Some(Duration::from_millis(200))).is_ok());
+                let stream = tcp_stream.try_clone().map(Stream::Tcp);
+                assert!(stream
+                    .and_then(|stream| adjust_stream(&stream, Some(Duration::from_millis(200))))
+                    .is_ok());
                 match tcp_stream.nodelay() {
                     Ok(true) => (),
-                    _ => assert!(false),
+                    _ => unreachable!(),
                 }
                 let mut buffer = [0u8; 1];
                 ready.store(true, Ordering::Relaxed);
@@ -331,11 +478,11 @@ mod tests {
 
             assert!(
                 listener_accept_nonblocking(listener, Duration::from_millis(100))
-                    .and_then(|mut reader| adjust_stream(
-                        &mut reader,
-                        Some(Duration::from_millis(100))
+                    .map(Stream::Tcp)
+                    .and_then(
+                        |reader| adjust_stream(&reader, Some(Duration::from_millis(100)))
+                            .map(|_| reader)
                     )
-                    .and_then(|_| Ok(reader)))
                     .and_then(|mut reader| wait_for_transmission(&mut reader))
                     .map(|data_length| {
                         assert_eq!(data_length, DATA_LENGTH);
@@ -370,11 +517,11 @@ mod tests {
 
             assert!(
                 listener_accept_nonblocking(listener, Duration::from_millis(100))
-                    .and_then(|mut reader| adjust_stream(
-                        &mut reader,
-                        Some(Duration::from_millis(100))
+                    .map(Stream::Tcp)
+                    .and_then(
+                        |reader| adjust_stream(&reader, Some(Duration::from_millis(100)))
+                            .map(|_| reader)
                     )
-                    .and_then(|_| Ok(reader)))
                     .and_then(|mut reader| wait_for_transmission(&mut reader))
                     .is_err()
             );
@@ -406,12 +553,14 @@ mod tests {
 
             assert!(
                 listener_accept_nonblocking(listener, Duration::from_millis(100))
-                    .and_then(|mut reader| adjust_stream(
-                        &mut reader,
-                        Some(Duration::from_millis(100))
+                    .map(Stream::Tcp)
+                    .and_then(
+                        |reader| adjust_stream(&reader, Some(Duration::from_millis(100)))
+                            .map(|_| reader)
                     )
-                    .and_then(|_| Ok(reader)))
-                    .and_then(|mut reader| read_transmission(&mut reader, DATA_LENGTH))
+                    .and_then(|mut reader| {
+                        read_transmission(&mut reader, DATA_LENGTH, DEFAULT_MAX_FRAME_SIZE, None)
+                    })
                     .map(|payload| {
                         assert_eq!(payload.len(), DATA_LENGTH as usize);
                         assert_eq!(payload, send_data);
@@ -447,12 +596,14 @@ mod tests {
 
             assert!(
                 listener_accept_nonblocking(listener, Duration::from_millis(100))
-                    .and_then(|mut reader| adjust_stream(
-                        &mut reader,
-                        Some(Duration::from_millis(1000))
+                    .map(Stream::Tcp)
+                    .and_then(
+                        |reader| adjust_stream(&reader, Some(Duration::from_millis(1000)))
+                            .map(|_| reader)
                     )
-                    .and_then(|_| Ok(reader)))
-                    .and_then(|mut reader| { read_transmission(&mut reader, DATA_LENGTH) })
+                    .and_then(|mut reader| {
+                        read_transmission(&mut reader, DATA_LENGTH, DEFAULT_MAX_FRAME_SIZE, None)
+                    })
                     .is_err()
             );
 
@@ -460,6 +611,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn read_transmission_frame_size_exceeded() {
+        let ip = Ipv4Addr::UNSPECIFIED;
+        let port = TEST_PORT_BASE.fetch_add(1, Ordering::Relaxed);
+        let listener = bind(ip, port);
+        assert!(listener.is_ok());
+
+        const DATA_LENGTH: u64 = u64::MAX;
+        const SEND_DATA: u64 = 73;
+        let send_data = u64::to_be_bytes(SEND_DATA);
+
+        if let Ok(listener) = listener {
+            let th = thread::spawn(move || {
+                let addr = SocketAddr::from((ip, port));
+                assert!(
+                    TcpStream::connect_timeout(&addr, Duration::from_millis(100))
+                        .map(|mut writer| writer.write(&send_data))
+                        .is_ok()
+                );
+            });
+
+            let result = listener_accept_nonblocking(listener, Duration::from_millis(100))
+                .map(Stream::Tcp)
+                .and_then(|reader| {
+                    adjust_stream(&reader, Some(Duration::from_millis(100))).map(|_| reader)
+                })
+                .and_then(|mut reader| read_transmission(&mut reader, DATA_LENGTH, 1024, None));
+            assert!(matches!(
+                result.as_ref().map_err(frame_size_exceeded),
+                Err(Some(FrameSizeExceeded {
+                    payload_size: DATA_LENGTH,
+                    max_frame_size: 1024,
+                    head,
+                })) if *head == send_data
+            ));
+
+            assert!(th.join().is_ok());
+        }
+    }
+
     #[test]
     fn write_transmission_success() {
         let ip = Ipv4Addr::UNSPECIFIED;
@@ -480,23 +671,23 @@ mod tests {
                 let addr = SocketAddr::from((ip, port));
                 assert!(
                     TcpStream::connect_timeout(&addr, Duration::from_millis(100))
-                        .and_then(|mut writer| adjust_stream(
-                            &mut writer,
-                            Some(Duration::from_millis(100))
+                        .map(Stream::Tcp)
+                        .and_then(
+                            |writer| adjust_stream(&writer, Some(Duration::from_millis(100)))
+                                .map(|_| writer)
                         )
-                        .and_then(|_| Ok(writer)))
-                        .map(|mut writer| write_transmission(&mut writer, payload.to_vec()))
+                        .map(|mut writer| write_transmission(&mut writer, payload.to_vec(), None))
                         .is_ok()
                 );
             });
 
             assert!(
                 listener_accept_nonblocking(listener, Duration::from_millis(100))
-                    .and_then(|mut reader| adjust_stream(
-                        &mut reader,
-                        Some(Duration::from_millis(100))
+                    .map(Stream::Tcp)
+                    .and_then(
+                        |reader| adjust_stream(&reader, Some(Duration::from_millis(100)))
+                            .map(|_| reader)
                     )
-                    .and_then(|_| Ok(reader)))
                     .and_then(|mut reader| {
                         let mut databuffer = vec![0u8; expected_data.len()];
                         reader